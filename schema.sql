@@ -12,15 +12,16 @@
 CREATE TABLE IF NOT EXISTS settlements (
     id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
     block_number BIGINT NOT NULL,
-    block_timestamp TIMESTAMP NOT NULL,
+    block_timestamp TIMESTAMP,                -- NULL when the block had no timestamp
     tx_hash VARCHAR(66) NOT NULL,
     log_index INTEGER NOT NULL,
 
     -- Payment details
     payer VARCHAR(42) NOT NULL,               -- Who paid (EIP-3009 authorizer)
     recipient VARCHAR(42) NOT NULL,           -- Resource server (payTo)
-    token VARCHAR(42) NOT NULL,               -- Token address (USDC)
-    amount NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Payment amount (atomic units)
+    token VARCHAR(42) NOT NULL,               -- Token address (USDC), or 'native' for ETH
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Payment amount (atomic units; wei for native)
+    amount_decimal VARCHAR(96),               -- amount scaled by the token's decimals, e.g. '1.000000'
 
     -- Settlement classification
     -- eip3009: facilitator called transferWithAuthorization on USDC
@@ -33,10 +34,50 @@ CREATE TABLE IF NOT EXISTS settlements (
     facilitator VARCHAR(42) NOT NULL,         -- tx.from - who submitted and paid gas
     gas_used NUMERIC(20, 0) NOT NULL DEFAULT 0,
     gas_price NUMERIC(30, 0) NOT NULL DEFAULT 0,
+    base_fee_per_gas NUMERIC(30, 0) NOT NULL DEFAULT 0,  -- EIP-1559 block base fee
+    priority_fee NUMERIC(30, 0) NOT NULL DEFAULT 0,      -- gas_price - base fee
+    gas_cost_usd NUMERIC(38, 6),              -- Transaction gas in USD (6 decimals); NULL before a price
 
     -- EIP-3009 authorization nonce (hex-encoded bytes32)
     nonce VARCHAR(66),
 
+    -- Facilitator fee taken in the same tx (amount is net of it when the recipient forwards it)
+    fee_amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    fee_recipient VARCHAR(42),
+
+    -- Stream-wide sort key (block_number * 2^32 + block log index, zero-padded)
+    global_seq VARCHAR(20) NOT NULL,
+
+    -- Block had no timestamp; block_timestamp is NULL, order by global_seq
+    timestamp_missing BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- EIP-3009 validity window from calldata (unix seconds, 0 if unknown)
+    valid_after BIGINT NOT NULL DEFAULT 0,
+    valid_before BIGINT NOT NULL DEFAULT 0,
+
+    -- Proxy contract version for proxy-path settlements ('unknown' if unmapped)
+    protocol_version VARCHAR(32),
+
+    -- Deterministic UUIDv5 of id, stable across reprocessing
+    uuid UUID NOT NULL,
+
+    -- Recipient is infrastructure (map_x402_settlements infra_addresses param)
+    infra BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- Sent through a known multicall aggregator, and which one ('' otherwise)
+    via_multicall BOOLEAN NOT NULL DEFAULT FALSE,
+    multicall_address VARCHAR(42),
+
+    -- Decoded from transferWithAuthorization calldata, with no matching log
+    from_calldata BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- Payer and recipient are the same address (wash / self transfer)
+    is_self_payment BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- Names from the db_out address_labels param (NULL when unlabeled)
+    payer_label VARCHAR(64),
+    recipient_label VARCHAR(64),
+
     created_at TIMESTAMP DEFAULT NOW()
 );
 
@@ -47,6 +88,254 @@ CREATE INDEX IF NOT EXISTS idx_settlements_facilitator ON settlements(facilitato
 CREATE INDEX IF NOT EXISTS idx_settlements_timestamp ON settlements(block_timestamp);
 CREATE INDEX IF NOT EXISTS idx_settlements_type ON settlements(settlement_type);
 CREATE INDEX IF NOT EXISTS idx_settlements_amount ON settlements(amount DESC);
+CREATE INDEX IF NOT EXISTS idx_settlements_global_seq ON settlements(global_seq);
+
+-------------------------------------------------
+-- SETTLEMENTS_{TYPE}: Per-type settlement tables
+-- Used instead of settlements with db_out split_tables_by_type=true;
+-- columns and indexes are copied from settlements
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS settlements_eip3009 (LIKE settlements INCLUDING ALL);
+CREATE TABLE IF NOT EXISTS settlements_eip3009_proxy (LIKE settlements INCLUDING ALL);
+CREATE TABLE IF NOT EXISTS settlements_settled (LIKE settlements INCLUDING ALL);
+CREATE TABLE IF NOT EXISTS settlements_settled_with_permit (LIKE settlements INCLUDING ALL);
+
+-------------------------------------------------
+-- DUPLICATE_AUTHORIZATIONS: Settlements reusing an already-settled
+-- EIP-3009 authorization (payer + nonce), kept out of settlements
+-- Only populated with db_out param dedup_authorizations=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS duplicate_authorizations (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index of the duplicate
+    authorization_id VARCHAR(112) NOT NULL,   -- payer:nonce
+    first_settlement_id VARCHAR(128) NOT NULL,  -- The settlement that was kept
+    block_number BIGINT NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- CANCELED_AUTHORIZATIONS: EIP-3009 authorizations canceled before use
+-- Aborted payments; never settlements
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS canceled_authorizations (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    log_index INTEGER NOT NULL,
+    token VARCHAR(42) NOT NULL,
+    authorizer VARCHAR(42) NOT NULL,          -- Payer who canceled
+    nonce VARCHAR(66) NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_canceled_authorizations_authorizer ON canceled_authorizations(authorizer);
+
+-------------------------------------------------
+-- PROXY_FUNDING: USDC transferred into the x402 proxies
+-- Pre-funding, not payments; excluded from settlement volume
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS proxy_funding (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    log_index INTEGER NOT NULL,
+    from_address VARCHAR(42) NOT NULL,
+    proxy VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- FLAGGED_SETTLEMENTS: Settlements touching a denylisted address
+-- Populated by the compliance_out module (param flagged_addresses)
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS flagged_settlements (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    block_timestamp TIMESTAMP,
+    tx_hash VARCHAR(66) NOT NULL,
+    payer VARCHAR(42) NOT NULL,
+    recipient VARCHAR(42) NOT NULL,
+    token VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    facilitator VARCHAR(42) NOT NULL,
+    matched_side VARCHAR(16) NOT NULL,        -- payer, recipient, both
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- SETTLEMENTS_WIDE: Settlements joined with participant totals
+-- Populated by the wide_out module; totals are as of each settlement
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS settlements_wide (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    block_timestamp TIMESTAMP,
+    tx_hash VARCHAR(66) NOT NULL,
+    payer VARCHAR(42) NOT NULL,
+    recipient VARCHAR(42) NOT NULL,
+    token VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    settlement_type VARCHAR(32) NOT NULL,
+    facilitator VARCHAR(42) NOT NULL,
+    payer_total_spent NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    payer_total_payments BIGINT NOT NULL DEFAULT 0,
+    recipient_total_received NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    recipient_total_payments BIGINT NOT NULL DEFAULT 0,
+    facilitator_total_volume_settled NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    facilitator_total_settlements BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- FIRST_PAYMENTS: Each payer's acquisition settlement
+-- Only populated with db_out param first_payment_only=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS first_payments (
+    payer VARCHAR(42) PRIMARY KEY,
+    settlement_id VARCHAR(128) NOT NULL,      -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    block_timestamp TIMESTAMP NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    recipient VARCHAR(42) NOT NULL,
+    token VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    facilitator VARCHAR(42) NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_first_payments_timestamp ON first_payments(block_timestamp);
+
+-------------------------------------------------
+-- USDC_UPGRADES: USDC proxy implementation changes
+-- Re-verify the event decoders against each new implementation
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS usdc_upgrades (
+    id VARCHAR(128) PRIMARY KEY,              -- tx_hash-log_index
+    block_number BIGINT NOT NULL,
+    block_timestamp TIMESTAMP,
+    tx_hash VARCHAR(66) NOT NULL,
+    log_index INTEGER NOT NULL,
+    implementation VARCHAR(42) NOT NULL,      -- New implementation address
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- NET_FLOW: Received minus paid per address (negative for net payers)
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS net_flow (
+    address VARCHAR(42) PRIMARY KEY,
+    net_flow NUMERIC(39, 6) NOT NULL DEFAULT 0,  -- Signed, atomic units
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- BLOCKS: Per-block settlement rollup
+-- Blocks without settlements only with db_out param empty_blocks=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS blocks (
+    block_number BIGINT PRIMARY KEY,
+    block_timestamp TIMESTAMP,
+    settlement_count INTEGER NOT NULL DEFAULT 0,
+    total_volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Atomic units
+    total_gas BIGINT NOT NULL DEFAULT 0,      -- Gas used, once per transaction
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- SETTLEMENTS_HOURLY: Protocol-wide volume and count per UTC hour
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS settlements_hourly (
+    hour VARCHAR(13) PRIMARY KEY,             -- YYYY-MM-DDTHH (UTC)
+    volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Running total for the hour (atomic units)
+    count INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- SETTLEMENTS_WEEKLY: Protocol-wide volume and count per ISO week
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS settlements_weekly (
+    week VARCHAR(8) PRIMARY KEY,              -- YYYY-Www (ISO 8601, weeks start Monday)
+    volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Running total for the week (atomic units)
+    count INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- SETTLEMENTS_MONTHLY: Protocol-wide volume and count per UTC month
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS settlements_monthly (
+    month VARCHAR(7) PRIMARY KEY,             -- YYYY-MM (UTC)
+    volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Running total for the month (atomic units)
+    count INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- PAYER_DAILY: Payment volume per payer per UTC day
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS payer_daily (
+    id VARCHAR(64) PRIMARY KEY,               -- payer:YYYY-MM-DD
+    payer VARCHAR(42) NOT NULL,
+    date DATE NOT NULL,                       -- UTC day
+    volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Running total for the day (atomic units)
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_payer_daily_date ON payer_daily(date);
+
+-------------------------------------------------
+-- HEARTBEAT: One row per UTC hour, even without settlements
+-- Only populated with db_out param heartbeat=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS heartbeat (
+    id VARCHAR(16) PRIMARY KEY,               -- YYYY-MM-DDTHH (UTC hour)
+    block_number BIGINT NOT NULL,             -- First block of the hour
+    block_timestamp TIMESTAMP NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- AGGREGATE_DELTAS: Per-block changes to volume totals
+-- Only populated with db_out param aggregate_deltas_only=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS aggregate_deltas (
+    id VARCHAR(96) PRIMARY KEY,               -- block_number:kind:address
+    block_number BIGINT NOT NULL,
+    kind VARCHAR(16) NOT NULL,                -- payer, recipient, facilitator
+    address VARCHAR(42) NOT NULL,
+    change NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Volume added this block
+    new_total NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_aggregate_deltas_block ON aggregate_deltas(block_number);
+CREATE INDEX IF NOT EXISTS idx_aggregate_deltas_address ON aggregate_deltas(address);
+
+-------------------------------------------------
+-- PERSONAL_RECORDS: Payments beating the payer's prior largest payment
+-- Only populated with db_out param personal_records=true
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS personal_records (
+    id VARCHAR(128) PRIMARY KEY,              -- settlement id (tx_hash-log_index)
+    payer VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL,           -- The new personal max
+    recipient VARCHAR(42) NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_timestamp TIMESTAMP,
+    tx_hash VARCHAR(66) NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_personal_records_payer ON personal_records(payer);
 
 -------------------------------------------------
 -- PAYERS: Aggregated stats per payer address
@@ -57,6 +346,10 @@ CREATE TABLE IF NOT EXISTS payers (
     -- Payment metrics
     total_spent NUMERIC(38, 6) NOT NULL DEFAULT 0,
     total_payments INTEGER NOT NULL DEFAULT 0,
+    avg_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- total_spent / total_payments
+    min_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Smallest single payment
+    max_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Largest single payment
+    distinct_tokens INTEGER NOT NULL DEFAULT 0,   -- Distinct tokens spent (USDC-only = 1)
 
     -- Timestamps
     first_payment_at TIMESTAMP,
@@ -76,10 +369,18 @@ CREATE TABLE IF NOT EXISTS recipients (
     -- Revenue metrics
     total_received NUMERIC(38, 6) NOT NULL DEFAULT 0,
     total_payments INTEGER NOT NULL DEFAULT 0,
+    avg_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- total_received / total_payments
+    min_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Smallest single payment
+    max_payment NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Largest single payment
+    unique_payers INTEGER NOT NULL DEFAULT 0,  -- Distinct customers
 
     -- Timestamps
     first_payment_at TIMESTAMP,
     last_payment_at TIMESTAMP,
+    time_to_second_payment_seconds BIGINT,    -- First to second payment; NULL until a second
+
+    -- Time-weighted average payment size (EWMA, 7-day half-life)
+    ewma_payment_size NUMERIC(38, 6) NOT NULL DEFAULT 0,
     updated_at TIMESTAMP DEFAULT NOW()
 );
 
@@ -100,9 +401,17 @@ CREATE TABLE IF NOT EXISTS facilitators (
     -- Settlement metrics
     total_settlements INTEGER NOT NULL DEFAULT 0,
     total_volume_settled NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    -- Settlement path; eip3009_proxy counts toward both, so these can sum past the total
+    eip3009_count INTEGER NOT NULL DEFAULT 0,
+    proxy_count INTEGER NOT NULL DEFAULT 0,
 
     -- Gas economics
     total_gas_spent NUMERIC(38, 0) NOT NULL DEFAULT 0,   -- Total gas cost in wei
+    total_gas_spent_usd NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Total gas cost in USD (6 decimals)
+    cost_per_dollar_bps BIGINT NOT NULL DEFAULT 0,         -- Gas USD per dollar settled (bps)
+
+    -- Revenue: fees whose fee_recipient is the facilitator itself
+    total_fees_earned NUMERIC(38, 6) NOT NULL DEFAULT 0,
 
     -- Timestamps
     first_settlement_at TIMESTAMP,
@@ -114,6 +423,234 @@ CREATE INDEX IF NOT EXISTS idx_facilitators_volume ON facilitators(total_volume_
 CREATE INDEX IF NOT EXISTS idx_facilitators_settlements ON facilitators(total_settlements DESC);
 CREATE INDEX IF NOT EXISTS idx_facilitators_gas ON facilitators(total_gas_spent DESC);
 
+-------------------------------------------------
+-- FACILITATOR_LATENCY: valid_after (payer signing) to settlement, per facilitator
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS facilitator_latency (
+    facilitator_address VARCHAR(42) PRIMARY KEY,
+    avg_latency_seconds BIGINT NOT NULL DEFAULT 0,
+    max_latency_seconds BIGINT NOT NULL DEFAULT 0,
+    samples INTEGER NOT NULL DEFAULT 0,       -- Settlements with a known valid_after
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- CATEGORY_STATS: Volume by recipient category (recipient_categories param)
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS category_stats (
+    category VARCHAR(64) PRIMARY KEY,         -- 'uncategorized' when unlabeled
+    total_volume NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    total_payments INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- FEE_COMPETITION: Facilitator fee rates vs the protocol median
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS fee_competition (
+    facilitator_address VARCHAR(42) PRIMARY KEY,
+    avg_fee_bps INTEGER NOT NULL DEFAULT 0,   -- Mean fee share of payment + fee
+    median_fee_bps INTEGER NOT NULL DEFAULT 0,  -- Protocol-wide, 25 bps buckets
+    low_fee_facilitator BOOLEAN NOT NULL DEFAULT FALSE,  -- avg below median
+    samples INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- FEE_PREVALENCE: Share of settlements carrying a fee
+-- id = 'protocol' for the protocol-wide row, else a facilitator address
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS fee_prevalence (
+    id VARCHAR(42) PRIMARY KEY,
+    with_fee BIGINT NOT NULL DEFAULT 0,       -- Matched settlements with a fee
+    total BIGINT NOT NULL DEFAULT 0,          -- Matched settlements
+    fee_prevalence_bps INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- FACILITATOR_SLA: Composite reliability score per facilitator
+-- sla = (5 * success + 3 * latency + 2 * gas) / 10, all in bps
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS facilitator_sla (
+    facilitator_address VARCHAR(42) PRIMARY KEY,
+    sla_score_bps INTEGER NOT NULL DEFAULT 0,
+    success_bps INTEGER NOT NULL DEFAULT 0,   -- Matched / all settlements
+    latency_score_bps INTEGER NOT NULL DEFAULT 0,  -- Halves at 60s average latency
+    gas_score_bps INTEGER NOT NULL DEFAULT 0,  -- Halves at 10 bps gas per dollar
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- PROTOCOL_TOTALS: Headline totals (singleton, id = 'protocol')
+-- Re-emitted every snapshot_every blocks (db_out param)
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS protocol_totals (
+    id VARCHAR(16) PRIMARY KEY,
+    total_volume NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    total_fees NUMERIC(38, 6) NOT NULL DEFAULT 0,
+    total_settlements BIGINT NOT NULL DEFAULT 0,
+    approx_unique_payers BIGINT NOT NULL DEFAULT 0,      -- HyperLogLog, ~3.25% std error
+    approx_unique_recipients BIGINT NOT NULL DEFAULT 0,  -- HyperLogLog, ~3.25% std error
+    payer_gini_bps BIGINT NOT NULL DEFAULT 0,  -- Approximate Gini of payer spend (0-10000)
+    subsidy_rate_bps BIGINT NOT NULL DEFAULT 0,  -- Facilitator gas USD / total volume
+    block_number BIGINT NOT NULL,             -- Block of the latest snapshot
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- GROWTH: Day-over-day settlement count change
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS growth (
+    date DATE PRIMARY KEY,
+    count INTEGER NOT NULL DEFAULT 0,         -- Settlements on this day
+    pct_change_bps BIGINT,                    -- vs previous day; NULL on the first day
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- THROUGHPUT: Settlements per second since the previous settlement block
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS throughput (
+    block_number BIGINT PRIMARY KEY,
+    settlements INTEGER NOT NULL DEFAULT 0,   -- Settlements in this block
+    seconds_since_prior BIGINT NOT NULL DEFAULT 0,
+    settlements_per_second_milli BIGINT,      -- Thousandths; NULL on the first block or a bad gap
+    created_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- VOLUME_MOMENTUM: Second difference of daily volume
+-- momentum = today - 2 * yesterday + day before
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS volume_momentum (
+    date DATE PRIMARY KEY,
+    volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Volume on this day (atomic units)
+    momentum NUMERIC(38, 6),                  -- > 0 accelerating; NULL until two prior days
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- NEW_VS_RETURNING: Daily volume by payer cohort
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS new_vs_returning (
+    date DATE PRIMARY KEY,
+    new_volume NUMERIC(38, 6) NOT NULL DEFAULT 0,        -- Payers whose first payment was this day
+    returning_volume NUMERIC(38, 6) NOT NULL DEFAULT 0,  -- Payers first seen on an earlier day
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- DAILY_TYPE_MIX: Settlements per day and settlement type
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS daily_type_mix (
+    id VARCHAR(48) PRIMARY KEY,               -- date:settlement_type
+    date DATE NOT NULL,
+    settlement_type VARCHAR(32) NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    share_bps INTEGER NOT NULL DEFAULT 0,     -- Share of the day's settlements
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_daily_type_mix_date ON daily_type_mix(date);
+
+-------------------------------------------------
+-- VALUE_SPLIT: Protocol take rate (singleton, id = 'protocol')
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS value_split (
+    id VARCHAR(16) PRIMARY KEY,
+    recipient_total NUMERIC(38, 6) NOT NULL DEFAULT 0,   -- USDC kept by recipients
+    facilitator_total NUMERIC(38, 6) NOT NULL DEFAULT 0, -- USDC fees kept by facilitators
+    recipient_share_bps INTEGER NOT NULL DEFAULT 0,
+    facilitator_share_bps INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- PROXY_TYPE_MIX: Settled vs SettledWithPermit (singleton, id = 'protocol')
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS proxy_type_mix (
+    id VARCHAR(16) PRIMARY KEY,
+    settled BIGINT NOT NULL DEFAULT 0,
+    settled_with_permit BIGINT NOT NULL DEFAULT 0,
+    permit_share_bps INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- SUBSCRIPTIONS: Recurring equal payments per payer -> recipient
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id VARCHAR(160) PRIMARY KEY,              -- payer:recipient:amount
+    payer VARCHAR(42) NOT NULL,
+    recipient VARCHAR(42) NOT NULL,
+    amount NUMERIC(38, 6) NOT NULL,           -- The repeated payment amount
+    occurrences INTEGER NOT NULL DEFAULT 0,
+    avg_interval_seconds BIGINT NOT NULL DEFAULT 0,
+    last_payment_at TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_subscriptions_recipient ON subscriptions(recipient);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_payer ON subscriptions(payer);
+
+-------------------------------------------------
+-- TOP_RECIPIENTS_REACH: Recipients by unique payers
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS top_recipients_reach (
+    recipient_address VARCHAR(42) PRIMARY KEY,
+    unique_payers INTEGER NOT NULL DEFAULT 0,   -- Distinct payers that have paid this recipient
+    block_number BIGINT NOT NULL,               -- Block of the latest reach change
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_top_recipients_reach ON top_recipients_reach(unique_payers DESC);
+
+-------------------------------------------------
+-- LEADERBOARD: Top payers and recipients by volume
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS leaderboard (
+    id VARCHAR(24) PRIMARY KEY,                 -- role:rank
+    role VARCHAR(16) NOT NULL,                  -- payer or recipient
+    rank INTEGER NOT NULL,                      -- 1-based, up to map_leaderboard's top_n
+    address VARCHAR(42) NOT NULL,
+    total_volume NUMERIC(38, 6) NOT NULL DEFAULT 0, -- Cumulative volume (atomic units)
+    block_number BIGINT NOT NULL,               -- Block of the latest ranking
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_leaderboard_role_rank ON leaderboard(role, rank);
+
+-------------------------------------------------
+-- RECIPIENT_AMOUNT_VARIABILITY: Metered vs flat-fee billing
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS recipient_amount_variability (
+    recipient_address VARCHAR(42) PRIMARY KEY,
+    amount_variability INTEGER NOT NULL DEFAULT 0,  -- 0 = flat fee, up to 10000 bps = highly metered
+    total_payments INTEGER NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
+-------------------------------------------------
+-- GAS_SAVINGS: Gas saved by batching (singleton, id = 'protocol')
+-- Baseline: 21000 gas per payment as its own transaction
+-------------------------------------------------
+CREATE TABLE IF NOT EXISTS gas_savings (
+    id VARCHAR(16) PRIMARY KEY,
+    total_gas_saved NUMERIC(38, 0) NOT NULL DEFAULT 0,
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT NOW()
+);
+
 -------------------------------------------------
 -- DAILY_STATS: Computed from settlements table
 -------------------------------------------------
@@ -176,6 +713,8 @@ SELECT
     total_settlements,
     total_volume_settled,
     total_gas_spent,
+    total_gas_spent_usd,
+    cost_per_dollar_bps,
     CASE WHEN total_settlements > 0
         THEN ROUND(total_volume_settled / total_settlements, 6)
         ELSE 0
@@ -215,3 +754,13 @@ SELECT
 FROM settlements
 ORDER BY block_number DESC, log_index DESC
 LIMIT 100;
+
+-- Settlement-path migration: settlements per type per day, from the
+-- daily_type_mix table (store_daily_type_count, keyed {date}:{settlement_type})
+CREATE OR REPLACE VIEW settlement_type_daily AS
+SELECT
+    settlement_type,
+    date,
+    count
+FROM daily_type_mix
+ORDER BY date DESC, settlement_type;
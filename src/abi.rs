@@ -9,10 +9,15 @@
 //! 2. **Permit2 proxy (secondary)**: `Settled()` and `SettledWithPermit()` events from the
 //!    x402ExactPermit2Proxy contract (parameterless events).
 //!
-//! Also decodes ERC-20 `Transfer` events to extract payment amounts.
+//! Also decodes EIP-3009 `AuthorizationCanceled` events (aborted payments),
+//! ERC-20 `Transfer` events to extract payment amounts, and the
+//! EIP-3009 `transferWithAuthorization` / `receiveWithAuthorization` calldata for
+//! the signed validity window, and the full `transferWithAuthorization` transfer
+//! for settlements whose logs can't be paired.
 
 use substreams::Hex;
 use substreams_ethereum::pb::eth::v2::Log;
+use tiny_keccak::{Hasher, Keccak};
 
 // =============================================
 // Event topic hashes (keccak256)
@@ -36,6 +41,15 @@ pub const AUTHORIZATION_USED_TOPIC: [u8; 32] = [
     0x9e, 0x9e, 0xc3, 0x33, 0x47, 0x9b, 0x10, 0xa5,
 ];
 
+/// AuthorizationCanceled(address indexed authorizer, bytes32 indexed nonce)
+/// keccak256("AuthorizationCanceled(address,bytes32)")
+pub const AUTHORIZATION_CANCELED_TOPIC: [u8; 32] = [
+    0x1c, 0xdd, 0x46, 0xff, 0x24, 0x27, 0x16, 0xcd,
+    0xaa, 0x72, 0xd1, 0x59, 0xd3, 0x39, 0xa4, 0x85,
+    0xb3, 0x43, 0x83, 0x98, 0x34, 0x8d, 0x68, 0xf0,
+    0x9d, 0x7c, 0x8c, 0x0a, 0x59, 0x35, 0x3d, 0x81,
+];
+
 /// Settled() - x402 proxy event (no parameters)
 /// keccak256("Settled()")
 pub const SETTLED_TOPIC: [u8; 32] = [
@@ -72,6 +86,40 @@ pub const FACILITATOR_REMOVED_TOPIC: [u8; 32] = [
     0xab, 0x91, 0x48, 0xc7, 0x69, 0x9c, 0x0a, 0x17,
 ];
 
+/// AnswerUpdated(int256 indexed current, uint256 indexed roundId, uint256 updatedAt)
+/// keccak256("AnswerUpdated(int256,uint256,uint256)") - Chainlink aggregator
+pub const ANSWER_UPDATED_TOPIC: [u8; 32] = [
+    0x05, 0x59, 0x88, 0x4f, 0xd3, 0xa4, 0x60, 0xdb,
+    0x30, 0x73, 0xb7, 0xfc, 0x89, 0x6c, 0xc7, 0x79,
+    0x86, 0xf1, 0x6e, 0x37, 0x82, 0x10, 0xde, 0xd4,
+    0x31, 0x86, 0x17, 0x5b, 0xf6, 0x46, 0xfc, 0x5f,
+];
+
+/// Upgraded(address indexed implementation)
+/// keccak256("Upgraded(address)") - EIP-1967 proxy (USDC on Base)
+pub const UPGRADED_TOPIC: [u8; 32] = [
+    0xbc, 0x7c, 0xd7, 0x5a, 0x20, 0xee, 0x27, 0xfd,
+    0x9a, 0xde, 0xba, 0xb3, 0x20, 0x41, 0xf7, 0x55,
+    0x21, 0x4d, 0xbc, 0x6b, 0xff, 0xa9, 0x0c, 0xc0,
+    0x22, 0x5b, 0x39, 0xda, 0x2e, 0x5c, 0x2d, 0x3b,
+];
+
+// =============================================
+// Function selectors (first 4 bytes of keccak256)
+// =============================================
+
+/// transferWithAuthorization(address,address,uint256,uint256,uint256,bytes32,uint8,bytes32,bytes32)
+pub const TRANSFER_WITH_AUTHORIZATION_SELECTOR: [u8; 4] = [0xe3, 0xee, 0x16, 0x0e];
+
+/// transferWithAuthorization(address,address,uint256,uint256,uint256,bytes32,bytes)
+pub const TRANSFER_WITH_AUTHORIZATION_BYTES_SELECTOR: [u8; 4] = [0xcf, 0x09, 0x29, 0x95];
+
+/// receiveWithAuthorization(address,address,uint256,uint256,uint256,bytes32,uint8,bytes32,bytes32)
+pub const RECEIVE_WITH_AUTHORIZATION_SELECTOR: [u8; 4] = [0xef, 0x55, 0xbe, 0xc6];
+
+/// receiveWithAuthorization(address,address,uint256,uint256,uint256,bytes32,bytes)
+pub const RECEIVE_WITH_AUTHORIZATION_BYTES_SELECTOR: [u8; 4] = [0x88, 0xb7, 0xab, 0x63];
+
 // =============================================
 // Decoded event structs
 // =============================================
@@ -101,6 +149,50 @@ pub struct AuthorizationUsedEvent {
     pub authorizer: Vec<u8>,
     pub nonce: Vec<u8>,
     pub log_index: u32,
+    /// Log index relative to the block (log_index is relative to the tx)
+    pub block_index: u32,
+}
+
+/// Decoded EIP-3009 AuthorizationCanceled event
+pub struct AuthorizationCanceledEvent {
+    pub authorizer: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub log_index: u32,
+}
+
+/// Decoded Chainlink AnswerUpdated event
+pub struct AnswerUpdatedEvent {
+    /// Latest answer, in the feed's decimals
+    pub current: String,
+    pub updated_at: u64,
+}
+
+/// Decoded proxy Upgraded event
+pub struct UpgradedEvent {
+    pub implementation: Vec<u8>,
+}
+
+/// Decoded EIP-3009 authorization call (transfer/receiveWithAuthorization)
+pub struct AuthorizationCall {
+    pub from: Vec<u8>,
+    /// Unix seconds; 0 if it doesn't fit in u64
+    pub valid_after: u64,
+    /// Unix seconds; 0 if it doesn't fit in u64 (e.g. max uint256)
+    pub valid_before: u64,
+    pub nonce: Vec<u8>,
+}
+
+/// Decoded transferWithAuthorization calldata
+pub struct TransferWithAuthorizationCall {
+    pub from: Vec<u8>,
+    pub to: Vec<u8>,
+    /// Atomic units
+    pub value: String,
+    /// Unix seconds; 0 if it doesn't fit in u64
+    pub valid_after: u64,
+    /// Unix seconds; 0 if it doesn't fit in u64 (e.g. max uint256)
+    pub valid_before: u64,
+    pub nonce: Vec<u8>,
 }
 
 // =============================================
@@ -117,8 +209,8 @@ pub fn decode_erc20_transfer(log: &Log) -> Option<TransferEvent> {
         return None;
     }
 
-    let from = log.topics[1][12..32].to_vec();
-    let to = log.topics[2][12..32].to_vec();
+    let from = topic_to_address(&log.topics[1])?;
+    let to = topic_to_address(&log.topics[2])?;
     let amount = parse_uint256(&log.data[0..32]);
 
     Some(TransferEvent {
@@ -142,13 +234,54 @@ pub fn decode_authorization_used(log: &Log) -> Option<AuthorizationUsedEvent> {
         return None;
     }
 
-    let authorizer = log.topics[1][12..32].to_vec();
+    let authorizer = topic_to_address(&log.topics[1])?;
+    if log.topics[2].len() != 32 {
+        return None;
+    }
     let nonce = log.topics[2].clone();
 
     Some(AuthorizationUsedEvent {
         authorizer,
         nonce,
         log_index: log.index,
+        block_index: log.block_index,
+    })
+}
+
+/// Build a well-formed AuthorizationUsed log for tests: the authorizer
+/// zero-padded into topic 1 and the nonce as topic 2. Address, index and
+/// block_index are left at their defaults for the caller to set.
+#[cfg(test)]
+pub(crate) fn authorization_used_log(authorizer: &[u8; 20], nonce: &[u8; 32]) -> Log {
+    let mut authorizer_topic = vec![0u8; 12];
+    authorizer_topic.extend_from_slice(authorizer);
+    Log {
+        topics: vec![AUTHORIZATION_USED_TOPIC.to_vec(), authorizer_topic, nonce.to_vec()],
+        ..Default::default()
+    }
+}
+
+/// Decode an EIP-3009 AuthorizationCanceled event.
+///
+/// Emitted by USDC when the authorizer calls cancelAuthorization before the
+/// authorization is used. No transfer happens, so it is never a settlement.
+pub fn decode_authorization_canceled(log: &Log) -> Option<AuthorizationCanceledEvent> {
+    if log.topics.len() < 3 {
+        return None;
+    }
+    if log.topics[0] != AUTHORIZATION_CANCELED_TOPIC {
+        return None;
+    }
+
+    let authorizer = topic_to_address(&log.topics[1])?;
+    if log.topics[2].len() != 32 {
+        return None;
+    }
+
+    Some(AuthorizationCanceledEvent {
+        authorizer,
+        nonce: log.topics[2].clone(),
+        log_index: log.index,
     })
 }
 
@@ -172,7 +305,7 @@ pub fn decode_facilitator_added(log: &Log) -> Option<FacilitatorAddedEvent> {
         return None;
     }
 
-    let facilitator = log.topics[1][12..32].to_vec();
+    let facilitator = topic_to_address(&log.topics[1])?;
 
     // ABI-decode dynamic data: name (string) and url (string)
     let name = decode_abi_string(&log.data, 0).unwrap_or_default();
@@ -191,10 +324,98 @@ pub fn decode_facilitator_removed(log: &Log) -> Option<FacilitatorRemovedEvent>
         return None;
     }
 
-    let facilitator = log.topics[1][12..32].to_vec();
+    let facilitator = topic_to_address(&log.topics[1])?;
     Some(FacilitatorRemovedEvent { facilitator })
 }
 
+/// Decode Chainlink AnswerUpdated event
+/// Event: AnswerUpdated(int256 indexed current, uint256 indexed roundId, uint256 updatedAt)
+///
+/// Negative answers are rejected since they can't be a valid price.
+pub fn decode_answer_updated(log: &Log) -> Option<AnswerUpdatedEvent> {
+    if log.topics.len() < 3 || log.data.len() < 32 {
+        return None;
+    }
+    if log.topics[0] != ANSWER_UPDATED_TOPIC || log.topics[1].len() != 32 {
+        return None;
+    }
+    if log.topics[1][0] & 0x80 != 0 {
+        return None;
+    }
+
+    let current = parse_uint256(&log.topics[1]);
+    let updated_at = parse_uint256_as_usize(&log.data[0..32])? as u64;
+    Some(AnswerUpdatedEvent { current, updated_at })
+}
+
+/// Decode proxy Upgraded event
+/// Event: Upgraded(address indexed implementation)
+pub fn decode_upgraded(log: &Log) -> Option<UpgradedEvent> {
+    if log.topics.len() < 2 || log.topics[0] != UPGRADED_TOPIC {
+        return None;
+    }
+    let implementation = topic_to_address(&log.topics[1])?;
+    Some(UpgradedEvent { implementation })
+}
+
+/// Decode EIP-3009 transferWithAuthorization / receiveWithAuthorization calldata.
+/// All four variants share the same leading params:
+/// (from, to, value, validAfter, validBefore, nonce, ...signature)
+pub fn decode_authorization_call(input: &[u8]) -> Option<AuthorizationCall> {
+    if input.len() < 4 + 6 * 32 {
+        return None;
+    }
+    let selector = &input[..4];
+    if selector != TRANSFER_WITH_AUTHORIZATION_SELECTOR
+        && selector != TRANSFER_WITH_AUTHORIZATION_BYTES_SELECTOR
+        && selector != RECEIVE_WITH_AUTHORIZATION_SELECTOR
+        && selector != RECEIVE_WITH_AUTHORIZATION_BYTES_SELECTOR
+    {
+        return None;
+    }
+
+    let word = |i: usize| &input[4 + i * 32..4 + (i + 1) * 32];
+    Some(AuthorizationCall {
+        from: topic_to_address(word(0))?,
+        valid_after: parse_uint256_as_u64(word(3)).unwrap_or(0),
+        valid_before: parse_uint256_as_u64(word(4)).unwrap_or(0),
+        nonce: word(5).to_vec(),
+    })
+}
+
+/// Decode transferWithAuthorization(address from, address to, uint256 value,
+/// uint256 validAfter, uint256 validBefore, bytes32 nonce, uint8 v, bytes32 r,
+/// bytes32 s) calldata, selector 0xe3ee160e, into the transfer it authorizes
+pub fn decode_transfer_with_authorization_call(
+    input: &[u8],
+) -> Option<TransferWithAuthorizationCall> {
+    if input.len() < 4 + 9 * 32 || input[..4] != TRANSFER_WITH_AUTHORIZATION_SELECTOR {
+        return None;
+    }
+
+    let word = |i: usize| &input[4 + i * 32..4 + (i + 1) * 32];
+    Some(TransferWithAuthorizationCall {
+        from: topic_to_address(word(0))?,
+        to: topic_to_address(word(1))?,
+        value: parse_uint256(word(2)),
+        valid_after: parse_uint256_as_u64(word(3)).unwrap_or(0),
+        valid_before: parse_uint256_as_u64(word(4)).unwrap_or(0),
+        nonce: word(5).to_vec(),
+    })
+}
+
+/// Extract the 20-byte address from an indexed address topic (or ABI word).
+/// Returns None for topics that are not a full 32-byte word, so a
+/// nonstandard or malformed token log can't panic the slice, and for words
+/// whose high 12 bytes aren't zero padding, which hold a hash or number
+/// rather than an address.
+fn topic_to_address(topic: &[u8]) -> Option<Vec<u8>> {
+    if topic.len() != 32 || topic[..12].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(topic[12..32].to_vec())
+}
+
 /// Decode an ABI-encoded string from event data at a given parameter index.
 /// ABI encoding: offset at param_index*32, then length at offset, then string bytes.
 fn decode_abi_string(data: &[u8], param_index: usize) -> Option<String> {
@@ -230,6 +451,16 @@ fn parse_uint256_as_usize(data: &[u8]) -> Option<usize> {
     Some(u64::from_be_bytes(bytes) as usize)
 }
 
+/// Parse uint256 as u64, returning None if it doesn't fit
+fn parse_uint256_as_u64(data: &[u8]) -> Option<u64> {
+    if data.len() != 32 || data[..24].iter().any(|&b| b != 0) {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[24..32]);
+    Some(u64::from_be_bytes(bytes))
+}
+
 /// Parse uint256 from 32-byte big-endian slice
 pub fn parse_uint256(data: &[u8]) -> String {
     if data.len() != 32 {
@@ -244,6 +475,30 @@ pub fn format_address(bytes: &[u8]) -> String {
     format!("0x{}", Hex(bytes).to_string())
 }
 
+/// Format raw bytes as an EIP-55 checksummed address: each hex letter is
+/// uppercased when the matching nibble of keccak256(lowercase hex) is >= 8
+pub fn format_address_checksummed(bytes: &[u8]) -> String {
+    let lower = Hex(bytes).to_string();
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(lower.as_bytes());
+    keccak.finalize(&mut hash);
+
+    let checksummed: String = lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -271,6 +526,152 @@ mod tests {
         assert_eq!(parse_uint256(&data), "1000000");
     }
 
+    fn transfer_log(from_topic: Vec<u8>, to_topic: Vec<u8>) -> Log {
+        let mut data = vec![0u8; 32];
+        data[31] = 1;
+        Log {
+            topics: vec![TRANSFER_TOPIC.to_vec(), from_topic, to_topic],
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_erc20_transfer_padded_topics() {
+        let mut from = vec![0u8; 32];
+        from[12..].copy_from_slice(&[0x11; 20]);
+        let mut to = vec![0u8; 32];
+        to[12..].copy_from_slice(&[0x22; 20]);
+
+        let transfer = decode_erc20_transfer(&transfer_log(from, to)).unwrap();
+        assert_eq!(transfer.from, vec![0x11; 20]);
+        assert_eq!(transfer.to, vec![0x22; 20]);
+        assert_eq!(transfer.amount, "1");
+    }
+
+    #[test]
+    fn test_topic_to_address_requires_zero_padding() {
+        let mut padded = vec![0u8; 32];
+        padded[12..].copy_from_slice(&[0x11; 20]);
+        assert_eq!(topic_to_address(&padded), Some(vec![0x11; 20]));
+
+        // A full 32-byte value such as a hash isn't an address
+        assert_eq!(topic_to_address(&[0xab; 32]), None);
+        let mut dirty = padded.clone();
+        dirty[0] = 0x01;
+        assert_eq!(topic_to_address(&dirty), None);
+        assert!(decode_erc20_transfer(&transfer_log(dirty, padded)).is_none());
+    }
+
+    #[test]
+    fn test_decode_erc20_transfer_short_topic_is_rejected() {
+        // A non-padded 20-byte address topic must not panic the [12..32] slice
+        let log = transfer_log(vec![0x11; 20], vec![0u8; 32]);
+        assert!(decode_erc20_transfer(&log).is_none());
+
+        // Truncated 16-byte topics, on either side
+        let log = transfer_log(vec![0x11; 16], vec![0u8; 32]);
+        assert!(decode_erc20_transfer(&log).is_none());
+        let log = transfer_log(vec![0u8; 32], vec![0x22; 16]);
+        assert!(decode_erc20_transfer(&log).is_none());
+    }
+
+    #[test]
+    fn test_decode_answer_updated() {
+        // 3,000.00000000 USD (8 decimals) = 300_000_000_000 = 0x45d964b800
+        let mut current = vec![0u8; 32];
+        current[27..].copy_from_slice(&[0x45, 0xd9, 0x64, 0xb8, 0x00]);
+        let mut updated_at = vec![0u8; 32];
+        updated_at[28..].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        let log = Log {
+            topics: vec![ANSWER_UPDATED_TOPIC.to_vec(), current, vec![0u8; 32]],
+            data: updated_at,
+            ..Default::default()
+        };
+
+        let answer = decode_answer_updated(&log).unwrap();
+        assert_eq!(answer.current, "300000000000");
+        assert_eq!(answer.updated_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_decode_upgraded() {
+        let mut implementation = vec![0u8; 32];
+        implementation[12..].copy_from_slice(&[0x5e; 20]);
+        let log = Log {
+            topics: vec![UPGRADED_TOPIC.to_vec(), implementation],
+            ..Default::default()
+        };
+
+        let upgraded = decode_upgraded(&log).unwrap();
+        assert_eq!(upgraded.implementation, vec![0x5e; 20]);
+
+        let other = Log {
+            topics: vec![TRANSFER_TOPIC.to_vec(), vec![0u8; 32]],
+            ..Default::default()
+        };
+        assert!(decode_upgraded(&other).is_none());
+    }
+
+    #[test]
+    fn test_decode_authorization_call() {
+        let word = |tail: &[u8]| {
+            let mut w = vec![0u8; 32];
+            w[32 - tail.len()..].copy_from_slice(tail);
+            w
+        };
+        let mut input = TRANSFER_WITH_AUTHORIZATION_SELECTOR.to_vec();
+        input.extend(word(&[0x11; 20]));
+        input.extend(word(&[0x22; 20]));
+        input.extend(word(&10_000u32.to_be_bytes()));
+        input.extend(word(&1_700_000_000u32.to_be_bytes()));
+        input.extend(vec![0xff; 32]); // validBefore = max uint256
+        input.extend(vec![0xab; 32]);
+        input.extend(vec![0u8; 3 * 32]); // v, r, s
+
+        let call = decode_authorization_call(&input).unwrap();
+        assert_eq!(call.from, vec![0x11; 20]);
+        assert_eq!(call.valid_after, 1_700_000_000);
+        assert_eq!(call.valid_before, 0);
+        assert_eq!(call.nonce, vec![0xab; 32]);
+
+        input[0] = 0x00;
+        assert!(decode_authorization_call(&input).is_none());
+    }
+
+    #[test]
+    fn test_decode_transfer_with_authorization_call() {
+        // transferWithAuthorization(0x11.., 0x22.., 2_500_000, 1_700_000_000,
+        // 1_700_003_600, 0xab.., v=27, r, s)
+        let input = Hex::decode(concat!(
+            "e3ee160e",
+            "0000000000000000000000001111111111111111111111111111111111111111",
+            "0000000000000000000000002222222222222222222222222222222222222222",
+            "00000000000000000000000000000000000000000000000000000000002625a0",
+            "000000000000000000000000000000000000000000000000000000006553f100",
+            "000000000000000000000000000000000000000000000000000000006553ff10",
+            "abababababababababababababababababababababababababababababababab",
+            "000000000000000000000000000000000000000000000000000000000000001b",
+            "0101010101010101010101010101010101010101010101010101010101010101",
+            "0202020202020202020202020202020202020202020202020202020202020202",
+        ))
+        .unwrap();
+
+        let call = decode_transfer_with_authorization_call(&input).unwrap();
+        assert_eq!(call.from, vec![0x11; 20]);
+        assert_eq!(call.to, vec![0x22; 20]);
+        assert_eq!(call.value, "2500000");
+        assert_eq!(call.valid_after, 1_700_000_000);
+        assert_eq!(call.valid_before, 1_700_003_600);
+        assert_eq!(call.nonce, vec![0xab; 32]);
+
+        // Other authorization variants and truncated calldata are not transfers
+        let mut receive = input.clone();
+        receive[..4].copy_from_slice(&RECEIVE_WITH_AUTHORIZATION_SELECTOR);
+        assert!(decode_transfer_with_authorization_call(&receive).is_none());
+        assert!(decode_transfer_with_authorization_call(&input[..4 + 8 * 32]).is_none());
+    }
+
     #[test]
     fn test_format_address() {
         let bytes = [0xAB; 20];
@@ -278,4 +679,108 @@ mod tests {
         assert!(addr.starts_with("0x"));
         assert_eq!(addr.len(), 42);
     }
+
+    #[test]
+    fn test_format_address_checksummed() {
+        // EIP-55 test vectors
+        for expected in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let bytes = Hex::decode(&expected[2..]).unwrap();
+            assert_eq!(format_address_checksummed(&bytes), expected);
+            assert_eq!(format_address(&bytes), expected.to_lowercase());
+        }
+    }
+
+    fn keccak256(input: &[u8]) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        let mut keccak = Keccak::v256();
+        keccak.update(input);
+        keccak.finalize(&mut hash);
+        hash
+    }
+
+    fn proxy_log(topic: &[u8; 32]) -> Log {
+        Log {
+            topics: vec![topic.to_vec()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_authorization_used() {
+        assert_eq!(
+            AUTHORIZATION_USED_TOPIC,
+            keccak256(b"AuthorizationUsed(address,bytes32)")
+        );
+        let mut log = authorization_used_log(&[0x11; 20], &[0x07; 32]);
+        log.index = 4;
+        log.block_index = 40;
+        let auth = decode_authorization_used(&log).unwrap();
+        assert_eq!(auth.authorizer, vec![0x11; 20]);
+        assert_eq!(auth.nonce, vec![0x07; 32]);
+        assert_eq!((auth.log_index, auth.block_index), (4, 40));
+
+        // Wrong signature
+        let mut wrong = log.clone();
+        wrong.topics[0] = TRANSFER_TOPIC.to_vec();
+        assert!(decode_authorization_used(&wrong).is_none());
+
+        // Too few topics
+        let mut short = log.clone();
+        short.topics.truncate(2);
+        assert!(decode_authorization_used(&short).is_none());
+
+        // A nonce topic that isn't a full word
+        let mut bad_nonce = log;
+        bad_nonce.topics[2].truncate(16);
+        assert!(decode_authorization_used(&bad_nonce).is_none());
+    }
+
+    #[test]
+    fn test_decode_authorization_canceled() {
+        assert_eq!(
+            AUTHORIZATION_CANCELED_TOPIC,
+            keccak256(b"AuthorizationCanceled(address,bytes32)")
+        );
+        let mut authorizer = vec![0u8; 12];
+        authorizer.extend_from_slice(&[0x11u8; 20]);
+        let log = Log {
+            topics: vec![AUTHORIZATION_CANCELED_TOPIC.to_vec(), authorizer, vec![0x07; 32]],
+            index: 3,
+            ..Default::default()
+        };
+        let canceled = decode_authorization_canceled(&log).unwrap();
+        assert_eq!(canceled.authorizer, vec![0x11u8; 20]);
+        assert_eq!(canceled.nonce, vec![0x07; 32]);
+        assert_eq!(canceled.log_index, 3);
+        // Not an AuthorizationUsed, so it never pairs with a Transfer
+        assert!(decode_authorization_used(&log).is_none());
+    }
+
+    #[test]
+    fn test_proxy_topics_match_signatures() {
+        assert_eq!(SETTLED_TOPIC, keccak256(b"Settled()"));
+        assert_eq!(SETTLED_WITH_PERMIT_TOPIC, keccak256(b"SettledWithPermit()"));
+    }
+
+    #[test]
+    fn test_settled_and_settled_with_permit_are_distinct() {
+        let settled = proxy_log(&SETTLED_TOPIC);
+        assert!(is_settled_event(&settled));
+        assert!(!is_settled_with_permit_event(&settled));
+
+        let with_permit = proxy_log(&SETTLED_WITH_PERMIT_TOPIC);
+        assert!(is_settled_with_permit_event(&with_permit));
+        assert!(!is_settled_event(&with_permit));
+
+        // Classification ignores data length
+        let mut padded = proxy_log(&SETTLED_TOPIC);
+        padded.data = vec![0u8; 256];
+        assert!(is_settled_event(&padded));
+        assert!(!is_settled_with_permit_event(&padded));
+    }
 }
@@ -15,26 +15,39 @@
 //!
 //! Module layers:
 //! - Layer 1: Event extraction (map_x402_settlements)
-//! - Layer 2: State stores (payer/recipient/facilitator volume, counts, gas)
-//! - Layer 3: Analytics (map_payer_stats, map_recipient_stats, map_facilitator_stats)
+//! - Layer 2: State stores (payer/recipient/facilitator volume, counts, gas, daily counts)
+//! - Layer 3: Analytics (map_payer_stats, map_recipient_stats, map_facilitator_stats,
+//!   map_growth_rate)
 //! - Layer 4: SQL sink (db_out)
 
+// The handler macros expand `params: String` inputs into raw-pointer reads.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 mod abi;
+#[allow(dead_code, clippy::all)]
 mod pb;
 
 use abi::{
+    decode_answer_updated, decode_authorization_call, decode_authorization_canceled,
     decode_authorization_used, decode_erc20_transfer, decode_facilitator_added,
-    decode_facilitator_removed, format_address, is_settled_event,
-    is_settled_with_permit_event,
+    decode_facilitator_removed, decode_transfer_with_authorization_call, decode_upgraded,
+    format_address, format_address_checksummed, is_settled_event, is_settled_with_permit_event,
+    AuthorizationCanceledEvent, AuthorizationUsedEvent, TransferEvent,
+    TransferWithAuthorizationCall,
 };
 use hex_literal::hex;
 use pb::x402::v1 as x402;
 use substreams::prelude::*;
 use substreams::scalar::BigInt;
-use substreams::store::{StoreAddBigInt, StoreAddInt64, StoreGet, StoreSet, StoreSetIfNotExistsInt64};
+use substreams::store::{
+    StoreAddBigInt, StoreAddInt64, StoreAppend, StoreGet, StoreGetArray, StoreSet,
+    StoreSetIfNotExistsInt64, StoreSetIfNotExistsString, StoreSetInt64,
+};
+use substreams::pb::substreams::store_delta::Operation;
+use substreams::pb::substreams::Clock;
 use substreams::Hex;
 use substreams_database_change::pb::database::DatabaseChanges;
-use substreams_database_change::tables::Tables;
+use substreams_database_change::tables::{Row, Tables};
 use substreams_ethereum::pb::eth::v2 as eth;
 
 // =============================================
@@ -45,28 +58,102 @@ use substreams_ethereum::pb::eth::v2 as eth;
 /// USDC on Base mainnet - EIP-3009 compliant token
 const USDC: [u8; 20] = hex!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913");
 
+/// EURC on Base mainnet - EIP-3009 compliant token
+const EURC: [u8; 20] = hex!("60a3E35Cc302bFA44Cb288Bc5a4F316Fdb1adb42");
+
+/// EIP-3009 tokens that x402 settles on Base
+const SETTLEMENT_TOKENS: &[[u8; 20]] = &[USDC, EURC];
+
 /// x402ExactPermit2Proxy - deterministic across all EVM chains via CREATE2
 const X402_PROXY: [u8; 20] = hex!("4020615294c913F045dc10f0a5cdEbd86c280001");
 
 /// x402UptoPermit2Proxy - secondary proxy for "upto" payment scheme
 const X402_UPTO_PROXY: [u8; 20] = hex!("4020633461b2895a48930Ff97eE8fCdE8E520002");
 
+/// x402 proxies watched when the `proxy_addresses` param is unset or invalid
+const PROXY_ADDRESSES: &[[u8; 20]] = &[X402_PROXY, X402_UPTO_PROXY];
+
+/// Protocol version of each known x402 proxy deployment. New proxy versions
+/// are deployed at new addresses, so the address identifies the version.
+const PROXY_VERSIONS: &[([u8; 20], &str)] = &[
+    (X402_PROXY, "exact_permit2_v1"),
+    (X402_UPTO_PROXY, "upto_permit2_v1"),
+];
+
+/// Multicall3 - deterministic across all EVM chains, batches calls into one tx
+const MULTICALL3: [u8; 20] = hex!("cA11bde05977b3631167028862bE2a173976CA11");
+
+/// Known multicall / aggregator contracts that batch settlements
+const MULTICALL_ADDRESSES: &[[u8; 20]] = &[MULTICALL3];
+
 /// FacilitatorRegistry on Base - tracks authorized x402 facilitator addresses
 const FACILITATOR_REGISTRY: [u8; 20] = hex!("67C75c4FD5BbbF5f6286A1874fe2d7dF0024Ebe8");
 
+/// Decimals of Chainlink USD-denominated feeds (ETH/USD answers are 8-decimal)
+const ETH_USD_DECIMALS: u32 = 8;
+
+/// Decimals of USD amounts derived here, matching USDC atomic units
+const USD_DECIMALS: u32 = 6;
+
+/// `token` value for settlements paid in native ETH (amounts in wei)
+const NATIVE_TOKEN: &str = "native";
+
 // Null / zero address
 const ZERO_ADDR: &str = "0x0000000000000000000000000000000000000000";
 
 substreams_ethereum::init!();
 
-/// Convert Unix timestamp seconds to PostgreSQL TIMESTAMP format
+/// Convert Unix timestamp seconds to PostgreSQL TIMESTAMP format. Pre-1970
+/// (negative) input clamps to the epoch, as the calendar walk below only
+/// counts forward.
 fn unix_to_timestamp(secs: i64) -> String {
-    let days_since_epoch = secs / 86400;
+    let secs = secs.max(0);
     let time_of_day = secs % 86400;
     let hours = time_of_day / 3600;
     let minutes = (time_of_day % 3600) / 60;
     let seconds = time_of_day % 60;
+    let (year, month, day) = days_to_civil(secs / 86400);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+/// Convert Unix timestamp seconds to a UTC `YYYY-MM-DD` day bucket, clamping
+/// pre-1970 input to the epoch
+fn unix_to_date(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (year, month, day) = days_to_civil(secs / 86400);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Convert Unix timestamp seconds to a UTC `YYYY-MM` month bucket
+fn unix_to_month(secs: i64) -> String {
+    unix_to_date(secs)[..7].to_string()
+}
+
+/// Convert Unix timestamp seconds to a UTC ISO 8601 week bucket, `YYYY-Www`.
+/// Weeks start on Monday and belong to the year holding their Thursday, so
+/// the days around New Year can fall in week 52 or 53 of the previous year
+/// or week 1 of the next.
+fn unix_to_iso_week(secs: i64) -> String {
+    let days = secs.max(0) / 86400;
+    // 1970-01-01 was a Thursday; ISO weekdays run Monday = 1 to Sunday = 7
+    let weekday = (days + 3) % 7 + 1;
+    let thursday = days - (weekday - 1) + 3;
+    let (year, _, _) = days_to_civil(thursday);
+    let week = (thursday - days_before_year(year)) / 7 + 1;
+    format!("{:04}-W{:02}", year, week)
+}
+
+/// Days from the Unix epoch to January 1 of `year` (1970 or later)
+fn days_before_year(year: i64) -> i64 {
+    (1970..year).map(|y| if is_leap_year(y) { 366 } else { 365 }).sum()
+}
 
+/// Convert days since the Unix epoch to a (year, month, day) calendar date
+fn days_to_civil(days_since_epoch: i64) -> (i64, i64, i64) {
     let mut days = days_since_epoch;
     let mut year = 1970i64;
     loop {
@@ -92,12 +179,8 @@ fn unix_to_timestamp(secs: i64) -> String {
         days -= d;
         month += 1;
     }
-    let day = days + 1;
 
-    format!(
-        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-        year, month, day, hours, minutes, seconds
-    )
+    (year, month, days + 1)
 }
 
 fn is_leap_year(y: i64) -> bool {
@@ -113,6 +196,37 @@ fn proto_bigint_to_string(bi: &eth::BigInt) -> String {
     val.to_string()
 }
 
+/// Parse a decimal amount string (atomic units, wei) into a BigInt of any
+/// size. Empty or malformed amounts count as 0.
+fn parse_amount(amount: &str) -> BigInt {
+    amount.parse().unwrap_or_else(|_| BigInt::zero())
+}
+
+/// Look up `key` in a `key=value&key=value` module params string
+fn param_value<'a>(params: &'a str, key: &str) -> Option<&'a str> {
+    params
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| k.trim() == key)
+        .map(|(_, v)| v.trim())
+}
+
+/// Parse a 0x-prefixed 20-byte hex address param
+fn parse_address_param(value: &str) -> Option<Vec<u8>> {
+    let bytes = Hex::decode(value.trim().trim_start_matches("0x")).ok()?;
+    if bytes.len() != 20 {
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Convert a wei gas cost to USD (6-decimal units, like USDC) at an
+/// ETH/USD price with ETH_USD_DECIMALS decimals.
+fn gas_cost_usd(gas_cost_wei: &BigInt, eth_usd_price: &BigInt) -> BigInt {
+    let scale = BigInt::from(10u64).pow(18 + ETH_USD_DECIMALS - USD_DECIMALS);
+    gas_cost_wei.clone() * eth_usd_price.clone() / scale
+}
+
 // =============================================
 // LAYER 0: Facilitator Registry
 // =============================================
@@ -173,10 +287,601 @@ fn store_facilitator_registry(
     }
 }
 
+// =============================================
+// LAYER 0: ETH/USD Price Feed
+// =============================================
+
+/// Extract the latest ETH/USD answer from a Chainlink aggregator's
+/// AnswerUpdated events in this block.
+///
+/// The aggregator is given by the `eth_usd_aggregator` param. It must be the
+/// aggregator contract that emits AnswerUpdated, not the EACAggregatorProxy
+/// in front of it, and it changes when Chainlink rotates the feed, which is
+/// why it isn't hardcoded. Without the param no price is emitted and USD gas
+/// metrics stay at zero.
+#[substreams::handlers::map]
+fn map_eth_usd_price(
+    params: String,
+    blk: eth::Block,
+) -> Result<x402::EthUsdPrice, substreams::errors::Error> {
+    let mut price = x402::EthUsdPrice {
+        block_number: blk.number,
+        ..Default::default()
+    };
+
+    let aggregator = match param_value(&params, "eth_usd_aggregator")
+        .and_then(parse_address_param)
+    {
+        Some(addr) => addr,
+        None => return Ok(price),
+    };
+
+    for log in blk.logs() {
+        if log.log.address != aggregator {
+            continue;
+        }
+        if let Some(answer) = decode_answer_updated(log.log) {
+            price.price = answer.current;
+            price.updated_at = answer.updated_at;
+        }
+    }
+
+    Ok(price)
+}
+
+/// Keep the last known ETH/USD price. Key: "latest"
+#[substreams::handlers::store]
+fn store_eth_usd_price(price: x402::EthUsdPrice, store: StoreSetBigInt) {
+    if let Ok(value) = BigInt::try_from(&price.price) {
+        store.set(0, "latest", &value);
+    }
+}
+
+// =============================================
+// LAYER 0: USDC Upgrades
+// =============================================
+
+/// Extract Upgraded events from the USDC proxy. A new implementation may
+/// change the event layout the decoders rely on, so each upgrade is surfaced
+/// for maintainers to re-verify decoding against it.
+#[substreams::handlers::map]
+fn map_usdc_upgrades(blk: eth::Block) -> Result<x402::UsdcUpgrades, substreams::errors::Error> {
+    let mut upgrades = x402::UsdcUpgrades {
+        block_number: blk.number,
+        block_timestamp: block_timestamp(&blk),
+        ..Default::default()
+    };
+
+    for log in blk.logs() {
+        if log.log.address != USDC {
+            continue;
+        }
+        if let Some(upgraded) = decode_upgraded(log.log) {
+            let tx_hash = Hex(&log.receipt.transaction.hash).to_string();
+            upgrades.upgrades.push(x402::UsdcUpgrade {
+                id: format!("{}-{}", tx_hash, log.log.index),
+                tx_hash,
+                log_index: log.log.index,
+                implementation: format_address(&upgraded.implementation),
+            });
+        }
+    }
+
+    Ok(upgrades)
+}
+
 // =============================================
 // LAYER 1: Event Extraction
 // =============================================
 
+/// Build a single monotonic sort key for a settlement across the whole stream:
+/// `block_number * 2^32 + block_index`, zero-padded to the width of u64::MAX so
+/// it sorts the same numerically and lexicographically. Uses the block-relative
+/// log index because `log_index` restarts in every transaction.
+fn global_seq(block_number: u64, block_index: u32) -> String {
+    format!("{:020}", (block_number << 32) | block_index as u64)
+}
+
+/// Namespace of settlement UUIDs: UUIDv5 of this package's URL in the RFC 4122
+/// URL namespace
+const SETTLEMENT_UUID_NAMESPACE: [u8; 16] = hex!("92b9813a8193583bb270331e556d82e0");
+
+/// SHA-1 digest, for UUIDv5. Not for anything security-sensitive.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (hv, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *hv = hv.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, v) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    digest
+}
+
+/// Deterministic UUIDv5 (RFC 4122) of a settlement id (`{tx_hash}-{log_index}`),
+/// for sinks that need UUID primary keys. Reprocessing yields the same UUID.
+fn settlement_uuid(id: &str) -> String {
+    let mut name = SETTLEMENT_UUID_NAMESPACE.to_vec();
+    name.extend_from_slice(id.as_bytes());
+    let mut b = [0u8; 16];
+    b.copy_from_slice(&sha1(&name)[..16]);
+    b[6] = (b[6] & 0x0f) | 0x50; // version 5
+    b[8] = (b[8] & 0x3f) | 0x80; // RFC 4122 variant
+    let hex = Hex(&b).to_string();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// AuthorizationUsed and Transfer events emitted by one token contract
+struct TokenLogs {
+    token: [u8; 20],
+    auths: Vec<AuthorizationUsedEvent>,
+    transfers: Vec<TransferEvent>,
+}
+
+/// Group a transaction's EIP-3009 logs by token contract, keeping only tokens
+/// with at least one AuthorizationUsed. Pairing within a group means a USDC
+/// authorization can never match another token's Transfer in the same tx.
+fn group_token_logs(logs: &[eth::Log], tokens: &[[u8; 20]]) -> Vec<TokenLogs> {
+    tokens
+        .iter()
+        .map(|token| {
+            let token_logs = || logs.iter().filter(move |log| log.address == token);
+            TokenLogs {
+                token: *token,
+                auths: token_logs().filter_map(decode_authorization_used).collect(),
+                transfers: token_logs().filter_map(decode_erc20_transfer).collect(),
+            }
+        })
+        .filter(|group| !group.auths.is_empty())
+        .collect()
+}
+
+/// transferWithAuthorization calls to the settlement tokens that no
+/// AuthorizationUsed log in `groups` accounts for (same token, payer and
+/// nonce), with their token and call index. These are settled from the
+/// calldata, catching what log pairing misses.
+fn unlogged_authorization_transfers<'a>(
+    trx: &'a eth::TransactionTrace,
+    groups: &[TokenLogs],
+) -> Vec<(&'a [u8], u32, TransferWithAuthorizationCall)> {
+    trx.calls
+        .iter()
+        .filter(|call| !call.state_reverted)
+        .filter(|call| SETTLEMENT_TOKENS.iter().any(|t| call.address == t))
+        .filter_map(|call| {
+            let transfer = decode_transfer_with_authorization_call(&call.input)?;
+            let logged = groups.iter().any(|group| {
+                group.token.as_slice() == call.address
+                    && group
+                        .auths
+                        .iter()
+                        .any(|a| a.authorizer == transfer.from && a.nonce == transfer.nonce)
+            });
+            (!logged).then_some((call.address.as_slice(), call.index, transfer))
+        })
+        .collect()
+}
+
+/// The block index just past every log in the block, where calldata-only
+/// settlements start numbering (see `unlogged_positions`)
+fn block_log_end(blk: &eth::Block) -> u32 {
+    blk.transaction_traces
+        .iter()
+        .filter_map(|trx| trx.receipt.as_ref()?.logs.last())
+        .map(|log| log.block_index + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// (log index, block index) for each of a tx's `count` calldata-only
+/// settlements, which have no log of their own. Log indexes continue past the
+/// tx's last log and block indexes continue from `next_block_index`, which
+/// starts at `block_log_end`, so no two settlements in a block share a
+/// position. They sort after the block's logged settlements.
+fn unlogged_positions(
+    logs: &[eth::Log],
+    count: usize,
+    next_block_index: &mut u32,
+) -> Vec<(u32, u32)> {
+    let first_log_index = logs.last().map_or(0, |log| log.index + 1);
+    (0..count as u32)
+        .map(|i| {
+            let block_index = *next_block_index;
+            *next_block_index += 1;
+            (first_log_index + i, block_index)
+        })
+        .collect()
+}
+
+/// AuthorizationCanceled events on the settlement tokens, with their token
+fn canceled_authorizations(logs: &[eth::Log]) -> Vec<(&[u8], AuthorizationCanceledEvent)> {
+    logs.iter()
+        .filter(|log| SETTLEMENT_TOKENS.iter().any(|t| log.address == t))
+        .filter_map(|log| decode_authorization_canceled(log).map(|c| (log.address.as_slice(), c)))
+        .collect()
+}
+
+/// A token's transfers in one transaction, indexed by sender and sorted by
+/// log index, split by whether they went to the facilitator.
+///
+/// Built once per token group so pairing each authorization is a hash
+/// lookup plus a binary search, O((auths + transfers) log transfers) per tx,
+/// rather than a scan of every transfer per authorization. That scan was
+/// quadratic for a crafted tx with thousands of transfers.
+struct TransferIndex<'a> {
+    /// Transfers to anyone but the facilitator, by sender
+    payments: TransfersBySender<'a>,
+    /// Transfers to the facilitator (fee candidates), by sender
+    to_facilitator: TransfersBySender<'a>,
+}
+
+type TransfersBySender<'a> = std::collections::HashMap<&'a [u8], Vec<&'a TransferEvent>>;
+
+impl<'a> TransferIndex<'a> {
+    fn new(transfers: &'a [TransferEvent], facilitator: &[u8]) -> Self {
+        let mut index = TransferIndex {
+            payments: TransfersBySender::new(),
+            to_facilitator: TransfersBySender::new(),
+        };
+        for t in transfers {
+            let by_sender = if t.to == facilitator {
+                &mut index.to_facilitator
+            } else {
+                &mut index.payments
+            };
+            by_sender.entry(t.from.as_slice()).or_default().push(t);
+        }
+        for list in index.payments.values_mut().chain(index.to_facilitator.values_mut()) {
+            list.sort_by_key(|t| t.log_index);
+        }
+        index
+    }
+}
+
+/// The earliest transfer in a log-index-sorted list after `log_index`,
+/// passing over those `skip` rejects
+fn first_transfer_after<'a>(
+    list: Option<&Vec<&'a TransferEvent>>,
+    log_index: u32,
+    skip: impl Fn(u32) -> bool,
+) -> Option<&'a TransferEvent> {
+    let list = list?;
+    let start = list.partition_point(|t| t.log_index <= log_index);
+    list[start..].iter().copied().find(|t| !skip(t.log_index))
+}
+
+/// Pair an AuthorizationUsed event with its payment Transfer and, if present,
+/// the fee Transfer that went to the facilitator in the same transaction.
+///
+/// In USDC's implementation, transferWithAuthorization emits AuthorizationUsed
+/// then Transfer, so the payment is the first Transfer from the authorizer with
+/// a higher log index. Transfers to the facilitator itself are passed over when
+/// picking the payment, since those are the facilitator's cut. The fee is the
+/// nearest such transfer sent by the payer or forwarded by the recipient.
+/// Transfers in `consumed` (by log index) already belong to an earlier
+/// authorization and are passed over.
+fn pair_authorization_transfers<'a>(
+    auth: &AuthorizationUsedEvent,
+    index: &TransferIndex<'a>,
+    consumed: &std::collections::HashSet<u32>,
+) -> (Option<&'a TransferEvent>, Option<&'a TransferEvent>) {
+    let after_auth = |by_sender: &TransfersBySender<'a>, from: &[u8], skip: &dyn Fn(u32) -> bool| {
+        first_transfer_after(by_sender.get(from), auth.log_index, skip)
+    };
+
+    let taken = |log_index| consumed.contains(&log_index);
+    let payment = after_auth(&index.payments, &auth.authorizer, &taken)
+        .or_else(|| after_auth(&index.to_facilitator, &auth.authorizer, &taken));
+
+    let fee = payment.and_then(|p| {
+        let skip = |log_index| log_index == p.log_index || consumed.contains(&log_index);
+        let from_payer = after_auth(&index.to_facilitator, &auth.authorizer, &skip);
+        let from_recipient = after_auth(&index.to_facilitator, &p.to, &skip);
+        from_payer.into_iter().chain(from_recipient).min_by_key(|t| t.log_index)
+    });
+
+    (payment, fee)
+}
+
+/// Pair each of a token group's authorizations with its payment and fee
+/// transfers, in the order given. Authorizations are paired in log-index
+/// order and each consumes the transfers it takes, so one authorizer
+/// settling twice in a transaction gets two distinct transfers rather than
+/// both claiming the first.
+fn pair_group_transfers<'a>(
+    auths: &[AuthorizationUsedEvent],
+    index: &TransferIndex<'a>,
+) -> Vec<(Option<&'a TransferEvent>, Option<&'a TransferEvent>)> {
+    let mut order: Vec<usize> = (0..auths.len()).collect();
+    order.sort_by_key(|&i| auths[i].log_index);
+
+    let mut consumed = std::collections::HashSet::new();
+    let mut pairs = vec![(None, None); auths.len()];
+    for i in order {
+        let (payment, fee) = pair_authorization_transfers(&auths[i], index, &consumed);
+        consumed.extend(payment.iter().chain(fee.iter()).map(|t| t.log_index));
+        pairs[i] = (payment, fee);
+    }
+    pairs
+}
+
+/// What the recipient keeps of its payment. A fee the payer sent the
+/// facilitator directly is separate; one the recipient forwarded on comes
+/// out of what it received.
+fn net_payment_amount(payment: &TransferEvent, fee: Option<&TransferEvent>) -> BigInt {
+    let amount = parse_amount(&payment.amount);
+    match fee.filter(|f| f.from == payment.to) {
+        Some(f) => amount - parse_amount(&f.amount),
+        None => amount,
+    }
+}
+
+/// The block's transactions that succeeded. Logs of reverted or failed
+/// transactions never took effect, so they are not settlements.
+fn succeeded_traces(blk: &eth::Block) -> impl Iterator<Item = &eth::TransactionTrace> {
+    blk.transaction_traces
+        .iter()
+        .filter(|trx| trx.status() == eth::TransactionTraceStatus::Succeeded)
+}
+
+/// The known multicall aggregator a transaction went through, if any: either
+/// the tx is sent straight to it or one of its calls is made to it.
+fn multicall_aggregator(trx: &eth::TransactionTrace) -> Option<&[u8]> {
+    let is_aggregator = |addr: &[u8]| MULTICALL_ADDRESSES.iter().any(|m| m.as_slice() == addr);
+    if is_aggregator(&trx.to) {
+        return Some(&trx.to);
+    }
+    trx.calls
+        .iter()
+        .map(|call| call.address.as_slice())
+        .find(|addr| is_aggregator(addr))
+}
+
+/// Parse the `extra_proxy_sigs` param: comma-separated `0x{topic0}:{type}`
+/// entries registering additional proxy event signatures, where type is
+/// `settled` or `settled_with_permit`. Malformed entries are ignored.
+fn parse_proxy_sigs(value: &str) -> Vec<(Vec<u8>, String)> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (sig, kind) = entry.trim().split_once(':')?;
+            if kind != "settled" && kind != "settled_with_permit" {
+                return None;
+            }
+            let topic = Hex::decode(sig.trim_start_matches("0x")).ok()?;
+            if topic.len() != 32 {
+                return None;
+            }
+            Some((topic, kind.to_string()))
+        })
+        .collect()
+}
+
+/// Parse the `proxy_addresses` param: comma-separated 20-byte hex addresses
+/// of the x402 proxies to watch, replacing the built-in ones. Unset, empty,
+/// or containing anything that is not an address, it falls back to
+/// `PROXY_ADDRESSES`.
+fn parse_proxy_addresses(value: Option<&str>) -> Vec<[u8; 20]> {
+    let parsed: Option<Vec<[u8; 20]>> = value
+        .filter(|v| !v.trim().is_empty())
+        .and_then(|v| {
+            v.split(',')
+                .map(|a| Hex::decode(a.trim().trim_start_matches("0x")).ok()?.try_into().ok())
+                .collect()
+        });
+    parsed.unwrap_or_else(|| PROXY_ADDRESSES.to_vec())
+}
+
+/// Whether `address` is one of the watched x402 `proxies`
+fn is_proxy(proxies: &[[u8; 20]], address: &[u8]) -> bool {
+    proxies.iter().any(|p| p.as_slice() == address)
+}
+
+/// Classify a proxy log as `settled` / `settled_with_permit`, checking the
+/// built-in signatures first and then any registered via `extra_proxy_sigs`.
+fn classify_proxy_log(log: &eth::Log, extra_sigs: &[(Vec<u8>, String)]) -> Option<String> {
+    if is_settled_with_permit_event(log) {
+        return Some("settled_with_permit".to_string());
+    }
+    if is_settled_event(log) {
+        return Some("settled".to_string());
+    }
+    let topic0 = log.topics.first()?;
+    extra_sigs
+        .iter()
+        .find(|(sig, _)| sig == topic0)
+        .map(|(_, kind)| kind.clone())
+}
+
+/// Split USDC transfers into (payments, proxy fundings). Transfers whose
+/// recipient is an x402 proxy pre-fund the proxy rather than pay anyone, so
+/// they are kept out of payment correlation.
+fn split_proxy_funding(
+    transfers: Vec<TransferEvent>,
+    proxies: &[[u8; 20]],
+) -> (Vec<TransferEvent>, Vec<TransferEvent>) {
+    transfers.into_iter().partition(|t| !is_proxy(proxies, &t.to))
+}
+
+/// The token contract that emitted `transfer`, found by its log index
+fn transfer_token(logs: &[eth::Log], transfer: &TransferEvent) -> String {
+    logs.iter()
+        .find(|log| log.index == transfer.log_index)
+        .map(|log| format_address(&log.address))
+        .unwrap_or_else(|| format_address(&USDC))
+}
+
+/// A proxy event's payment transfer and, if any, its fee transfer
+type ProxyTransferPair<'a> = (Option<&'a TransferEvent>, Option<&'a TransferEvent>);
+
+/// Pair each proxy event, given by log index, with its payment and fee
+/// transfers. Transfers to the facilitator are fee candidates; the rest are
+/// payments. Each event takes the nearest unused payment by log index, the
+/// preceding one on a tie since the proxy moves the tokens before emitting
+/// `Settled`. Each payment then takes the first unused fee sent by its payer
+/// or forwarded by its recipient. When every transfer goes to the
+/// facilitator they are all payments.
+fn pair_proxy_transfers<'a>(
+    transfers: &'a [TransferEvent],
+    facilitator: &[u8],
+    events: &[u32],
+) -> Vec<ProxyTransferPair<'a>> {
+    let (mut payments, mut fees): (Vec<&TransferEvent>, Vec<&TransferEvent>) =
+        transfers.iter().partition(|t| t.to != facilitator);
+    if payments.is_empty() {
+        payments = std::mem::take(&mut fees);
+    }
+
+    let mut payment_used = vec![false; payments.len()];
+    let mut fee_used = vec![false; fees.len()];
+    events
+        .iter()
+        .map(|&event| {
+            let payment = (0..payments.len())
+                .filter(|&j| !payment_used[j])
+                .min_by_key(|&j| {
+                    let index = payments[j].log_index;
+                    (index.abs_diff(event), index > event)
+                })
+                .map(|j| {
+                    payment_used[j] = true;
+                    payments[j]
+                });
+            let fee = payment.and_then(|p| {
+                let j = (0..fees.len()).find(|&j| {
+                    !fee_used[j] && (fees[j].from == p.from || fees[j].from == p.to)
+                })?;
+                fee_used[j] = true;
+                Some(fees[j])
+            });
+            (payment, fee)
+        })
+        .collect()
+}
+
+/// Whether a settlement found no payment to pair with (no recipient or zero
+/// amount). Unmatched settlements add nothing to volume.
+fn is_unmatched(s: &x402::Settlement) -> bool {
+    s.recipient.is_empty() || s.amount.is_empty() || s.amount == "0"
+}
+
+/// A stat map's count: matched settlements, plus unmatched ones when the
+/// `count_unmatched` param is set
+fn stat_count(matched: i64, unmatched: i64, count_unmatched: bool) -> u64 {
+    let count = if count_unmatched { matched + unmatched } else { matched };
+    count.max(0) as u64
+}
+
+/// Protocol version of the proxy at `address`, "unknown" if unmapped
+fn proxy_protocol_version(address: &[u8]) -> &'static str {
+    PROXY_VERSIONS
+        .iter()
+        .find(|(proxy, _)| proxy == address)
+        .map(|(_, version)| *version)
+        .unwrap_or("unknown")
+}
+
+/// A native ETH value transfer paid out by an x402 proxy
+struct NativePayment {
+    payer: Vec<u8>,
+    recipient: Vec<u8>,
+    /// Wei
+    amount: String,
+}
+
+/// Native ETH payments made by an x402 proxy in this transaction: internal
+/// calls from a proxy that carry value, in call order. The payer is the
+/// transaction sender, who supplied the value.
+fn native_proxy_payments(
+    trx: &eth::TransactionTrace,
+    proxies: &[[u8; 20]],
+) -> Vec<NativePayment> {
+    trx.calls
+        .iter()
+        .filter(|call| !call.state_reverted)
+        .filter(|call| is_proxy(proxies, &call.caller))
+        .filter_map(|call| {
+            let value = call.value.as_ref().filter(|v| v.bytes.iter().any(|b| *b != 0))?;
+            Some(NativePayment {
+                payer: trx.from.clone(),
+                recipient: call.address.clone(),
+                amount: proto_bigint_to_string(value),
+            })
+        })
+        .collect()
+}
+
+/// The block's header timestamp, or None when the header or timestamp is
+/// absent or zero. `eth::Block::timestamp()` unwraps and would panic instead.
+fn block_timestamp(blk: &eth::Block) -> Option<prost_types::Timestamp> {
+    blk.header
+        .as_ref()
+        .and_then(|h| h.timestamp)
+        .filter(|t| t.seconds > 0)
+}
+
+/// The block's EIP-1559 base fee per gas in wei, "0" for a header without one
+fn block_base_fee(blk: &eth::Block) -> String {
+    blk.header
+        .as_ref()
+        .and_then(|h| h.base_fee_per_gas.as_ref())
+        .map(proto_bigint_to_string)
+        .unwrap_or_else(|| "0".to_string())
+}
+
+/// Priority fee per gas in wei: the effective gas price above the base fee,
+/// floored at 0. Base's L1 data fee is not part of either.
+fn priority_fee(gas_price: &str, base_fee: &str) -> String {
+    let tip = parse_amount(gas_price) - parse_amount(base_fee);
+    let tip = if tip > BigInt::zero() { tip } else { BigInt::zero() };
+    tip.to_string()
+}
+
 /// Extract x402 settlements by detecting EIP-3009 AuthorizationUsed events
 /// on the USDC contract. EIP-3009 settlements are gated by the FacilitatorRegistry.
 ///
@@ -187,110 +892,221 @@ fn store_facilitator_registry(
 /// event to capture payer, recipient, and amount.
 ///
 /// Also detects Permit2 proxy settlements (Settled / SettledWithPermit) from
-/// the x402ExactPermit2Proxy contract for the newer settlement path.
+/// the x402ExactPermit2Proxy contract for the newer settlement path. Signatures
+/// of newer proxy versions can be registered with the `extra_proxy_sigs` param.
+///
+/// transferWithAuthorization calls with no matching AuthorizationUsed log are
+/// settled from their calldata, flagged `from_calldata`.
+///
+/// A block without a timestamp still yields its settlements, flagged
+/// `timestamp_missing` with no timestamp; order them by `global_seq`.
 #[substreams::handlers::map]
 fn map_x402_settlements(
+    params: String,
     blk: eth::Block,
     registry_store: StoreGetString,
+    price_store: StoreGetBigInt,
 ) -> Result<x402::Settlements, substreams::errors::Error> {
+    let block_ts = block_timestamp(&blk);
+    let timestamp_missing = block_ts.is_none();
+    let base_fee = block_base_fee(&blk);
     let mut settlements = x402::Settlements {
         block_number: blk.number,
-        block_timestamp: Some(blk.timestamp().clone()),
+        block_timestamp: block_ts,
         ..Default::default()
     };
+    let extra_sigs = param_value(&params, "extra_proxy_sigs")
+        .map(parse_proxy_sigs)
+        .unwrap_or_default();
+    let proxies = parse_proxy_addresses(param_value(&params, "proxy_addresses"));
+    let mut next_unlogged_index = block_log_end(&blk);
 
-    for trx in blk.transaction_traces.iter() {
+    for trx in succeeded_traces(&blk) {
         let receipt = match trx.receipt.as_ref() {
             Some(r) => r,
             None => continue,
         };
 
+        // Canceled authorizations: aborted payments, never paired with a
+        // Transfer. Recorded whoever sent the tx, as the payer cancels.
+        for (token, canceled) in canceled_authorizations(&receipt.logs) {
+            settlements.canceled_authorizations.push(x402::CanceledAuthorization {
+                id: format!("{}-{}", Hex(&trx.hash).to_string(), canceled.log_index),
+                tx_hash: Hex(&trx.hash).to_string(),
+                log_index: canceled.log_index,
+                block_number: blk.number,
+                token: format_address(token),
+                authorizer: format_address(&canceled.authorizer),
+                nonce: Hex(&canceled.nonce).to_string(),
+            });
+        }
+
         // -----------------------------------------------
-        // Path 1: EIP-3009 AuthorizationUsed on USDC
-        // Facilitator calls transferWithAuthorization on USDC.
-        // USDC emits AuthorizationUsed + Transfer events.
+        // Path 1: EIP-3009 AuthorizationUsed on a settlement token
+        // Facilitator calls transferWithAuthorization on the token.
+        // The token emits AuthorizationUsed + Transfer events, grouped
+        // per token so pairing never crosses token contracts.
         // -----------------------------------------------
-        let auth_events: Vec<_> = receipt
-            .logs
-            .iter()
-            .filter(|log| log.address == USDC)
-            .filter_map(|log| decode_authorization_used(log))
-            .collect();
+        let token_groups = group_token_logs(&receipt.logs, SETTLEMENT_TOKENS);
+        let unlogged_transfers = unlogged_authorization_transfers(trx, &token_groups);
+        let multicall_address = multicall_aggregator(trx)
+            .map(format_address)
+            .unwrap_or_default();
 
-        if !auth_events.is_empty() {
+        if !token_groups.is_empty() || !unlogged_transfers.is_empty() {
             // Gate: only process EIP-3009 if tx.from is a registered facilitator
             let facilitator_addr = format_address(&trx.from).to_lowercase();
             if registry_store.get_last(&facilitator_addr).is_none() {
                 continue; // Not a registered facilitator, skip
             }
 
-            // Collect Transfer events from USDC in this transaction
-            let transfer_events: Vec<_> = receipt
-                .logs
-                .iter()
-                .filter(|log| log.address == USDC)
-                .filter_map(|log| decode_erc20_transfer(log))
-                .collect();
-
             let facilitator = format_address(&trx.from);
             let gas_used = trx.gas_used.to_string();
             let gas_price = trx
                 .gas_price
                 .as_ref()
-                .map(|p| proto_bigint_to_string(p))
+                .map(proto_bigint_to_string)
                 .unwrap_or_else(|| "0".to_string());
+            let priority_fee = priority_fee(&gas_price, &base_fee);
+
+            // Decode the authorization calls made to the tokens for their validity window
+            let auth_calls: Vec<_> = trx
+                .calls
+                .iter()
+                .filter(|call| !call.state_reverted)
+                .filter(|call| SETTLEMENT_TOKENS.iter().any(|t| call.address == t))
+                .filter_map(|call| {
+                    Some((call.address.clone(), decode_authorization_call(&call.input)?))
+                })
+                .collect();
 
             // Check if this tx also has proxy events (hybrid detection)
             let has_proxy_settled = receipt.logs.iter().any(|log| {
-                (log.address == X402_PROXY || log.address == X402_UPTO_PROXY)
-                    && (is_settled_event(log) || is_settled_with_permit_event(log))
+                is_proxy(&proxies, &log.address) && classify_proxy_log(log, &extra_sigs).is_some()
             });
 
-            for auth in &auth_events {
-                // Find the corresponding Transfer event for this authorization.
-                // In USDC's implementation, transferWithAuthorization emits
-                // AuthorizationUsed then Transfer, so we look for a Transfer
-                // where from == authorizer with log_index > auth.log_index.
-                let transfer = transfer_events
-                    .iter()
-                    .filter(|t| t.from == auth.authorizer && t.log_index > auth.log_index)
-                    .min_by_key(|t| t.log_index);
-
-                let (payer, recipient, amount) = if let Some(t) = transfer {
-                    (
-                        format_address(&auth.authorizer),
-                        format_address(&t.to),
-                        t.amount.clone(),
-                    )
-                } else {
-                    // AuthorizationUsed without a matching Transfer (shouldn't happen
-                    // in normal USDC operation, but handle gracefully)
-                    (format_address(&auth.authorizer), String::new(), "0".to_string())
-                };
+            for group in &token_groups {
+                let transfer_index = TransferIndex::new(&group.transfers, &trx.from);
+                // Find the corresponding Transfer event for each authorization,
+                // plus any fee transfer the facilitator took alongside it.
+                let pairs = pair_group_transfers(&group.auths, &transfer_index);
+                for (auth, (transfer, fee)) in group.auths.iter().zip(pairs) {
 
-                let settlement_type = if has_proxy_settled {
-                    "eip3009_proxy".to_string()
-                } else {
-                    "eip3009".to_string()
-                };
+                    let (payer, recipient, amount) = if let Some(t) = transfer {
+                        (
+                            format_address(&auth.authorizer),
+                            format_address(&t.to),
+                            net_payment_amount(t, fee).to_string(),
+                        )
+                    } else {
+                        // AuthorizationUsed without a matching Transfer (shouldn't happen
+                        // in normal EIP-3009 operation, but handle gracefully)
+                        (format_address(&auth.authorizer), String::new(), "0".to_string())
+                    };
 
-                let nonce = Hex(&auth.nonce).to_string();
+                    let (fee_amount, fee_recipient) = fee
+                        .map(|f| (f.amount.clone(), format_address(&f.to)))
+                        .unwrap_or_else(|| ("0".to_string(), String::new()));
 
+                    let settlement_type = if has_proxy_settled {
+                        "eip3009_proxy".to_string()
+                    } else {
+                        "eip3009".to_string()
+                    };
+
+                    let nonce = Hex(&auth.nonce).to_string();
+
+                    // valid_after / valid_before from the call that used this nonce;
+                    // 0 when the calldata isn't available
+                    let (valid_after, valid_before) = auth_calls
+                        .iter()
+                        .find(|(token, c)| {
+                            *token == group.token && c.nonce == auth.nonce && c.from == auth.authorizer
+                        })
+                        .map(|(_, c)| (c.valid_after, c.valid_before))
+                        .unwrap_or((0, 0));
+
+                    let id = format!("{}-{}", Hex(&trx.hash).to_string(), auth.log_index);
+                    settlements.settlements.push(x402::Settlement {
+                        uuid: settlement_uuid(&id),
+                        id,
+                        tx_hash: Hex(&trx.hash).to_string(),
+                        log_index: auth.log_index,
+                        block_number: blk.number,
+                        timestamp: block_ts,
+                        payer,
+                        recipient,
+                        token: format_address(&group.token),
+                        amount,
+                        settlement_type,
+                        facilitator: facilitator.clone(),
+                        gas_used: gas_used.clone(),
+                        gas_price: gas_price.clone(),
+                        nonce,
+                        fee_amount,
+                        fee_recipient,
+                        global_seq: global_seq(blk.number, auth.block_index),
+                        timestamp_missing,
+                        valid_after,
+                        valid_before,
+                        facilitator_rank: 0,
+                        protocol_version: String::new(),
+                        infra: false,
+                        via_multicall: !multicall_address.is_empty(),
+                        multicall_address: multicall_address.clone(),
+                        base_fee_per_gas: base_fee.clone(),
+                        priority_fee: priority_fee.clone(),
+                        from_calldata: false,
+                        is_self_payment: false,
+                        gas_cost_usd: String::new(),
+                    });
+                }
+            }
+
+            // Fallback: authorizations seen only in calldata. There is no log
+            // to place them, so each gets its own position past the logs.
+            let positions = unlogged_positions(
+                &receipt.logs,
+                unlogged_transfers.len(),
+                &mut next_unlogged_index,
+            );
+            for ((token, call_index, call), (log_index, block_index)) in
+                unlogged_transfers.iter().zip(positions)
+            {
+                let settlement_type = if has_proxy_settled { "eip3009_proxy" } else { "eip3009" };
+                let id = format!("{}-call{}", Hex(&trx.hash).to_string(), call_index);
                 settlements.settlements.push(x402::Settlement {
-                    id: format!("{}-{}", Hex(&trx.hash).to_string(), auth.log_index),
+                    uuid: settlement_uuid(&id),
+                    id,
                     tx_hash: Hex(&trx.hash).to_string(),
-                    log_index: auth.log_index,
+                    log_index,
                     block_number: blk.number,
-                    timestamp: Some(blk.timestamp().clone()),
-                    payer,
-                    recipient,
-                    token: format_address(&USDC),
-                    amount,
-                    settlement_type,
+                    timestamp: block_ts,
+                    payer: format_address(&call.from),
+                    recipient: format_address(&call.to),
+                    token: format_address(token),
+                    amount: call.value.clone(),
+                    settlement_type: settlement_type.to_string(),
                     facilitator: facilitator.clone(),
                     gas_used: gas_used.clone(),
                     gas_price: gas_price.clone(),
-                    nonce,
+                    nonce: Hex(&call.nonce).to_string(),
+                    fee_amount: "0".to_string(),
+                    fee_recipient: String::new(),
+                    global_seq: global_seq(blk.number, block_index),
+                    timestamp_missing,
+                    valid_after: call.valid_after,
+                    valid_before: call.valid_before,
+                    facilitator_rank: 0,
+                    protocol_version: String::new(),
+                    infra: false,
+                    via_multicall: !multicall_address.is_empty(),
+                    multicall_address: multicall_address.clone(),
+                    base_fee_per_gas: base_fee.clone(),
+                    priority_fee: priority_fee.clone(),
+                    from_calldata: true,
+                    is_self_payment: false,
+                    gas_cost_usd: String::new(),
                 });
             }
 
@@ -301,412 +1117,7447 @@ fn map_x402_settlements(
         // Path 2: Permit2 proxy (Settled / SettledWithPermit)
         // x402ExactPermit2Proxy emits parameterless Settled() or
         // SettledWithPermit() events. We correlate each with its
-        // corresponding USDC Transfer event in the same tx.
+        // corresponding settlement token Transfer event in the same tx.
         // -----------------------------------------------
         let proxy_events: Vec<_> = receipt
             .logs
             .iter()
-            .filter(|log| {
-                (log.address == X402_PROXY || log.address == X402_UPTO_PROXY)
-                    && (is_settled_event(log) || is_settled_with_permit_event(log))
-            })
+            .filter(|log| is_proxy(&proxies, &log.address))
+            .filter_map(|log| classify_proxy_log(log, &extra_sigs).map(|kind| (log, kind)))
             .collect();
 
+        // Collect settlement token transfers for correlation, setting aside
+        // transfers into the proxy itself (pre-funding), which are not payments
+        let (token_transfers, fundings) = split_proxy_funding(
+            receipt
+                .logs
+                .iter()
+                .filter(|log| SETTLEMENT_TOKENS.iter().any(|t| log.address == t))
+                .filter_map(decode_erc20_transfer)
+                .collect(),
+            &proxies,
+        );
+
+        for f in &fundings {
+            settlements.proxy_fundings.push(x402::ProxyFunding {
+                id: format!("{}-{}", Hex(&trx.hash).to_string(), f.log_index),
+                tx_hash: Hex(&trx.hash).to_string(),
+                log_index: f.log_index,
+                block_number: blk.number,
+                from: format_address(&f.from),
+                proxy: format_address(&f.to),
+                amount: f.amount.clone(),
+            });
+        }
+
         if proxy_events.is_empty() {
             continue;
         }
 
-        // Collect USDC transfers for correlation
-        let usdc_transfers: Vec<_> = receipt
-            .logs
-            .iter()
-            .filter(|log| log.address == USDC)
-            .filter_map(|log| decode_erc20_transfer(log))
-            .collect();
-
         let facilitator = format_address(&trx.from);
         let gas_used = trx.gas_used.to_string();
         let gas_price = trx
             .gas_price
             .as_ref()
-            .map(|p| proto_bigint_to_string(p))
+            .map(proto_bigint_to_string)
             .unwrap_or_else(|| "0".to_string());
+        let priority_fee = priority_fee(&gas_price, &base_fee);
 
-        for (i, proxy_log) in proxy_events.iter().enumerate() {
-            let settlement_type = if is_settled_with_permit_event(proxy_log) {
-                "settled_with_permit".to_string()
-            } else {
-                "settled".to_string()
+        // No settlement token moved: the proxy may have settled in native ETH
+        let native_payments = if token_transfers.is_empty() {
+            native_proxy_payments(trx, &proxies)
+        } else {
+            Vec::new()
+        };
+
+        let event_indices: Vec<u32> = proxy_events.iter().map(|(log, _)| log.index).collect();
+        let transfer_pairs = pair_proxy_transfers(&token_transfers, &trx.from, &event_indices);
+
+        for (i, (proxy_log, settlement_type)) in proxy_events.into_iter().enumerate() {
+            // Each proxy event's nearest token payment and its fee, or failing
+            // those its native value transfer by position
+            let (transfer, fee) = transfer_pairs[i];
+            let (payer, recipient, amount, token) = match transfer {
+                Some(t) => {
+                    (
+                        format_address(&t.from),
+                        format_address(&t.to),
+                        net_payment_amount(t, fee).to_string(),
+                        transfer_token(&receipt.logs, t),
+                    )
+                }
+                None => match native_payments.get(i) {
+                    Some(p) => (
+                        format_address(&p.payer),
+                        format_address(&p.recipient),
+                        p.amount.clone(),
+                        NATIVE_TOKEN.to_string(),
+                    ),
+                    None => (
+                        facilitator.clone(),
+                        String::new(),
+                        "0".to_string(),
+                        format_address(&USDC),
+                    ),
+                },
             };
 
-            // Pair each proxy event with its corresponding USDC transfer by position
-            let (payer, recipient, amount) = usdc_transfers
-                .get(i)
-                .map(|t| (format_address(&t.from), format_address(&t.to), t.amount.clone()))
-                .unwrap_or_else(|| (facilitator.clone(), String::new(), "0".to_string()));
+            let (fee_amount, fee_recipient) = fee
+                .map(|f| (f.amount.clone(), format_address(&f.to)))
+                .unwrap_or_else(|| ("0".to_string(), String::new()));
 
+            let id = format!("{}-{}", Hex(&trx.hash).to_string(), proxy_log.index);
             settlements.settlements.push(x402::Settlement {
-                id: format!("{}-{}", Hex(&trx.hash).to_string(), proxy_log.index),
+                uuid: settlement_uuid(&id),
+                id,
                 tx_hash: Hex(&trx.hash).to_string(),
                 log_index: proxy_log.index,
                 block_number: blk.number,
-                timestamp: Some(blk.timestamp().clone()),
+                timestamp: block_ts,
                 payer,
                 recipient,
-                token: format_address(&USDC),
+                token,
                 amount,
                 settlement_type,
                 facilitator: facilitator.clone(),
                 gas_used: gas_used.clone(),
                 gas_price: gas_price.clone(),
                 nonce: String::new(),
+                fee_amount,
+                fee_recipient,
+                global_seq: global_seq(blk.number, proxy_log.block_index),
+                timestamp_missing,
+                valid_after: 0,
+                valid_before: 0,
+                facilitator_rank: 0,
+                protocol_version: proxy_protocol_version(&proxy_log.address).to_string(),
+                infra: false,
+                via_multicall: !multicall_address.is_empty(),
+                multicall_address: multicall_address.clone(),
+                base_fee_per_gas: base_fee.clone(),
+                priority_fee: priority_fee.clone(),
+                from_calldata: false,
+                is_self_payment: false,
+                gas_cost_usd: String::new(),
             });
         }
     }
 
+    let infra = param_value(&params, "infra_addresses")
+        .map(parse_address_list)
+        .unwrap_or_default();
+    tag_infra(&mut settlements.settlements, &infra);
+    tag_self_payments(&mut settlements.settlements);
+    price_gas_usd(&mut settlements.settlements, price_store.get_last("latest").as_ref());
+
     Ok(settlements)
 }
 
-// =============================================
-// LAYER 2: State Stores
-// =============================================
+/// Default `max_block_keys`: distinct addresses a block may touch in the
+/// per-address stores. Far above any real block; guards pathological ones.
+const DEFAULT_MAX_BLOCK_KEYS: usize = 10_000;
 
-/// Accumulate total payment volume per payer
-#[substreams::handlers::store]
-fn store_payer_volume(settlements: x402::Settlements, store: StoreAddBigInt) {
-    for s in settlements.settlements {
-        if s.payer.is_empty() || s.payer == ZERO_ADDR {
-            continue;
-        }
-        let amount = BigInt::try_from(&s.amount).unwrap_or_else(|_| BigInt::zero());
-        store.add(0, &s.payer.to_lowercase(), &amount);
-    }
+/// Most processed blocks an overflowing block's settlements are spread over.
+/// The last chunk takes whatever is left, so nothing is dropped however far
+/// over the cap a block is.
+const MAX_DEFER_BLOCKS: u64 = 16;
+
+/// The `max_block_keys` param, shared by `store_deferred_settlements` and
+/// `map_capped_settlements`, which must agree on it
+fn max_block_keys(params: &str) -> usize {
+    param_value(params, "max_block_keys")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_BLOCK_KEYS)
 }
 
-/// Count total payments per payer
+/// Split settlements at the `max_block_keys` cap on distinct store keys
+/// (payer, recipient and facilitator addresses). Settlements are admitted in
+/// order while their keys fit; the first that would exceed the cap and
+/// everything after it are deferred, so block order is preserved. The first
+/// settlement is always admitted, so a chunk never comes back empty.
+fn cap_block_keys(
+    settlements: Vec<x402::Settlement>,
+    cap: usize,
+) -> (Vec<x402::Settlement>, Vec<x402::Settlement>) {
+    let mut keys = std::collections::HashSet::new();
+    let mut kept = Vec::new();
+    let mut deferred = Vec::new();
+    for s in settlements {
+        if deferred.is_empty() {
+            let new_keys: std::collections::HashSet<String> =
+                [&s.payer, &s.recipient, &s.facilitator]
+                    .into_iter()
+                    .filter(|a| !a.is_empty())
+                    .map(|a| a.to_lowercase())
+                    .filter(|a| !keys.contains(a))
+                    .collect();
+            if kept.is_empty() || keys.len() + new_keys.len() <= cap {
+                keys.extend(new_keys);
+                kept.push(s);
+                continue;
+            }
+        }
+        deferred.push(s);
+    }
+    (kept, deferred)
+}
+
+/// `store_deferred_settlements` key of the chunk applied at processed block
+/// `seq`, deferred `lag` processed blocks
+fn deferred_key(seq: i64, lag: u64) -> String {
+    format!("{}:{}", seq, lag)
+}
+
+/// Spread a block's overflow over the next processed blocks: chunk `lag`
+/// (from 1) fits the cap and is applied `lag` blocks after `seq`, the
+/// overflowing block's processed-block number. Chunk `MAX_DEFER_BLOCKS` takes
+/// the rest. Keys: see `deferred_key`
+fn defer_schedule(
+    seq: i64,
+    overflow: Vec<x402::Settlement>,
+    cap: usize,
+) -> Vec<(String, Vec<x402::Settlement>)> {
+    let mut schedule = Vec::new();
+    let mut rest = overflow;
+    for lag in 1..=MAX_DEFER_BLOCKS {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, next) = if lag == MAX_DEFER_BLOCKS {
+            (std::mem::take(&mut rest), vec![])
+        } else {
+            cap_block_keys(rest, cap)
+        };
+        schedule.push((deferred_key(seq + lag as i64, lag), chunk));
+        rest = next;
+    }
+    schedule
+}
+
+/// Operator warning for a block whose settlements overflowed the key cap
+fn cap_warning(block_number: u64, deferred: usize, cap: usize) -> Option<String> {
+    (deferred > 0).then(|| {
+        format!(
+            "block {}: {} settlements deferred to later blocks, over max_block_keys={}",
+            block_number, deferred, cap
+        )
+    })
+}
+
+/// Count the blocks this pipeline processes. Deferred settlements are
+/// scheduled in processed blocks rather than block numbers, since the block
+/// filter skips blocks without settlement events. Key: seq
+#[substreams::handlers::store]
+fn store_block_seq(_settlements: x402::Settlements, store: StoreAddInt64) {
+    store.add(0, "seq", 1);
+}
+
+/// Hold the settlements of a block over the `max_block_keys` cap (param)
+/// until the processed blocks they're scheduled for (see `defer_schedule`).
+/// Keys are cleared the block after they fall due. Keys: {seq}:{lag}
+#[substreams::handlers::store]
+fn store_deferred_settlements(
+    params: String,
+    settlements: x402::Settlements,
+    seq_store: StoreGetInt64,
+    store: StoreSetProto<x402::Settlements>,
+) {
+    let seq = seq_store.get_last("seq").unwrap_or(0);
+    // The previous processed block already applied what fell due in it
+    store.delete_prefix(0, &format!("{}:", seq - 1));
+    let cap = max_block_keys(&params);
+    let (_, overflow) = cap_block_keys(settlements.settlements, cap);
+    for (key, chunk) in defer_schedule(seq, overflow, cap) {
+        let held = x402::Settlements {
+            settlements: chunk,
+            ..Default::default()
+        };
+        store.set(0, key, &held);
+    }
+}
+
+/// The settlements the stores apply this block: those deferred from earlier
+/// blocks that fall due now, oldest first, then this block's own up to the
+/// `max_block_keys` cap (param, default 10,000). The rest are logged, carried
+/// in `deferred_settlements` and applied in later blocks, so no settlement is
+/// dropped. A deferred settlement keeps its own block number and timestamp,
+/// but stores keyed by the block's time count it in the block that applies it.
+#[substreams::handlers::map]
+fn map_capped_settlements(
+    params: String,
+    settlements: x402::Settlements,
+    seq_store: StoreGetInt64,
+    deferred_store: StoreGetProto<x402::Settlements>,
+) -> Result<x402::Settlements, substreams::errors::Error> {
+    let mut settlements = settlements;
+    let seq = seq_store.get_last("seq").unwrap_or(0);
+    let cap = max_block_keys(&params);
+    let (kept, deferred) = cap_block_keys(std::mem::take(&mut settlements.settlements), cap);
+    if let Some(warning) = cap_warning(settlements.block_number, deferred.len(), cap) {
+        substreams::log::info!("warning: {}", warning);
+    }
+    settlements.settlements = (1..=MAX_DEFER_BLOCKS)
+        .rev()
+        .filter_map(|lag| deferred_store.get_last(deferred_key(seq, lag)))
+        .flat_map(|held| held.settlements)
+        .chain(kept)
+        .collect();
+    settlements.deferred_settlements = deferred;
+    Ok(settlements)
+}
+
+/// Mark settlements paying an infrastructure address (treasury, protocol
+/// sweeps) rather than a merchant
+fn tag_infra(settlements: &mut [x402::Settlement], infra: &std::collections::HashSet<String>) {
+    for s in settlements.iter_mut() {
+        s.infra = infra.contains(&s.recipient.to_lowercase());
+    }
+}
+
+/// Flag wash / self transfers, where the payer pays itself
+fn tag_self_payments(settlements: &mut [x402::Settlement]) {
+    for s in settlements.iter_mut() {
+        s.is_self_payment = !s.payer.is_empty() && s.payer.eq_ignore_ascii_case(&s.recipient);
+    }
+}
+
+/// Price each settlement's transaction gas in USD at the ETH/USD answer as of
+/// this block: one updated in the block, else the last known. Left empty
+/// before any answer is known rather than reported as free.
+fn price_gas_usd(settlements: &mut [x402::Settlement], eth_usd_price: Option<&BigInt>) {
+    let Some(price) = eth_usd_price else {
+        return;
+    };
+    for s in settlements.iter_mut() {
+        let gas_cost_wei = parse_amount(&s.gas_used) * parse_amount(&s.gas_price);
+        s.gas_cost_usd = gas_cost_usd(&gas_cost_wei, price).to_string();
+    }
+}
+
+/// Whether a settlement's amount is in token atomic units. Native ETH
+/// settlements are in wei, so the volume aggregates leave them out rather
+/// than sum them alongside 6-decimal tokens.
+fn is_token_amount(s: &x402::Settlement) -> bool {
+    s.token != NATIVE_TOKEN
+}
+
+/// Whether a settlement adds to volume: self-payments are left out when the
+/// `exclude_self` param is set
+fn counts_toward_volume(s: &x402::Settlement, exclude_self: bool) -> bool {
+    !(exclude_self && s.is_self_payment)
+}
+
+/// Whether a settlement counts toward merchant revenue: infra settlements are
+/// left out when the `exclude_infra` param is set
+fn is_merchant_revenue(s: &x402::Settlement, exclude_infra: bool) -> bool {
+    !(exclude_infra && s.infra)
+}
+
+// =============================================
+// LAYER 2: State Stores
+// =============================================
+
+/// Whether a settlement adds to its payer's volume. Shared by
+/// `store_payer_volume` and `store_payer_volume_count`, so `avg_payment`
+/// divides a volume by the count of the same settlements.
+fn counts_toward_payer_volume(s: &x402::Settlement, exclude_self: bool) -> bool {
+    !s.payer.is_empty()
+        && s.payer != ZERO_ADDR
+        && !is_unmatched(s)
+        && counts_toward_volume(s, exclude_self)
+        && is_token_amount(s)
+}
+
+/// Whether a settlement adds to its recipient's volume, shared by
+/// `store_recipient_volume` and `store_recipient_volume_count`
+fn counts_toward_recipient_volume(
+    s: &x402::Settlement,
+    exclude_infra: bool,
+    exclude_self: bool,
+) -> bool {
+    !s.recipient.is_empty()
+        && s.recipient != ZERO_ADDR
+        && !is_unmatched(s)
+        && is_merchant_revenue(s, exclude_infra)
+        && counts_toward_volume(s, exclude_self)
+        && is_token_amount(s)
+}
+
+/// Accumulate total payment volume per payer
+#[substreams::handlers::store]
+fn store_payer_volume(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    for s in settlements.settlements {
+        if !counts_toward_payer_volume(&s, exclude_self) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        store.add(0, s.payer.to_lowercase(), &amount);
+    }
+}
+
+/// Count the payments behind each payer's volume, for `avg_payment`. Takes
+/// the same params as `store_payer_volume`. Key: {payer_address}
+#[substreams::handlers::store]
+fn store_payer_volume_count(params: String, settlements: x402::Settlements, store: StoreAddInt64) {
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    for s in settlements.settlements {
+        if counts_toward_payer_volume(&s, exclude_self) {
+            store.add(0, s.payer.to_lowercase(), 1);
+        }
+    }
+}
+
+/// Count matched payments per payer (see `store_unmatched_count`)
 #[substreams::handlers::store]
 fn store_payer_count(settlements: x402::Settlements, store: StoreAddInt64) {
     for s in settlements.settlements {
-        if s.payer.is_empty() || s.payer == ZERO_ADDR {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR || is_unmatched(&s) {
             continue;
         }
-        store.add(0, &s.payer.to_lowercase(), 1);
+        store.add(0, s.payer.to_lowercase(), 1);
     }
 }
 
 /// Accumulate total revenue per recipient (resource server)
 #[substreams::handlers::store]
-fn store_recipient_volume(settlements: x402::Settlements, store: StoreAddBigInt) {
+fn store_recipient_volume(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let exclude_infra = param_value(&params, "exclude_infra") == Some("true");
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
     for s in settlements.settlements {
-        if s.recipient.is_empty() || s.recipient == ZERO_ADDR {
+        if !counts_toward_recipient_volume(&s, exclude_infra, exclude_self) {
             continue;
         }
-        let amount = BigInt::try_from(&s.amount).unwrap_or_else(|_| BigInt::zero());
-        store.add(0, &s.recipient.to_lowercase(), &amount);
+        let amount = parse_amount(&s.amount);
+        store.add(0, s.recipient.to_lowercase(), &amount);
+    }
+}
+
+/// Count the payments behind each recipient's volume, for `avg_payment`.
+/// Takes the same params as `store_recipient_volume`. Key: {recipient_address}
+#[substreams::handlers::store]
+fn store_recipient_volume_count(
+    params: String,
+    settlements: x402::Settlements,
+    store: StoreAddInt64,
+) {
+    let exclude_infra = param_value(&params, "exclude_infra") == Some("true");
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    for s in settlements.settlements {
+        if counts_toward_recipient_volume(&s, exclude_infra, exclude_self) {
+            store.add(0, s.recipient.to_lowercase(), 1);
+        }
+    }
+}
+
+/// Signed changes to each address's net flow for a block: + what it
+/// received, - what it paid, in order of first appearance
+fn net_flow_changes(settlements: &[x402::Settlement]) -> Vec<(String, BigInt)> {
+    let mut changes: Vec<(String, BigInt)> = Vec::new();
+    let mut add = |address: &str, amount: BigInt| {
+        if address.is_empty() || address == ZERO_ADDR {
+            return;
+        }
+        let address = address.to_lowercase();
+        match changes.iter_mut().find(|(a, _)| *a == address) {
+            Some((_, total)) => *total = total.clone() + amount,
+            None => changes.push((address, amount)),
+        }
+    };
+    for s in settlements.iter().filter(|s| is_token_amount(s)) {
+        let amount = parse_amount(&s.amount);
+        add(&s.recipient, amount.clone());
+        add(&s.payer, BigInt::zero() - amount);
+    }
+    changes
+}
+
+/// Accumulate each address's net flow: received minus paid, which goes
+/// negative for net payers. Key: {address}
+#[substreams::handlers::store]
+fn store_net_flow(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for (address, change) in net_flow_changes(&settlements.settlements) {
+        store.add(0, address, &change);
     }
 }
 
 /// Count total payments per recipient
 #[substreams::handlers::store]
-fn store_recipient_count(settlements: x402::Settlements, store: StoreAddInt64) {
+fn store_recipient_count(params: String, settlements: x402::Settlements, store: StoreAddInt64) {
+    let exclude_infra = param_value(&params, "exclude_infra") == Some("true");
     for s in settlements.settlements {
-        if s.recipient.is_empty() || s.recipient == ZERO_ADDR {
+        if s.recipient.is_empty() || s.recipient == ZERO_ADDR || is_unmatched(&s) {
+            continue;
+        }
+        if !is_merchant_revenue(&s, exclude_infra) {
             continue;
         }
-        store.add(0, &s.recipient.to_lowercase(), 1);
+        store.add(0, s.recipient.to_lowercase(), 1);
     }
 }
 
 /// Accumulate total volume settled per facilitator
 #[substreams::handlers::store]
-fn store_facilitator_volume(settlements: x402::Settlements, store: StoreAddBigInt) {
+fn store_facilitator_volume(
+    params: String,
+    settlements: x402::Settlements,
+    store: StoreAddBigInt,
+) {
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
     for s in settlements.settlements {
-        if s.facilitator.is_empty() {
+        if s.facilitator.is_empty() || !counts_toward_volume(&s, exclude_self) {
+            continue;
+        }
+        if !is_token_amount(&s) {
             continue;
         }
-        let amount = BigInt::try_from(&s.amount).unwrap_or_else(|_| BigInt::zero());
-        store.add(0, &s.facilitator.to_lowercase(), &amount);
+        let amount = parse_amount(&s.amount);
+        store.add(0, s.facilitator.to_lowercase(), &amount);
     }
 }
 
-/// Count total settlements per facilitator
+/// Count matched settlements per facilitator (see `store_unmatched_count`)
 #[substreams::handlers::store]
 fn store_facilitator_count(settlements: x402::Settlements, store: StoreAddInt64) {
     for s in settlements.settlements {
-        if s.facilitator.is_empty() {
+        if s.facilitator.is_empty() || is_unmatched(&s) {
             continue;
         }
-        store.add(0, &s.facilitator.to_lowercase(), 1);
+        store.add(0, s.facilitator.to_lowercase(), 1);
     }
 }
 
-/// Accumulate total gas cost per facilitator (gas_used * gas_price in wei)
+/// Whether a settlement type went through an EIP-3009 authorization
+fn is_eip3009_path(settlement_type: &str) -> bool {
+    matches!(settlement_type, "eip3009" | "eip3009_proxy")
+}
+
+/// Whether a settlement type went through the Permit2 proxies. The hybrid
+/// `eip3009_proxy` took both paths, so it counts toward both.
+fn is_proxy_path(settlement_type: &str) -> bool {
+    matches!(settlement_type, "eip3009_proxy" | "settled" | "settled_with_permit")
+}
+
+/// Count matched EIP-3009 settlements per facilitator, including the
+/// `eip3009_proxy` hybrid. Key: {facilitator_address}
 #[substreams::handlers::store]
-fn store_facilitator_gas(settlements: x402::Settlements, store: StoreAddBigInt) {
+fn store_facilitator_eip3009_count(settlements: x402::Settlements, store: StoreAddInt64) {
     for s in settlements.settlements {
-        if s.facilitator.is_empty() {
+        if s.facilitator.is_empty() || is_unmatched(&s) || !is_eip3009_path(&s.settlement_type) {
             continue;
         }
-        let gas_used = BigInt::try_from(&s.gas_used).unwrap_or_else(|_| BigInt::zero());
-        let gas_price = BigInt::try_from(&s.gas_price).unwrap_or_else(|_| BigInt::zero());
-        let gas_cost = gas_used * gas_price;
-        store.add(0, &s.facilitator.to_lowercase(), &gas_cost);
+        store.add(0, s.facilitator.to_lowercase(), 1);
     }
 }
 
-/// Record the first-seen block timestamp per payer, recipient, and facilitator.
-/// Uses set_if_not_exists so only the earliest timestamp is stored.
+/// Count matched Permit2 proxy settlements per facilitator, including the
+/// `eip3009_proxy` hybrid. Key: {facilitator_address}
 #[substreams::handlers::store]
-fn store_first_seen(settlements: x402::Settlements, store: StoreSetIfNotExistsInt64) {
-    let ts = settlements
-        .block_timestamp
-        .as_ref()
-        .map(|t| t.seconds)
-        .unwrap_or(0);
+fn store_facilitator_proxy_count(settlements: x402::Settlements, store: StoreAddInt64) {
     for s in settlements.settlements {
-        if !s.payer.is_empty() && s.payer != ZERO_ADDR {
-            store.set_if_not_exists(0, format!("payer:{}", s.payer.to_lowercase()), &ts);
+        if s.facilitator.is_empty() || is_unmatched(&s) || !is_proxy_path(&s.settlement_type) {
+            continue;
         }
-        if !s.recipient.is_empty() && s.recipient != ZERO_ADDR {
-            store.set_if_not_exists(0, format!("recipient:{}", s.recipient.to_lowercase()), &ts);
+        store.add(0, s.facilitator.to_lowercase(), 1);
+    }
+}
+
+/// A settlement's payer as the payer stores key it, if it has one
+fn payer_key(s: &x402::Settlement) -> Option<String> {
+    (!s.payer.is_empty() && s.payer != ZERO_ADDR).then(|| s.payer.to_lowercase())
+}
+
+/// A settlement's facilitator as the facilitator stores key it, if it has one
+fn facilitator_key(s: &x402::Settlement) -> Option<String> {
+    (!s.facilitator.is_empty()).then(|| s.facilitator.to_lowercase())
+}
+
+/// Count unmatched settlements, kept apart from the matched counts so stat
+/// maps can opt into them. Keys: payer:{address}, facilitator:{address}
+#[substreams::handlers::store]
+fn store_unmatched_count(settlements: x402::Settlements, store: StoreAddInt64) {
+    for s in settlements.settlements {
+        if !is_unmatched(&s) {
+            continue;
         }
-        if !s.facilitator.is_empty() {
-            store.set_if_not_exists(
-                0,
-                format!("facilitator:{}", s.facilitator.to_lowercase()),
-                &ts,
-            );
+        if let Some(payer) = payer_key(&s) {
+            store.add(0, format!("payer:{}", payer), 1);
+        }
+        if let Some(facilitator) = facilitator_key(&s) {
+            store.add(0, format!("facilitator:{}", facilitator), 1);
         }
     }
 }
 
-// =============================================
-// LAYER 3: Analytics
-// =============================================
+/// Whether a block precedes the `reset_at_block` param, so its cumulative
+/// values still belong to the baseline subtracted after the reset
+fn before_reset(params: &str, block_number: u64) -> bool {
+    param_value(params, "reset_at_block")
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_some_and(|reset_at| block_number < reset_at)
+}
 
-/// Compute aggregated payer statistics
-#[substreams::handlers::map]
-fn map_payer_stats(
-    settlements: x402::Settlements,
-    volume_deltas: Deltas<DeltaBigInt>,
-    count_store: StoreGetInt64,
-    first_seen_store: StoreGetInt64,
-) -> Result<x402::PayerStats, substreams::errors::Error> {
-    let mut stats = x402::PayerStats {
-        block_number: settlements.block_number,
-        ..Default::default()
-    };
+/// Cumulative value counted from the reset: the total minus the baseline
+/// recorded just before `reset_at_block` (zero without a reset)
+fn since_reset(total: &BigInt, baseline: Option<BigInt>) -> BigInt {
+    total.clone() - baseline.unwrap_or_else(BigInt::zero)
+}
 
-    for delta in volume_deltas.deltas {
-        let payer = delta.key.clone();
-        let total_payments = count_store.get_last(&payer).unwrap_or(0) as u64;
-        let first_payment_at = first_seen_store
-            .get_last(&format!("payer:{}", payer))
-            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
+/// Cumulative count counted from the reset, like `since_reset`; never negative
+fn count_since_reset(total: i64, baseline: Option<BigInt>) -> i64 {
+    u64::try_from(since_reset(&BigInt::from(total), baseline)).map_or(0, |count| count as i64)
+}
 
-        stats.stats.push(x402::PayerStat {
-            payer_address: payer,
-            total_spent: delta.new_value.to_string(),
-            total_payments,
-            first_payment_at,
-            last_payment_at: settlements.block_timestamp.clone(),
-        });
+/// Record cumulative volumes, counts and gas as they stand before
+/// `reset_at_block` (param), so stat maps can report totals starting fresh
+/// from that block without reprocessing from genesis. Empty without the param.
+/// Keys: payer:{address}, recipient:{address}, facilitator:{address} (volume);
+/// payer_count:, recipient_count:, facilitator_count:, unmatched:{store key},
+/// facilitator_gas:, facilitator_gas_usd:, facilitator_fees:,
+/// facilitator_eip3009:, facilitator_proxy:, payer_volume_count:,
+/// recipient_volume_count: (each followed by {address})
+#[substreams::handlers::store]
+fn store_stat_baselines(
+    params: String,
+    clock: Clock,
+    payer_deltas: Deltas<DeltaBigInt>,
+    recipient_deltas: Deltas<DeltaBigInt>,
+    facilitator_deltas: Deltas<DeltaBigInt>,
+    payer_count_deltas: Deltas<DeltaInt64>,
+    recipient_count_deltas: Deltas<DeltaInt64>,
+    facilitator_count_deltas: Deltas<DeltaInt64>,
+    unmatched_deltas: Deltas<DeltaInt64>,
+    facilitator_gas_deltas: Deltas<DeltaBigInt>,
+    facilitator_gas_usd_deltas: Deltas<DeltaBigInt>,
+    facilitator_fee_deltas: Deltas<DeltaBigInt>,
+    eip3009_count_deltas: Deltas<DeltaInt64>,
+    proxy_count_deltas: Deltas<DeltaInt64>,
+    payer_volume_count_deltas: Deltas<DeltaInt64>,
+    recipient_volume_count_deltas: Deltas<DeltaInt64>,
+    store: StoreSetBigInt,
+) {
+    if !before_reset(&params, clock.number) {
+        return;
+    }
+    for (kind, deltas) in [
+        ("payer", payer_deltas),
+        ("recipient", recipient_deltas),
+        ("facilitator", facilitator_deltas),
+        ("facilitator_gas", facilitator_gas_deltas),
+        ("facilitator_gas_usd", facilitator_gas_usd_deltas),
+        ("facilitator_fees", facilitator_fee_deltas),
+    ] {
+        for delta in deltas.deltas {
+            store.set(0, format!("{}:{}", kind, delta.key), &delta.new_value);
+        }
+    }
+    for (kind, deltas) in [
+        ("payer_count", payer_count_deltas),
+        ("recipient_count", recipient_count_deltas),
+        ("facilitator_count", facilitator_count_deltas),
+        ("unmatched", unmatched_deltas),
+        ("facilitator_eip3009", eip3009_count_deltas),
+        ("facilitator_proxy", proxy_count_deltas),
+        ("payer_volume_count", payer_volume_count_deltas),
+        ("recipient_volume_count", recipient_volume_count_deltas),
+    ] {
+        for delta in deltas.deltas {
+            store.set(0, format!("{}:{}", kind, delta.key), &BigInt::from(delta.new_value));
+        }
     }
-
-    Ok(stats)
 }
 
-/// Compute aggregated recipient (resource server) statistics
-#[substreams::handlers::map]
-fn map_recipient_stats(
-    settlements: x402::Settlements,
-    volume_deltas: Deltas<DeltaBigInt>,
-    count_store: StoreGetInt64,
-    first_seen_store: StoreGetInt64,
-) -> Result<x402::RecipientStats, substreams::errors::Error> {
-    let mut stats = x402::RecipientStats {
-        block_number: settlements.block_number,
-        ..Default::default()
-    };
-
+/// List every facilitator once, in order of first settlement, so maps can
+/// rank them all. Key: "all"
+#[substreams::handlers::store]
+fn store_facilitator_roster(volume_deltas: Deltas<DeltaBigInt>, store: StoreAppend<String>) {
     for delta in volume_deltas.deltas {
-        let recipient = delta.key.clone();
-        let total_payments = count_store.get_last(&recipient).unwrap_or(0) as u64;
-        let first_payment_at = first_seen_store
-            .get_last(&format!("recipient:{}", recipient))
-            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
-
-        stats.stats.push(x402::RecipientStat {
-            recipient_address: recipient,
-            total_received: delta.new_value.to_string(),
-            total_payments,
-            first_payment_at,
-            last_payment_at: settlements.block_timestamp.clone(),
-        });
+        if delta.operation == Operation::Create {
+            store.append(0, "all", delta.key);
+        }
     }
+}
 
-    Ok(stats)
+/// Number of leaderboard volume tiers; the top tier holds every volume of at
+/// least 2^(LEADERBOARD_TIERS - 1) atomic units
+const LEADERBOARD_TIERS: u64 = 96;
+
+/// A cumulative volume's (or reach's) leaderboard tier: its bit length, so
+/// each tier spans a doubling
+fn leaderboard_tier(volume: &BigInt) -> u64 {
+    if *volume <= BigInt::zero() {
+        return 0;
+    }
+    (volume.bits() as u64).min(LEADERBOARD_TIERS - 1)
 }
 
-/// Compute facilitator economics, enriched with name and active status from
-/// the FacilitatorRegistry.
-#[substreams::handlers::map]
-fn map_facilitator_stats(
-    settlements: x402::Settlements,
-    volume_deltas: Deltas<DeltaBigInt>,
-    count_store: StoreGetInt64,
-    gas_store: StoreGetBigInt,
-    first_seen_store: StoreGetInt64,
-    registry_store: StoreGetString,
-) -> Result<x402::FacilitatorStats, substreams::errors::Error> {
-    let mut stats = x402::FacilitatorStats {
-        block_number: settlements.block_number,
-        ..Default::default()
-    };
+/// The tier an address left and the tier it entered on a volume delta, when
+/// the delta moved it. A new address leaves no tier.
+fn leaderboard_tier_move(delta: &DeltaBigInt) -> Option<(Option<u64>, u64)> {
+    let new_tier = leaderboard_tier(&delta.new_value);
+    if delta.operation == Operation::Create {
+        return Some((None, new_tier));
+    }
+    let old_tier = leaderboard_tier(&delta.old_value);
+    (old_tier != new_tier).then_some((Some(old_tier), new_tier))
+}
 
-    for delta in volume_deltas.deltas {
-        let facilitator = delta.key.clone();
-        let total_settlements = count_store.get_last(&facilitator).unwrap_or(0) as u64;
-        let total_gas = gas_store
-            .get_last(&facilitator)
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| "0".to_string());
-        let first_settlement_at = first_seen_store
-            .get_last(&format!("facilitator:{}", facilitator))
-            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
+/// Each ranked role's deltas: payer and recipient volume, and recipient
+/// reach (unique payers) widened to BigInt so all three share the tiers
+fn leaderboard_role_deltas(
+    payer_volume_deltas: Deltas<DeltaBigInt>,
+    recipient_volume_deltas: Deltas<DeltaBigInt>,
+    unique_payer_deltas: Deltas<DeltaInt64>,
+) -> [(&'static str, Vec<DeltaBigInt>); 3] {
+    let reach = unique_payer_deltas
+        .deltas
+        .into_iter()
+        .map(|d| DeltaBigInt {
+            operation: d.operation,
+            ordinal: d.ordinal,
+            key: d.key,
+            old_value: BigInt::from(d.old_value),
+            new_value: BigInt::from(d.new_value),
+        })
+        .collect();
+    [
+        ("payer", payer_volume_deltas.deltas),
+        ("recipient", recipient_volume_deltas.deltas),
+        ("reach", reach),
+    ]
+}
 
-        // Look up facilitator name and status from registry
-        let (name, url, is_active) = match registry_store.get_last(&facilitator) {
-            Some(val) if !val.is_empty() => {
-                let parts: Vec<&str> = val.splitn(2, '|').collect();
-                let name = parts.first().unwrap_or(&"").to_string();
-                let url = parts.get(1).unwrap_or(&"").to_string();
-                (name, url, true)
+/// List payers and recipients under each volume tier they've entered (and
+/// recipients under each reach tier), so `map_leaderboard` and
+/// `map_top_recipients_by_reach` only read the top tiers. An address that
+/// moves up stays listed in the tiers below; readers drop it there.
+/// Keys: {role}:{tier}, role payer, recipient or reach
+#[substreams::handlers::store]
+fn store_leaderboard_roster(
+    payer_volume_deltas: Deltas<DeltaBigInt>,
+    recipient_volume_deltas: Deltas<DeltaBigInt>,
+    unique_payer_deltas: Deltas<DeltaInt64>,
+    store: StoreAppend<String>,
+) {
+    let roles =
+        leaderboard_role_deltas(payer_volume_deltas, recipient_volume_deltas, unique_payer_deltas);
+    for (role, deltas) in roles {
+        for delta in deltas {
+            if let Some((_, tier)) = leaderboard_tier_move(&delta) {
+                store.append(0, format!("{}:{}", role, tier), delta.key);
             }
-            Some(_) => (String::new(), String::new(), false), // Removed facilitator
-            None => (String::new(), String::new(), false),     // Unknown facilitator
-        };
-
-        stats.stats.push(x402::FacilitatorStat {
-            facilitator_address: facilitator,
-            total_settlements,
-            total_volume_settled: delta.new_value.to_string(),
-            total_gas_spent: total_gas,
-            first_settlement_at,
-            last_settlement_at: settlements.block_timestamp.clone(),
-            name,
-            is_active,
-            url,
-        });
+        }
     }
+}
 
-    Ok(stats)
+/// Count payers and recipients currently in each volume tier, and recipients
+/// in each reach tier, plus how many entries each tier's roster has had
+/// appended (stale ones included), so readers can bound a roster read.
+/// Keys: {role}:{tier} and listed:{role}:{tier}, role payer, recipient or reach
+#[substreams::handlers::store]
+fn store_leaderboard_tier_count(
+    payer_volume_deltas: Deltas<DeltaBigInt>,
+    recipient_volume_deltas: Deltas<DeltaBigInt>,
+    unique_payer_deltas: Deltas<DeltaInt64>,
+    store: StoreAddInt64,
+) {
+    let roles =
+        leaderboard_role_deltas(payer_volume_deltas, recipient_volume_deltas, unique_payer_deltas);
+    for (role, deltas) in roles {
+        for delta in deltas {
+            let Some((old_tier, new_tier)) = leaderboard_tier_move(&delta) else {
+                continue;
+            };
+            if let Some(old_tier) = old_tier {
+                store.add(0, format!("{}:{}", role, old_tier), -1);
+            }
+            store.add(0, format!("{}:{}", role, new_tier), 1);
+            store.add(0, format!("listed:{}:{}", role, new_tier), 1);
+        }
+    }
 }
 
-// =============================================
-// LAYER 4: SQL Sink
-// =============================================
+/// Accumulate total gas cost per facilitator (gas_used * gas_price in wei)
+#[substreams::handlers::store]
+fn store_facilitator_gas(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for s in settlements.settlements {
+        if s.facilitator.is_empty() {
+            continue;
+        }
+        let gas_used = parse_amount(&s.gas_used);
+        let gas_price = parse_amount(&s.gas_price);
+        let gas_cost = gas_used * gas_price;
+        store.add(0, s.facilitator.to_lowercase(), &gas_cost);
+    }
+}
 
-/// Output database changes for PostgreSQL
-#[substreams::handlers::map]
-fn db_out(
-    params: String,
+/// The fee a settlement paid its own facilitator. Fees routed to any other
+/// address aren't the facilitator's revenue.
+fn facilitator_fee_earned(s: &x402::Settlement) -> Option<BigInt> {
+    if s.facilitator.is_empty() || !s.fee_recipient.eq_ignore_ascii_case(&s.facilitator) {
+        return None;
+    }
+    Some(parse_amount(&s.fee_amount)).filter(|fee| *fee > BigInt::zero())
+}
+
+/// Accumulate fees each facilitator captured for itself. Key: {facilitator_address}
+#[substreams::handlers::store]
+fn store_facilitator_fee_revenue(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for s in settlements.settlements {
+        if let Some(fee) = facilitator_fee_earned(&s) {
+            store.add(0, s.facilitator.to_lowercase(), &fee);
+        }
+    }
+}
+
+/// Accumulate total gas cost per facilitator in USD (6-decimal units), priced
+/// at the last known ETH/USD answer. Gas settled before any price is known is
+/// not counted.
+#[substreams::handlers::store]
+fn store_facilitator_gas_usd(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for s in settlements.settlements {
+        if s.facilitator.is_empty() || s.gas_cost_usd.is_empty() {
+            continue;
+        }
+        store.add(0, s.facilitator.to_lowercase(), &parse_amount(&s.gas_cost_usd));
+    }
+}
+
+/// Gas USD added across all facilitators in a block of gas USD deltas
+fn gas_usd_added(deltas: &[DeltaBigInt]) -> BigInt {
+    deltas
+        .iter()
+        .fold(BigInt::zero(), |sum, d| sum + (d.new_value.clone() - d.old_value.clone()))
+}
+
+/// Accumulate facilitator gas spent in USD protocol-wide. Key: gas_usd
+#[substreams::handlers::store]
+fn store_protocol_gas_usd(gas_usd_deltas: Deltas<DeltaBigInt>, store: StoreAddBigInt) {
+    store.add(0, "gas_usd", gas_usd_added(&gas_usd_deltas.deltas));
+}
+
+/// Seconds from the payer signing (approximated by `valid_after`) to the
+/// settlement block. None when valid_after is unknown (0) or in the future.
+fn settlement_latency(block_ts: i64, valid_after: u64) -> Option<i64> {
+    let valid_after = i64::try_from(valid_after).ok().filter(|v| *v > 0)?;
+    (block_ts >= valid_after).then(|| block_ts - valid_after)
+}
+
+/// Accumulate settlement latency per facilitator.
+/// Keys: {facilitator}:total (seconds), {facilitator}:count (samples)
+#[substreams::handlers::store]
+fn store_facilitator_latency(settlements: x402::Settlements, store: StoreAddInt64) {
+    let block_ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    for s in settlements.settlements {
+        if let Some(latency) = settlement_latency(block_ts, s.valid_after) {
+            let facilitator = s.facilitator.to_lowercase();
+            store.add(0, format!("{}:total", facilitator), latency);
+            store.add(0, format!("{}:count", facilitator), 1);
+        }
+    }
+}
+
+/// A matched settlement's facilitator fee rate: the fee's share of the
+/// payment plus fee, in bps (see `value_split_bps`). None when unmatched.
+fn settlement_fee_bps(s: &x402::Settlement) -> Option<u64> {
+    if is_unmatched(s) {
+        return None;
+    }
+    let amount = parse_amount(&s.amount);
+    let fee = parse_amount(&s.fee_amount);
+    Some(value_split_bps(&amount, &fee).1)
+}
+
+/// Accumulate fee rates per facilitator for the average fee rate.
+/// Keys: {facilitator}:total_bps, {facilitator}:count
+#[substreams::handlers::store]
+fn store_facilitator_fee_rate(settlements: x402::Settlements, store: StoreAddInt64) {
+    for s in settlements.settlements {
+        if let Some(bps) = settlement_fee_bps(&s) {
+            let facilitator = s.facilitator.to_lowercase();
+            store.add(0, format!("{}:total_bps", facilitator), bps as i64);
+            store.add(0, format!("{}:count", facilitator), 1);
+        }
+    }
+}
+
+/// Count settlements per fee rate bucket, protocol-wide, for the median fee
+/// rate. Key: {bucket}, see `fee_rate_bucket`
+#[substreams::handlers::store]
+fn store_fee_rate_histogram(settlements: x402::Settlements, store: StoreAddInt64) {
+    for s in settlements.settlements {
+        if let Some(bps) = settlement_fee_bps(&s) {
+            store.add(0, fee_rate_bucket(bps).to_string(), 1);
+        }
+    }
+}
+
+/// Track the slowest settlement latency per facilitator. Key: {facilitator_address}
+#[substreams::handlers::store]
+fn store_facilitator_latency_max(settlements: x402::Settlements, store: StoreMaxInt64) {
+    let block_ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    for s in settlements.settlements {
+        if let Some(latency) = settlement_latency(block_ts, s.valid_after) {
+            store.max(0, s.facilitator.to_lowercase(), latency);
+        }
+    }
+}
+
+/// Identity of the EIP-3009 authorization behind a settlement: nonces are
+/// per authorizer, so {payer}:{nonce}. None for proxy settlements, which
+/// carry no nonce.
+fn authorization_id(s: &x402::Settlement) -> Option<String> {
+    if s.nonce.is_empty() || s.payer.is_empty() {
+        return None;
+    }
+    Some(format!("{}:{}", s.payer.to_lowercase(), s.nonce.to_lowercase()))
+}
+
+/// Record the first settlement id seen for each authorization, so later
+/// settlements reusing it can be told apart. Key: {payer}:{nonce}
+#[substreams::handlers::store]
+fn store_authorization_first(settlements: x402::Settlements, store: StoreSetIfNotExistsString) {
+    for s in settlements.settlements {
+        if let Some(key) = authorization_id(&s) {
+            store.set_if_not_exists(0, key, &s.id);
+        }
+    }
+}
+
+/// Record the first-seen block timestamp per payer, recipient, and facilitator.
+/// Uses set_if_not_exists so only the earliest timestamp is stored. Blocks
+/// without a timestamp are skipped rather than recorded as epoch 0. The stat
+/// maps read it in get mode, which sees this block's writes, so an address
+/// first seen in a block already has its `first_payment_at` /
+/// `first_settlement_at` in that block's stats.
+#[substreams::handlers::store]
+fn store_first_seen(settlements: x402::Settlements, store: StoreSetIfNotExistsInt64) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    for s in settlements.settlements {
+        if !s.payer.is_empty() && s.payer != ZERO_ADDR {
+            store.set_if_not_exists(0, format!("payer:{}", s.payer.to_lowercase()), &ts);
+        }
+        if !s.recipient.is_empty() && s.recipient != ZERO_ADDR {
+            store.set_if_not_exists(0, format!("recipient:{}", s.recipient.to_lowercase()), &ts);
+        }
+        if !s.facilitator.is_empty() {
+            store.set_if_not_exists(
+                0,
+                format!("facilitator:{}", s.facilitator.to_lowercase()),
+                &ts,
+            );
+        }
+        if let Some(key) = subscription_key(&s) {
+            store.set_if_not_exists(0, format!("subscription:{}", key), &ts);
+        }
+    }
+}
+
+/// Whether a payment count change took a recipient to its second payment
+fn reached_second_payment(old_count: i64, new_count: i64) -> bool {
+    old_count < 2 && new_count >= 2
+}
+
+/// Seconds from a recipient's first payment to its second, once both are known
+fn time_to_second_payment(first_at: Option<i64>, second_at: Option<i64>) -> Option<i64> {
+    Some(second_at? - first_at?)
+}
+
+/// Record the block timestamp of each recipient's second payment, when its
+/// payment count reaches 2. Key: {recipient_address}
+#[substreams::handlers::store]
+fn store_recipient_second_payment(
     settlements: x402::Settlements,
-    payer_stats: x402::PayerStats,
-    recipient_stats: x402::RecipientStats,
-    facilitator_stats: x402::FacilitatorStats,
-) -> Result<DatabaseChanges, substreams::errors::Error> {
-    let mut tables = Tables::new();
+    count_deltas: Deltas<DeltaInt64>,
+    store: StoreSetIfNotExistsInt64,
+) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    for delta in count_deltas.deltas {
+        if reached_second_payment(delta.old_value, delta.new_value) {
+            store.set_if_not_exists(0, &delta.key, &ts);
+        }
+    }
+}
 
-    // Parse min_amount param
-    let min_amount = params
-        .split('=')
-        .nth(1)
-        .map(|v| v.to_string())
-        .and_then(|v| BigInt::try_from(&v).ok())
-        .unwrap_or_else(BigInt::zero);
+/// Half-life of the payment-size EWMA: a payment's weight halves for every
+/// week of age, so the average follows a new price level within a few weeks
+const EWMA_HALF_LIFE_SECONDS: i64 = 7 * 86400;
+
+/// Origin of the EWMA weights (2025-01-01 UTC). Only ratios of weights
+/// matter, so any fixed origin works; earlier payments weigh as if at it.
+const EWMA_EPOCH_SECONDS: i64 = 1_735_689_600;
+
+/// Fractional bits of the fixed-point EWMA weight
+const EWMA_FRACTION_BITS: u32 = 32;
+
+/// Weight of a payment made at `ts`: 2^(age / half-life) since the EWMA
+/// epoch, in fixed point with `EWMA_FRACTION_BITS` fractional bits, so it
+/// rises continuously rather than in weekly steps. Later payments weigh
+/// exponentially more, which is an exponential moving average once
+/// normalized, without the store reading its previous value.
+///
+/// The effective smoothing factor depends on the gap between payments: a
+/// payment arriving `dt` after the last takes a share
+/// alpha = 1 - 2^(-dt / half-life) of the average, about 0.094 for daily
+/// payments and 0.004 for hourly ones. Weights grow by one bit per half-life
+/// (about 52 bits a year); the sums are BigInts, so this costs only store
+/// size, and moving the epoch would need a full reprocess.
+fn ewma_weight(ts: i64) -> BigInt {
+    let age = (ts - EWMA_EPOCH_SECONDS).max(0);
+    let half_lives = (age / EWMA_HALF_LIFE_SECONDS) as usize;
+    let rest = (age % EWMA_HALF_LIFE_SECONDS) as f64 / EWMA_HALF_LIFE_SECONDS as f64;
+    // 2^rest in [1, 2), scaled to an integer
+    let fraction = (rest.exp2() * (1u64 << EWMA_FRACTION_BITS) as f64).round() as u64;
+    (num_bigint::BigInt::from(fraction) << half_lives).into()
+}
+
+/// Mean payment from a running total and count, floored to atomic units.
+/// Both must cover the same settlements: the count from the volume store's
+/// own filter (`store_payer_volume_count`), and after `reset_at_block` the
+/// since-reset total and count. Zero before any payment, so a stat row never
+/// divides by zero.
+fn avg_payment(total: &BigInt, payments: u64) -> BigInt {
+    if payments == 0 {
+        return BigInt::zero();
+    }
+    total.clone() / BigInt::from(payments)
+}
+
+/// Time-weighted average payment size from the decayed sums
+fn ewma_payment_size(weighted_amount: &BigInt, weight: &BigInt) -> BigInt {
+    if weight.is_zero() {
+        return BigInt::zero();
+    }
+    weighted_amount.clone() / weight.clone()
+}
+
+/// Accumulate each recipient's payment-size EWMA as two decayed sums, since a
+/// store can't read back its own previous average. Keys:
+/// {recipient}:weighted_amount (sum of amount * weight), {recipient}:weight
+#[substreams::handlers::store]
+fn store_recipient_ewma(settlements: x402::Settlements, store: StoreAddBigInt) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let weight = ewma_weight(ts);
+    for s in settlements
+        .settlements
+        .iter()
+        .filter(|s| !is_unmatched(s) && is_token_amount(s))
+    {
+        let recipient = s.recipient.to_lowercase();
+        let amount = parse_amount(&s.amount);
+        store.add(0, format!("{}:weighted_amount", recipient), amount * weight.clone());
+        store.add(0, format!("{}:weight", recipient), &weight);
+    }
+}
+
+/// A settlement's single-payment amount under each side's key, for the
+/// min/max payment stores. Unmatched settlements carry no payment, and native
+/// ETH amounts aren't comparable with token amounts.
+/// Keys: payer:{address}, recipient:{address}
+fn payment_extreme_keys(s: &x402::Settlement) -> Vec<(String, BigInt)> {
+    if is_unmatched(s) || !is_token_amount(s) {
+        return vec![];
+    }
+    let amount = parse_amount(&s.amount);
+    [("payer", &s.payer), ("recipient", &s.recipient)]
+        .into_iter()
+        .filter(|(_, address)| !address.is_empty() && *address != ZERO_ADDR)
+        .map(|(role, address)| (format!("{}:{}", role, address.to_lowercase()), amount.clone()))
+        .collect()
+}
+
+/// Track each payer's and recipient's smallest single payment. A key's first
+/// payment sets it outright, so min and max start equal. A min can't be
+/// rebased by subtraction, so with `reset_at_block` (param) this store skips
+/// the blocks before it instead.
+/// Keys: payer:{address}, recipient:{address}
+#[substreams::handlers::store]
+fn store_payment_min(params: String, settlements: x402::Settlements, store: StoreMinBigInt) {
+    if before_reset(&params, settlements.block_number) {
+        return;
+    }
+    for s in settlements.settlements {
+        for (key, amount) in payment_extreme_keys(&s) {
+            store.min(0, key, &amount);
+        }
+    }
+}
+
+/// Track each payer's and recipient's largest single payment, from
+/// `reset_at_block` (param) on like `store_payment_min`. `db_out` reads the
+/// payer keys for personal records, so those count from the reset too.
+/// Keys: payer:{address}, recipient:{address}
+#[substreams::handlers::store]
+fn store_payment_max(params: String, settlements: x402::Settlements, store: StoreMaxBigInt) {
+    if before_reset(&params, settlements.block_number) {
+        return;
+    }
+    for s in settlements.settlements {
+        for (key, amount) in payment_extreme_keys(&s) {
+            store.max(0, key, &amount);
+        }
+    }
+}
 
-    // Insert settlements
+/// Count payments per (payer, recipient, amount). Key: {payer}:{recipient}:{amount}
+#[substreams::handlers::store]
+fn store_pair_amount_count(settlements: x402::Settlements, store: StoreAddInt64) {
     for s in settlements.settlements {
-        let amount = BigInt::try_from(&s.amount).unwrap_or_else(|_| BigInt::zero());
-        if amount < min_amount {
+        if let Some(key) = subscription_key(&s) {
+            store.add(0, key, 1);
+        }
+    }
+}
+
+/// Accumulate settled USDC value kept by recipients vs facilitators,
+/// protocol-wide. Other tokens are left out: their units differ, so one sum
+/// across tokens wouldn't be a value. Keys: "recipient", "facilitator"
+#[substreams::handlers::store]
+fn store_value_split(settlements: x402::Settlements, store: StoreAddBigInt) {
+    let usdc = format_address(&USDC).to_lowercase();
+    for s in settlements.settlements.iter().filter(|s| s.token.to_lowercase() == usdc) {
+        let amount = parse_amount(&s.amount);
+        let fee = parse_amount(&s.fee_amount);
+        store.add(0, "recipient", &amount);
+        store.add(0, "facilitator", &fee);
+    }
+}
+
+/// Whether a settlement counts in the protocol-wide daily, hourly, weekly and
+/// monthly rollups: matched, and not a self-payment under `exclude_self`.
+/// The volume rollups also skip native ETH (`is_token_amount`).
+fn counts_toward_rollup(s: &x402::Settlement, exclude_self: bool) -> bool {
+    !is_unmatched(s) && counts_toward_volume(s, exclude_self)
+}
+
+/// A block's settlements that count in the rollups (`counts_toward_rollup`)
+fn rollup_count(settlements: &[x402::Settlement], exclude_self: bool) -> i64 {
+    settlements
+        .iter()
+        .filter(|s| counts_toward_rollup(s, exclude_self))
+        .count() as i64
+}
+
+/// Count settlements per UTC day, protocol-wide. Key: YYYY-MM-DD
+#[substreams::handlers::store]
+fn store_daily_count(params: String, settlements: x402::Settlements, store: StoreAddInt64) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    let count = rollup_count(&settlements.settlements, exclude_self);
+    if count == 0 {
+        return;
+    }
+    store.add(0, unix_to_date(ts), count);
+}
+
+/// Accumulate settled volume per UTC day, protocol-wide. Key: YYYY-MM-DD
+#[substreams::handlers::store]
+fn store_daily_volume(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    for s in &settlements.settlements {
+        if !counts_toward_rollup(s, exclude_self) || !is_token_amount(s) {
             continue;
         }
+        let amount = parse_amount(&s.amount);
+        store.add(0, unix_to_date(ts), &amount);
+    }
+}
 
-        let timestamp = s
-            .timestamp
-            .as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
+/// Accumulate settled volume per UTC hour, protocol-wide. Key: YYYY-MM-DDTHH
+#[substreams::handlers::store]
+fn store_hourly_volume(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    for s in &settlements.settlements {
+        if !counts_toward_rollup(s, exclude_self) || !is_token_amount(s) {
+            continue;
+        }
+        store.add(0, unix_to_hour(ts), &parse_amount(&s.amount));
+    }
+}
 
-        tables
-            .create_row("settlements", &s.id)
-            .set("block_number", s.block_number)
-            .set("block_timestamp", &timestamp)
-            .set("tx_hash", &s.tx_hash)
-            .set("log_index", s.log_index)
-            .set("payer", &s.payer)
-            .set("recipient", &s.recipient)
-            .set("token", &s.token)
-            .set("amount", &s.amount)
-            .set("settlement_type", &s.settlement_type)
-            .set("facilitator", &s.facilitator)
-            .set("gas_used", &s.gas_used)
-            .set("gas_price", &s.gas_price)
-            .set("nonce", &s.nonce);
+/// Count settlements per UTC hour, protocol-wide. Key: YYYY-MM-DDTHH
+#[substreams::handlers::store]
+fn store_hourly_count(params: String, settlements: x402::Settlements, store: StoreAddInt64) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    let count = rollup_count(&settlements.settlements, exclude_self);
+    if count == 0 {
+        return;
     }
+    store.add(0, unix_to_hour(ts), count);
+}
 
-    // Upsert payer stats
-    for stat in payer_stats.stats {
-        let first_ts = stat.first_payment_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        let last_ts = stat.last_payment_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        tables
-            .create_row("payers", &stat.payer_address)
-            .set("total_spent", stat.total_spent.as_str())
-            .set("total_payments", stat.total_payments as i64)
-            .set("first_payment_at", &first_ts)
-            .set("last_payment_at", &last_ts);
+/// The hour a block's settlements rolled into, with the hour's running
+/// volume and count, from the hourly store deltas. A block falls in a single
+/// hour, so the last delta of each holds the totals.
+fn hourly_rollup<'a>(
+    volume_deltas: &'a [DeltaBigInt],
+    count_deltas: &[DeltaInt64],
+) -> Option<(&'a str, &'a BigInt, i64)> {
+    let volume = volume_deltas.last()?;
+    let count = count_deltas
+        .iter()
+        .rev()
+        .find(|d| d.key == volume.key)
+        .map_or(0, |d| d.new_value);
+    Some((&volume.key, &volume.new_value, count))
+}
+
+/// Accumulate settled volume per ISO week and per month, protocol-wide.
+/// Keys: week:{YYYY-Www}, month:{YYYY-MM}
+#[substreams::handlers::store]
+fn store_period_volume(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    let periods = [
+        format!("week:{}", unix_to_iso_week(ts)),
+        format!("month:{}", unix_to_month(ts)),
+    ];
+    for s in &settlements.settlements {
+        if !counts_toward_rollup(s, exclude_self) || !is_token_amount(s) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        for period in &periods {
+            store.add(0, period, &amount);
+        }
     }
+}
 
-    // Upsert recipient stats
-    for stat in recipient_stats.stats {
-        let first_ts = stat.first_payment_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        let last_ts = stat.last_payment_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        tables
-            .create_row("recipients", &stat.recipient_address)
-            .set("total_received", stat.total_received.as_str())
-            .set("total_payments", stat.total_payments as i64)
-            .set("first_payment_at", &first_ts)
-            .set("last_payment_at", &last_ts);
+/// Count settlements per ISO week and per month, protocol-wide.
+/// Keys: week:{YYYY-Www}, month:{YYYY-MM}
+#[substreams::handlers::store]
+fn store_period_count(params: String, settlements: x402::Settlements, store: StoreAddInt64) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let exclude_self = param_value(&params, "exclude_self") == Some("true");
+    let count = rollup_count(&settlements.settlements, exclude_self);
+    if count == 0 {
+        return;
     }
+    store.add(0, format!("week:{}", unix_to_iso_week(ts)), count);
+    store.add(0, format!("month:{}", unix_to_month(ts)), count);
+}
 
-    // Upsert facilitator stats
-    for stat in facilitator_stats.stats {
-        let first_ts = stat.first_settlement_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        let last_ts = stat.last_settlement_at.as_ref()
-            .map(|t| unix_to_timestamp(t.seconds))
-            .unwrap_or_else(|| "1970-01-01 00:00:00".to_string());
-        tables
-            .create_row("facilitators", &stat.facilitator_address)
-            .set("name", &stat.name)
-            .set("url", &stat.url)
-            .set("is_active", stat.is_active)
-            .set("total_settlements", stat.total_settlements as i64)
-            .set("total_volume_settled", stat.total_volume_settled.as_str())
-            .set("total_gas_spent", stat.total_gas_spent.as_str())
-            .set("first_settlement_at", &first_ts)
-            .set("last_settlement_at", &last_ts);
+/// Each period a block's settlements rolled into, with the period's running
+/// volume and count, from the period store deltas: the last delta per key
+/// holds the totals.
+fn period_rollups<'a>(
+    volume_deltas: &'a [DeltaBigInt],
+    count_deltas: &[DeltaInt64],
+) -> Vec<(&'a str, &'a BigInt, i64)> {
+    let mut rollups: Vec<(&str, &BigInt, i64)> = Vec::new();
+    for delta in volume_deltas {
+        match rollups.iter_mut().find(|(key, _, _)| *key == delta.key) {
+            Some(rollup) => rollup.1 = &delta.new_value,
+            None => rollups.push((&delta.key, &delta.new_value, 0)),
+        }
+    }
+    for (key, _, count) in rollups.iter_mut() {
+        *count = count_deltas
+            .iter()
+            .rev()
+            .find(|d| d.key == *key)
+            .map_or(0, |d| d.new_value);
     }
+    rollups
+}
 
-    Ok(tables.to_database_changes())
+/// Accumulate payment volume per payer per UTC day, for time-series charts.
+/// Key: {payer}:{YYYY-MM-DD}
+#[substreams::handlers::store]
+fn store_daily_payer_volume(settlements: x402::Settlements, store: StoreAddBigInt) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    let date = unix_to_date(ts);
+    for s in settlements.settlements {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR || !is_token_amount(&s) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        store.add(0, payer_day_key(&s.payer, &date), &amount);
+    }
+}
+
+/// `store_daily_payer_volume` key for a payer's UTC day
+fn payer_day_key(payer: &str, date: &str) -> String {
+    format!("{}:{}", payer.to_lowercase(), date)
+}
+
+/// Track the all-time high of any day's cumulative volume. Key: all_time
+#[substreams::handlers::store]
+fn store_max_daily_volume(daily_volume_deltas: Deltas<DeltaBigInt>, store: StoreMaxBigInt) {
+    for delta in daily_volume_deltas.deltas {
+        store.max(0, "all_time", &delta.new_value);
+    }
+}
+
+/// Whether an all-time-high delta sets a new record: the high rose above a
+/// prior one. The very first day has no prior high to beat.
+fn is_volume_record(delta: &DeltaBigInt) -> bool {
+    delta.operation != Operation::Create && delta.new_value > delta.old_value
+}
+
+/// Settlement types, in the order `map_daily_type_mix` reports them
+const SETTLEMENT_TYPES: &[&str] = &["eip3009", "eip3009_proxy", "settled", "settled_with_permit"];
+
+/// Per-type settlement counts for a block, keyed {date}:{settlement_type}
+fn daily_type_counts(settlements: &[x402::Settlement], date: &str) -> Vec<(String, i64)> {
+    let mut counts: Vec<(String, i64)> = Vec::new();
+    for s in settlements {
+        let key = format!("{}:{}", date, s.settlement_type);
+        match counts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((key, 1)),
+        }
+    }
+    counts
+}
+
+/// Running sums for a streaming Pearson correlation of gas price (x) against
+/// settlement amount (y), in store key order
+const CORRELATION_SUMS: [&str; 6] = ["n", "sum_x", "sum_y", "sum_xy", "sum_xx", "sum_yy"];
+
+/// A settlement's (gas price, amount) sample, when both are known and positive
+fn gas_amount_sample(s: &x402::Settlement) -> Option<(BigInt, BigInt)> {
+    if is_unmatched(s) {
+        return None;
+    }
+    let gas_price = Some(parse_amount(&s.gas_price)).filter(|g| *g > BigInt::zero())?;
+    let amount = Some(parse_amount(&s.amount)).filter(|a| *a > BigInt::zero())?;
+    Some((gas_price, amount))
+}
+
+/// What one (x, y) sample adds to each of `CORRELATION_SUMS`
+fn correlation_increments(x: &BigInt, y: &BigInt) -> [BigInt; 6] {
+    [
+        BigInt::one(),
+        x.clone(),
+        y.clone(),
+        x.clone() * y.clone(),
+        x.clone() * x.clone(),
+        y.clone() * y.clone(),
+    ]
+}
+
+/// Pearson correlation coefficient from `CORRELATION_SUMS`, in basis points
+/// (-10000..=10000), in integer arithmetic:
+/// (nΣxy − ΣxΣy) / √((nΣx² − (Σx)²)(nΣy² − (Σy)²)).
+/// None with fewer than two samples or when either variable is constant.
+fn pearson_bps(sums: &[BigInt; 6]) -> Option<i64> {
+    let [n, sx, sy, sxy, sxx, syy] = sums.clone();
+    let covariance = n.clone() * sxy - sx.clone() * sy.clone();
+    let var_x = n.clone() * sxx - sx.clone() * sx;
+    let var_y = n * syy - sy.clone() * sy;
+    if var_x <= BigInt::zero() || var_y <= BigInt::zero() {
+        return None;
+    }
+    let denominator: num_bigint::BigInt = (var_x * var_y).into();
+    let bps = covariance * BigInt::from(10_000) / BigInt::from(denominator.sqrt());
+    bps.to_string().parse().ok()
+}
+
+/// Accumulate the sums for the gas price / amount correlation, protocol-wide.
+/// Keys: n, sum_x, sum_y, sum_xy, sum_xx, sum_yy
+#[substreams::handlers::store]
+fn store_gas_amount_sums(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for (x, y) in settlements.settlements.iter().filter_map(gas_amount_sample) {
+        for (key, value) in CORRELATION_SUMS.iter().zip(correlation_increments(&x, &y)) {
+            store.add(0, key, &value);
+        }
+    }
+}
+
+/// UTC hour bucket of a Unix timestamp: `YYYY-MM-DDTHH`
+fn unix_to_hour(secs: i64) -> String {
+    format!("{}T{:02}", unix_to_date(secs), (secs.max(0) % 86400) / 3600)
+}
+
+/// Mark each UTC hour with the timestamp of its first block, whether or not
+/// it settled anything. Key: {YYYY-MM-DDTHH}
+#[substreams::handlers::store]
+fn store_heartbeat_hours(clock: Clock, store: StoreSetIfNotExistsInt64) {
+    let secs = match clock.timestamp.as_ref() {
+        Some(t) if t.seconds > 0 => t.seconds,
+        _ => return,
+    };
+    store.set_if_not_exists(0, unix_to_hour(secs), &secs);
+}
+
+/// Keep the timestamp of the latest block with settlements. Its deltas carry
+/// the previous one as old_value. Key: last
+#[substreams::handlers::store]
+fn store_last_settlement_block_ts(settlements: x402::Settlements, store: StoreSetInt64) {
+    if settlements.settlements.is_empty() {
+        return;
+    }
+    if let Some(t) = settlements.block_timestamp.as_ref() {
+        store.set(0, "last", &t.seconds);
+    }
+}
+
+/// Seconds between the previous settlement block and this one, from a
+/// `store_last_settlement_block_ts` delta. None for the very first block.
+fn prior_block_gap(delta: &DeltaInt64) -> Option<i64> {
+    (delta.operation != Operation::Create).then_some(delta.new_value - delta.old_value)
+}
+
+/// Hours first seen this block, with their first block's timestamp
+fn new_heartbeat_hours(deltas: &[DeltaInt64]) -> Vec<(&str, i64)> {
+    deltas
+        .iter()
+        .filter(|d| d.operation == Operation::Create)
+        .map(|d| (d.key.as_str(), d.new_value))
+        .collect()
+}
+
+/// USDC denominations by upper bound in atomic units (6 decimals), ascending;
+/// amounts at or above the last bound are `thousands_plus`
+const DENOMINATIONS: &[(&str, u64)] = &[
+    ("sub_cent", 10_000),
+    ("cents", 1_000_000),
+    ("dollars", 10_000_000),
+    ("tens", 100_000_000),
+    ("hundreds", 1_000_000_000),
+];
+
+/// Denomination of a USDC amount in atomic units
+fn denomination(amount: &BigInt) -> &'static str {
+    DENOMINATIONS
+        .iter()
+        .find(|(_, bound)| *amount < BigInt::from(*bound))
+        .map(|(name, _)| *name)
+        .unwrap_or("thousands_plus")
+}
+
+/// Per-denomination counts of a block's matched USDC settlements
+fn denomination_counts(settlements: &[x402::Settlement]) -> Vec<(&'static str, i64)> {
+    let usdc = format_address(&USDC).to_lowercase();
+    let mut counts: Vec<(&'static str, i64)> = Vec::new();
+    for s in settlements {
+        if is_unmatched(s) || s.token.to_lowercase() != usdc {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        let name = denomination(&amount);
+        match counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    counts
+}
+
+/// Count USDC settlements per denomination, protocol-wide. Key: {denomination}
+#[substreams::handlers::store]
+fn store_denomination_count(settlements: x402::Settlements, store: StoreAddInt64) {
+    for (name, count) in denomination_counts(&settlements.settlements) {
+        store.add(0, name, count);
+    }
+}
+
+/// Count settlements per UTC day and settlement type.
+/// Key: {date}:{settlement_type}
+#[substreams::handlers::store]
+fn store_daily_type_count(settlements: x402::Settlements, store: StoreAddInt64) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    for (key, count) in daily_type_counts(&settlements.settlements, &unix_to_date(ts)) {
+        store.add(0, key, count);
+    }
+}
+
+/// Split a block's settlement volume into (new-payer, returning-payer) volume.
+/// A payer counts as new for the whole UTC day of its first-ever payment.
+fn new_vs_returning_volume(
+    settlements: &[x402::Settlement],
+    block_ts: i64,
+    first_seen: impl Fn(&str) -> Option<i64>,
+) -> (BigInt, BigInt) {
+    let today = unix_to_date(block_ts);
+    let mut new_volume = BigInt::zero();
+    let mut returning_volume = BigInt::zero();
+    for s in settlements {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR || !is_token_amount(s) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        let is_new = first_seen(&s.payer.to_lowercase())
+            .map(|ts| unix_to_date(ts) == today)
+            .unwrap_or(true);
+        if is_new {
+            new_volume = new_volume + amount;
+        } else {
+            returning_volume = returning_volume + amount;
+        }
+    }
+    (new_volume, returning_volume)
+}
+
+/// Accumulate daily volume from new vs returning payers.
+/// Keys: {YYYY-MM-DD}:new, {YYYY-MM-DD}:returning
+#[substreams::handlers::store]
+fn store_daily_new_vs_returning(
+    settlements: x402::Settlements,
+    first_seen_store: StoreGetInt64,
+    store: StoreAddBigInt,
+) {
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return,
+    };
+    if settlements.settlements.is_empty() {
+        return;
+    }
+    let (new_volume, returning_volume) =
+        new_vs_returning_volume(&settlements.settlements, ts, |payer| {
+            first_seen_store.get_last(format!("payer:{}", payer))
+        });
+    let date = unix_to_date(ts);
+    store.add(0, format!("{}:new", date), &new_volume);
+    store.add(0, format!("{}:returning", date), &returning_volume);
+}
+
+/// Accumulate protocol-wide totals. Keys: "volume", "fees", "settlements"
+#[substreams::handlers::store]
+fn store_protocol_totals(settlements: x402::Settlements, store: StoreAddBigInt) {
+    for s in settlements.settlements {
+        store.add(0, "settlements", BigInt::one());
+        if !is_token_amount(&s) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        let fee = parse_amount(&s.fee_amount);
+        store.add(0, "volume", &amount);
+        store.add(0, "fees", &fee);
+    }
+}
+
+/// Histogram of payers by cumulative spend tier (see `amount_bucket`),
+/// moved along as payer volumes change. Keys: count:{tier}, spend:{tier}
+#[substreams::handlers::store]
+fn store_payer_spend_tiers(volume_deltas: Deltas<DeltaBigInt>, store: StoreAddBigInt) {
+    for delta in volume_deltas.deltas {
+        if delta.operation == Operation::Update {
+            let old_tier = amount_bucket(&delta.old_value);
+            store.add(0, format!("count:{}", old_tier), BigInt::from(-1));
+            store.add(0, format!("spend:{}", old_tier), BigInt::zero() - delta.old_value.clone());
+        }
+        let new_tier = amount_bucket(&delta.new_value);
+        store.add(0, format!("count:{}", new_tier), BigInt::one());
+        store.add(0, format!("spend:{}", new_tier), delta.new_value);
+    }
+}
+
+/// Observe each payer and recipient in the HyperLogLog registers.
+/// Keys: payer:{register}, recipient:{register} (HLL_REGISTERS per kind)
+///
+/// Each register is its own max-policy key: max is exactly the HLL register
+/// merge, whereas a single proto value of registers would need a
+/// read-modify-write that a store can't do.
+#[substreams::handlers::store]
+fn store_hll_registers(settlements: x402::Settlements, store: StoreMaxInt64) {
+    for s in settlements.settlements {
+        for (kind, address) in [("payer", &s.payer), ("recipient", &s.recipient)] {
+            if address.is_empty() || address == ZERO_ADDR {
+                continue;
+            }
+            let (register, rank) = hll_observe(&address.to_lowercase());
+            store.max(0, format!("{}:{}", kind, register), rank as i64);
+        }
+    }
+}
+
+/// Maintain the HLL estimate inputs incrementally from register changes, so
+/// reading an estimate takes two keys instead of every register.
+/// Keys: {kind}:sum (sum of 2^(64-new) - 2^(64-old)), {kind}:nonzero
+#[substreams::handlers::store]
+fn store_hll_sums(register_deltas: Deltas<DeltaInt64>, store: StoreAddBigInt) {
+    let two = BigInt::from(2u64);
+    for delta in register_deltas.deltas {
+        let kind = match delta.key.split(':').next() {
+            Some(k) => k,
+            None => continue,
+        };
+        if delta.new_value == delta.old_value {
+            continue;
+        }
+        let new_term = two.clone().pow(64 - delta.new_value as u32);
+        let old_term = two.clone().pow(64 - delta.old_value as u32);
+        store.add(0, format!("{}:sum", kind), new_term - old_term);
+        if delta.operation == Operation::Create {
+            store.add(0, format!("{}:nonzero", kind), BigInt::one());
+        }
+    }
+}
+
+/// Count payments per recipient in a bounded amount histogram.
+/// Key: {recipient}:{bucket}, see `amount_bucket`
+#[substreams::handlers::store]
+fn store_recipient_amount_histogram(settlements: x402::Settlements, store: StoreAddInt64) {
+    for s in settlements.settlements {
+        if s.recipient.is_empty() || s.recipient == ZERO_ADDR || !is_token_amount(&s) {
+            continue;
+        }
+        let amount = parse_amount(&s.amount);
+        store.add(
+            0,
+            format!("{}:{}", s.recipient.to_lowercase(), amount_bucket(&amount)),
+            1,
+        );
+    }
+}
+
+/// Count proxy-path settlements by type. Keys: "settled", "settled_with_permit"
+#[substreams::handlers::store]
+fn store_proxy_type_count(settlements: x402::Settlements, store: StoreAddInt64) {
+    for s in settlements.settlements {
+        if s.settlement_type == "settled" || s.settlement_type == "settled_with_permit" {
+            store.add(0, &s.settlement_type, 1);
+        }
+    }
+}
+
+/// Whether a matched settlement carried a facilitator fee
+fn has_fee(s: &x402::Settlement) -> bool {
+    parse_amount(&s.fee_amount) > BigInt::zero()
+}
+
+/// Fee-bearing and total matched settlement counts for a block, protocol-wide
+/// and per facilitator. Keys: with_fee, total, {facilitator}:with_fee,
+/// {facilitator}:total
+fn fee_presence_counts(settlements: &[x402::Settlement]) -> Vec<(String, i64)> {
+    let mut counts: Vec<(String, i64)> = Vec::new();
+    let mut add = |key: String| match counts.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((key, 1)),
+    };
+    for s in settlements.iter().filter(|s| !is_unmatched(s)) {
+        let facilitator = s.facilitator.to_lowercase();
+        add("total".to_string());
+        add(format!("{}:total", facilitator));
+        if has_fee(s) {
+            add("with_fee".to_string());
+            add(format!("{}:with_fee", facilitator));
+        }
+    }
+    counts
+}
+
+/// Count matched settlements with and without a fee, protocol-wide and per
+/// facilitator. Keys: see `fee_presence_counts`
+#[substreams::handlers::store]
+fn store_fee_presence_count(settlements: x402::Settlements, store: StoreAddInt64) {
+    for (key, count) in fee_presence_counts(&settlements.settlements) {
+        store.add(0, key, count);
+    }
+}
+
+/// Accumulate estimated gas saved by batching, protocol-wide. Key: "total"
+#[substreams::handlers::store]
+fn store_gas_saved(settlements: x402::Settlements, store: StoreAddInt64) {
+    for (_, count, gas_used) in settlement_batches(&settlements.settlements) {
+        store.add(0, "total", batch_gas_saved(count, gas_used) as i64);
+    }
+}
+
+/// Mark each (recipient, payer) pair the first time it settles.
+/// Key: {recipient}:{payer}
+#[substreams::handlers::store]
+fn store_recipient_payers(settlements: x402::Settlements, store: StoreSetIfNotExistsInt64) {
+    for s in settlements.settlements {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR || s.recipient.is_empty() {
+            continue;
+        }
+        store.set_if_not_exists(
+            0,
+            format!("{}:{}", s.recipient.to_lowercase(), s.payer.to_lowercase()),
+            &1,
+        );
+    }
+}
+
+/// Owners of newly created `{owner}:{member}` dedup markers, one entry per
+/// marker. A set_if_not_exists store only emits Create deltas for new keys.
+fn new_marker_owners(deltas: &[DeltaInt64]) -> Vec<&str> {
+    deltas
+        .iter()
+        .filter(|d| d.operation == Operation::Create)
+        .filter_map(|d| d.key.split(':').next())
+        .collect()
+}
+
+/// Count unique payers per recipient from newly created pair markers.
+/// Key: {recipient_address}
+#[substreams::handlers::store]
+fn store_recipient_unique_payers(pair_deltas: Deltas<DeltaInt64>, store: StoreAddInt64) {
+    for recipient in new_marker_owners(&pair_deltas.deltas) {
+        store.add(0, recipient, 1);
+    }
+}
+
+/// Mark each (payer, token) pair the first time the payer spends the token.
+/// Key: {payer}:{token}
+#[substreams::handlers::store]
+fn store_payer_tokens(settlements: x402::Settlements, store: StoreSetIfNotExistsInt64) {
+    for s in settlements.settlements {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR || s.token.is_empty() {
+            continue;
+        }
+        store.set_if_not_exists(
+            0,
+            format!("{}:{}", s.payer.to_lowercase(), s.token.to_lowercase()),
+            &1,
+        );
+    }
+}
+
+/// Count distinct tokens spent per payer from newly created pair markers.
+/// Key: {payer_address}
+#[substreams::handlers::store]
+fn store_payer_distinct_tokens(token_deltas: Deltas<DeltaInt64>, store: StoreAddInt64) {
+    for payer in new_marker_owners(&token_deltas.deltas) {
+        store.add(0, payer, 1);
+    }
+}
+
+/// `{date}/{nonce}:{authorizer}` marker keys for a block's EIP-3009
+/// settlements, scoping nonce reuse to a UTC day
+fn nonce_authorizer_markers(settlements: &[x402::Settlement], date: &str) -> Vec<String> {
+    settlements
+        .iter()
+        .filter(|s| !s.nonce.is_empty() && !s.payer.is_empty())
+        .map(|s| format!("{}/{}:{}", date, s.nonce.to_lowercase(), s.payer.to_lowercase()))
+        .collect()
+}
+
+/// Mark each (nonce, authorizer) pair the first time it is seen in a UTC day.
+/// Key: {date}/{nonce}:{authorizer}
+#[substreams::handlers::store]
+fn store_nonce_authorizers(settlements: x402::Settlements, store: StoreSetIfNotExistsInt64) {
+    let date = match settlements.block_timestamp.as_ref() {
+        Some(t) => unix_to_date(t.seconds),
+        None => return,
+    };
+    for key in nonce_authorizer_markers(&settlements.settlements, &date) {
+        store.set_if_not_exists(0, key, &1);
+    }
+}
+
+/// Count distinct authorizers per nonce value and UTC day from newly created
+/// markers. Key: {date}/{nonce}
+#[substreams::handlers::store]
+fn store_nonce_authorizer_count(marker_deltas: Deltas<DeltaInt64>, store: StoreAddInt64) {
+    for nonce in new_marker_owners(&marker_deltas.deltas) {
+        store.add(0, nonce, 1);
+    }
+}
+
+/// Days in the rolling payer activity window; one bit per day
+const ACTIVITY_WINDOW_DAYS: i64 = 30;
+
+/// Days since the Unix epoch
+fn unix_day(secs: i64) -> i64 {
+    secs.div_euclid(86400)
+}
+
+/// Record the latest day each payer was active, in a ring of
+/// `ACTIVITY_WINDOW_DAYS` slots so state stays bounded: a slot holds the most
+/// recent day with that offset. Key: {payer}:{day % 30}
+#[substreams::handlers::store]
+fn store_payer_activity_bitmap(
+    settlements: x402::Settlements,
+    store: StoreSetProto<x402::ActivityDay>,
+) {
+    let day = match settlements.block_timestamp.as_ref() {
+        Some(t) => unix_day(t.seconds),
+        None => return,
+    };
+    let slot = day.rem_euclid(ACTIVITY_WINDOW_DAYS);
+    let payers: std::collections::HashSet<String> = settlements
+        .settlements
+        .iter()
+        .filter(|s| !s.payer.is_empty() && s.payer != ZERO_ADDR)
+        .map(|s| s.payer.to_lowercase())
+        .collect();
+    for payer in payers {
+        store.set(0, format!("{}:{}", payer, slot), &x402::ActivityDay { day });
+    }
+}
+
+/// Activity over the window ending on `today`: bit i is set when the payer
+/// was active i days ago. `slot_days` are the ring slots' recorded days;
+/// stale slots (older than the window) are ignored.
+fn activity_bitmap(today: i64, slot_days: &[Option<i64>]) -> u32 {
+    slot_days.iter().flatten().fold(0, |bits, &day| {
+        let ago = today - day;
+        if (0..ACTIVITY_WINDOW_DAYS).contains(&ago) {
+            bits | (1 << ago)
+        } else {
+            bits
+        }
+    })
+}
+
+/// Category of recipients not in the `recipient_categories` param
+const UNCATEGORIZED: &str = "uncategorized";
+
+/// Parse an address-label param such as `recipient_categories` or
+/// `address_labels`: comma-separated `{address}:{label}` entries, e.g.
+/// `0xabc...:api,0xdef...:ai-agent`. Addresses are lowercased; malformed
+/// entries are ignored.
+fn parse_address_labels(value: &str) -> std::collections::HashMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (address, category) = entry.trim().split_once(':')?;
+            let (address, category) = (address.trim(), category.trim());
+            if address.is_empty() || category.is_empty() {
+                return None;
+            }
+            Some((address.to_lowercase(), category.to_string()))
+        })
+        .collect()
+}
+
+/// Matched volume and payment count per recipient category in a block, in
+/// order of first appearance
+fn category_totals(
+    settlements: &[x402::Settlement],
+    categories: &std::collections::HashMap<String, String>,
+) -> Vec<(String, BigInt, i64)> {
+    let mut totals: Vec<(String, BigInt, i64)> = Vec::new();
+    for s in settlements.iter().filter(|s| !is_unmatched(s) && is_token_amount(s)) {
+        let category = categories
+            .get(&s.recipient.to_lowercase())
+            .map(String::as_str)
+            .unwrap_or(UNCATEGORIZED);
+        let amount = parse_amount(&s.amount);
+        match totals.iter_mut().find(|(c, _, _)| c == category) {
+            Some((_, volume, count)) => {
+                *volume = volume.clone() + amount;
+                *count += 1;
+            }
+            None => totals.push((category.to_string(), amount, 1)),
+        }
+    }
+    totals
+}
+
+/// Accumulate volume and payments per recipient category, labeled by the
+/// `recipient_categories` param. Keys: {category}:volume, {category}:count
+#[substreams::handlers::store]
+fn store_category_stats(params: String, settlements: x402::Settlements, store: StoreAddBigInt) {
+    let categories = parse_address_labels(param_value(&params, "recipient_categories").unwrap_or(""));
+    for (category, volume, count) in category_totals(&settlements.settlements, &categories) {
+        store.add(0, format!("{}:volume", category), volume);
+        store.add(0, format!("{}:count", category), BigInt::from(count));
+    }
+}
+
+// =============================================
+// LAYER 3: Analytics
+// =============================================
+
+/// Volume deltas `map_recipient_stats` emits a row for.
+///
+/// Reorgs never reach the maps as undo deltas: the engine rewinds the stores
+/// and re-runs the maps for the new canonical blocks, and the SQL sink undoes
+/// the rows written for the abandoned ones. A `Delete` delta therefore means
+/// the key was removed from the store; its `new_value` is empty, so it is
+/// skipped rather than emitted as a zeroed stat row, and the sink keeps the
+/// last row written for that address.
+fn stat_deltas(deltas: Deltas<DeltaBigInt>) -> impl Iterator<Item = DeltaBigInt> {
+    deltas
+        .deltas
+        .into_iter()
+        .filter(|delta| delta.operation != Operation::Delete)
+}
+
+/// Addresses the payer and facilitator stats maps emit a row for: everyone
+/// with a settlement in the block, once, in order of first appearance.
+///
+/// Rows can't follow the volume deltas alone: a block whose only settlements
+/// for an address are unmatched moves its unmatched count (and so
+/// `total_payments` under `count_unmatched`) without touching its volume.
+fn stat_row_keys(
+    settlements: &[x402::Settlement],
+    key: impl Fn(&x402::Settlement) -> Option<String>,
+) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    settlements
+        .iter()
+        .filter_map(key)
+        .filter(|address| seen.insert(address.clone()))
+        .collect()
+}
+
+/// Compute aggregated payer statistics
+#[substreams::handlers::map]
+fn map_payer_stats(
+    params: String,
+    settlements: x402::Settlements,
+    volume_store: StoreGetBigInt,
+    count_store: StoreGetInt64,
+    first_seen_store: StoreGetInt64,
+    distinct_tokens_store: StoreGetInt64,
+    unmatched_store: StoreGetInt64,
+    baseline_store: StoreGetBigInt,
+    min_payment_store: StoreGetBigInt,
+    max_payment_store: StoreGetBigInt,
+    volume_count_store: StoreGetInt64,
+) -> Result<x402::PayerStats, substreams::errors::Error> {
+    let count_unmatched = param_value(&params, "count_unmatched") == Some("true");
+    let mut stats = x402::PayerStats {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    for payer in stat_row_keys(&settlements.settlements, payer_key) {
+        let baseline = |kind: &str| baseline_store.get_last(format!("{}:{}", kind, payer));
+        let total_payments = stat_count(
+            count_since_reset(count_store.get_last(&payer).unwrap_or(0), baseline("payer_count")),
+            count_since_reset(
+                unmatched_store.get_last(format!("payer:{}", payer)).unwrap_or(0),
+                baseline("unmatched:payer"),
+            ),
+            count_unmatched,
+        );
+        let first_payment_at = first_seen_store
+            .get_last(format!("payer:{}", payer))
+            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
+
+        let distinct_tokens = distinct_tokens_store.get_last(&payer).unwrap_or(0) as u64;
+        let volume = volume_store.get_last(&payer).unwrap_or_else(BigInt::zero);
+        let total_spent = since_reset(&volume, baseline("payer"));
+        // The payments behind total_spent, not total_payments, which can count
+        // unmatched, self and native ETH payments the volume leaves out
+        let volume_payments = count_since_reset(
+            volume_count_store.get_last(&payer).unwrap_or(0),
+            baseline("payer_volume_count"),
+        ) as u64;
+
+        let extreme = |store: &StoreGetBigInt| {
+            store
+                .get_last(format!("payer:{}", payer))
+                .unwrap_or_else(BigInt::zero)
+                .to_string()
+        };
+        let min_payment = extreme(&min_payment_store);
+        let max_payment = extreme(&max_payment_store);
+
+        stats.stats.push(x402::PayerStat {
+            payer_address: payer,
+            avg_payment: avg_payment(&total_spent, volume_payments).to_string(),
+            min_payment,
+            max_payment,
+            total_spent: total_spent.to_string(),
+            total_payments,
+            first_payment_at,
+            last_payment_at: settlements.block_timestamp,
+            distinct_tokens,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Compute aggregated recipient (resource server) statistics
+#[substreams::handlers::map]
+fn map_recipient_stats(
+    settlements: x402::Settlements,
+    volume_deltas: Deltas<DeltaBigInt>,
+    count_store: StoreGetInt64,
+    first_seen_store: StoreGetInt64,
+    baseline_store: StoreGetBigInt,
+    second_payment_store: StoreGetInt64,
+    ewma_store: StoreGetBigInt,
+    unique_payers_store: StoreGetInt64,
+    min_payment_store: StoreGetBigInt,
+    max_payment_store: StoreGetBigInt,
+    volume_count_store: StoreGetInt64,
+) -> Result<x402::RecipientStats, substreams::errors::Error> {
+    let mut stats = x402::RecipientStats {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    for delta in stat_deltas(volume_deltas) {
+        let recipient = delta.key.clone();
+        let baseline = |kind: &str| baseline_store.get_last(format!("{}:{}", kind, recipient));
+        let total_payments = count_since_reset(
+            count_store.get_last(&recipient).unwrap_or(0),
+            baseline("recipient_count"),
+        ) as u64;
+        let unique_payers = unique_payers_store.get_last(&recipient).unwrap_or(0).max(0) as u64;
+        let first_payment_at = first_seen_store
+            .get_last(format!("recipient:{}", recipient))
+            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
+
+        let total_received = since_reset(&delta.new_value, baseline("recipient"));
+        let volume_payments = count_since_reset(
+            volume_count_store.get_last(&recipient).unwrap_or(0),
+            baseline("recipient_volume_count"),
+        ) as u64;
+        let time_to_second_payment_seconds = time_to_second_payment(
+            first_payment_at.as_ref().map(|t| t.seconds),
+            second_payment_store.get_last(&recipient),
+        );
+        let ewma = |key: &str| {
+            ewma_store
+                .get_last(format!("{}:{}", recipient, key))
+                .unwrap_or_else(BigInt::zero)
+        };
+        let ewma_payment_size = ewma_payment_size(&ewma("weighted_amount"), &ewma("weight"));
+        let extreme = |store: &StoreGetBigInt| {
+            store
+                .get_last(format!("recipient:{}", recipient))
+                .unwrap_or_else(BigInt::zero)
+                .to_string()
+        };
+        let min_payment = extreme(&min_payment_store);
+        let max_payment = extreme(&max_payment_store);
+
+        stats.stats.push(x402::RecipientStat {
+            recipient_address: recipient,
+            avg_payment: avg_payment(&total_received, volume_payments).to_string(),
+            min_payment,
+            max_payment,
+            total_received: total_received.to_string(),
+            total_payments,
+            first_payment_at,
+            last_payment_at: settlements.block_timestamp,
+            time_to_second_payment_seconds,
+            ewma_payment_size: ewma_payment_size.to_string(),
+            unique_payers,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Compute facilitator economics, enriched with name and active status from
+/// the FacilitatorRegistry.
+#[substreams::handlers::map]
+fn map_facilitator_stats(
+    params: String,
+    settlements: x402::Settlements,
+    volume_store: StoreGetBigInt,
+    count_store: StoreGetInt64,
+    gas_store: StoreGetBigInt,
+    first_seen_store: StoreGetInt64,
+    registry_store: StoreGetString,
+    gas_usd_store: StoreGetBigInt,
+    unmatched_store: StoreGetInt64,
+    baseline_store: StoreGetBigInt,
+    fee_revenue_store: StoreGetBigInt,
+    eip3009_count_store: StoreGetInt64,
+    proxy_count_store: StoreGetInt64,
+) -> Result<x402::FacilitatorStats, substreams::errors::Error> {
+    let count_unmatched = param_value(&params, "count_unmatched") == Some("true");
+    let mut stats = x402::FacilitatorStats {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    for facilitator in stat_row_keys(&settlements.settlements, facilitator_key) {
+        let baseline = |kind: &str| baseline_store.get_last(format!("{}:{}", kind, facilitator));
+        let count = |store: &StoreGetInt64, key: &str, kind: &str| {
+            count_since_reset(store.get_last(key).unwrap_or(0), baseline(kind))
+        };
+        let total = |store: &StoreGetBigInt, kind: &str| {
+            since_reset(&store.get_last(&facilitator).unwrap_or_else(BigInt::zero), baseline(kind))
+        };
+        let total_settlements = stat_count(
+            count(&count_store, &facilitator, "facilitator_count"),
+            count(
+                &unmatched_store,
+                &format!("facilitator:{}", facilitator),
+                "unmatched:facilitator",
+            ),
+            count_unmatched,
+        );
+        let total_gas = total(&gas_store, "facilitator_gas").to_string();
+        let total_gas_usd = total(&gas_usd_store, "facilitator_gas_usd");
+        let total_fees_earned = total(&fee_revenue_store, "facilitator_fees");
+        let eip3009_count =
+            count(&eip3009_count_store, &facilitator, "facilitator_eip3009") as u64;
+        let proxy_count = count(&proxy_count_store, &facilitator, "facilitator_proxy") as u64;
+        let first_settlement_at = first_seen_store
+            .get_last(format!("facilitator:{}", facilitator))
+            .map(|secs| prost_types::Timestamp { seconds: secs, nanos: 0 });
+
+        // Look up facilitator name and status from registry
+        let (name, url, is_active) = match registry_store.get_last(&facilitator) {
+            Some(val) if !val.is_empty() => {
+                let parts: Vec<&str> = val.splitn(2, '|').collect();
+                let name = parts.first().unwrap_or(&"").to_string();
+                let url = parts.get(1).unwrap_or(&"").to_string();
+                (name, url, true)
+            }
+            Some(_) => (String::new(), String::new(), false), // Removed facilitator
+            None => (String::new(), String::new(), false),     // Unknown facilitator
+        };
+
+        let total_volume_settled = total(&volume_store, "facilitator");
+
+        stats.stats.push(x402::FacilitatorStat {
+            facilitator_address: facilitator,
+            total_settlements,
+            total_volume_settled: total_volume_settled.to_string(),
+            total_gas_spent: total_gas,
+            first_settlement_at,
+            last_settlement_at: settlements.block_timestamp,
+            name,
+            is_active,
+            url,
+            cost_per_dollar_bps: cost_per_dollar_bps(&total_gas_usd, &total_volume_settled),
+            total_gas_spent_usd: total_gas_usd.to_string(),
+            total_fees_earned: total_fees_earned.to_string(),
+            eip3009_count,
+            proxy_count,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Facilitator efficiency: gas spent in USD per dollar of volume settled, in
+/// basis points. Both inputs are 6-decimal USD units; zero volume yields 0.
+fn cost_per_dollar_bps(gas_usd: &BigInt, volume_usd: &BigInt) -> u64 {
+    if *volume_usd <= BigInt::zero() {
+        return 0;
+    }
+    u64::try_from(gas_usd.clone() * BigInt::from(10_000u64) / volume_usd.clone()).unwrap_or(0)
+}
+
+/// `part` as a share of `total`, in basis points, clamped to 0..=10_000.
+/// Zero when total is zero.
+fn share_bps(part: &BigInt, total: &BigInt) -> u64 {
+    if total.is_zero() || *part <= BigInt::zero() {
+        return 0;
+    }
+    if part >= total {
+        return 10_000;
+    }
+    (part.clone() * BigInt::from(10_000u64) / total.clone()).to_u64()
+}
+
+/// Split a recipient amount and facilitator fee into (recipient, facilitator)
+/// basis points of their combined value.
+fn value_split_bps(recipient: &BigInt, fee: &BigInt) -> (u64, u64) {
+    let total = recipient.clone() + fee.clone();
+    if total.is_zero() {
+        return (0, 0);
+    }
+    let facilitator_bps = share_bps(fee, &total);
+    (10_000 - facilitator_bps, facilitator_bps)
+}
+
+/// Compute the take rate of the protocol: per settlement and cumulatively,
+/// how much of the settled value went to the recipient vs the facilitator fee.
+/// The cumulative totals cover USDC only (see `store_value_split`).
+#[substreams::handlers::map]
+fn map_value_split(
+    settlements: x402::Settlements,
+    split_store: StoreGetBigInt,
+) -> Result<x402::ValueSplit, substreams::errors::Error> {
+    let mut split = x402::ValueSplit {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    for s in &settlements.settlements {
+        let amount = parse_amount(&s.amount);
+        let fee = parse_amount(&s.fee_amount);
+        let (recipient_share_bps, facilitator_share_bps) = value_split_bps(&amount, &fee);
+        split.settlements.push(x402::SettlementValueSplit {
+            settlement_id: s.id.clone(),
+            recipient_share_bps,
+            facilitator_share_bps,
+        });
+    }
+
+    if split.settlements.is_empty() {
+        return Ok(split);
+    }
+
+    let recipient_total = split_store.get_last("recipient").unwrap_or_else(BigInt::zero);
+    let facilitator_total = split_store.get_last("facilitator").unwrap_or_else(BigInt::zero);
+    let (recipient_share_bps, facilitator_share_bps) =
+        value_split_bps(&recipient_total, &facilitator_total);
+    split.recipient_total = recipient_total.to_string();
+    split.facilitator_total = facilitator_total.to_string();
+    split.recipient_share_bps = recipient_share_bps;
+    split.facilitator_share_bps = facilitator_share_bps;
+
+    Ok(split)
+}
+
+/// Store key grouping a settlement with others of the same payer, recipient
+/// and amount. None for unmatched settlements, which can't be subscriptions.
+fn subscription_key(s: &x402::Settlement) -> Option<String> {
+    if s.payer.is_empty() || s.payer == ZERO_ADDR || s.recipient.is_empty() || s.amount == "0" {
+        return None;
+    }
+    Some(format!(
+        "{}:{}:{}",
+        s.payer.to_lowercase(),
+        s.recipient.to_lowercase(),
+        s.amount
+    ))
+}
+
+/// Default number of equal payments before a pair counts as a subscription
+const DEFAULT_SUBSCRIPTION_MIN_OCCURRENCES: i64 = 3;
+
+/// Average seconds between payments of a repeated (payer, recipient, amount),
+/// or None until it has occurred at least `min_occurrences` times.
+fn subscription_interval(
+    occurrences: i64,
+    first_ts: i64,
+    last_ts: i64,
+    min_occurrences: i64,
+) -> Option<i64> {
+    if occurrences < min_occurrences.max(2) {
+        return None;
+    }
+    Some((last_ts - first_ts) / (occurrences - 1))
+}
+
+/// Compare how quickly facilitators settle: average and max seconds from
+/// the payer's `valid_after` to the settlement block, per facilitator that
+/// settled a payment with a known valid_after in this block.
+#[substreams::handlers::map]
+fn map_facilitator_latency(
+    settlements: x402::Settlements,
+    latency_store: StoreGetInt64,
+    latency_max_store: StoreGetInt64,
+) -> Result<x402::FacilitatorLatencies, substreams::errors::Error> {
+    let mut latencies = x402::FacilitatorLatencies {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    let block_ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return Ok(latencies),
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    for s in &settlements.settlements {
+        let facilitator = s.facilitator.to_lowercase();
+        if settlement_latency(block_ts, s.valid_after).is_none()
+            || !seen.insert(facilitator.clone())
+        {
+            continue;
+        }
+        let total = latency_store
+            .get_last(format!("{}:total", facilitator))
+            .unwrap_or(0);
+        let samples = latency_store
+            .get_last(format!("{}:count", facilitator))
+            .unwrap_or(0);
+        if samples <= 0 {
+            continue;
+        }
+        latencies.facilitators.push(x402::FacilitatorLatency {
+            avg_latency_seconds: total / samples,
+            max_latency_seconds: latency_max_store.get_last(&facilitator).unwrap_or(0),
+            samples: samples as u64,
+            facilitator_address: facilitator,
+        });
+    }
+
+    Ok(latencies)
+}
+
+/// Average latency (seconds) at which the SLA latency score halves
+const SLA_LATENCY_REF_SECONDS: u64 = 60;
+/// Gas cost per dollar (bps) at which the SLA gas score halves
+const SLA_GAS_REF_BPS: u64 = 10;
+
+/// A 0..=10000 score that is 10000 at zero and halves at `reference`
+fn halving_score(value: u64, reference: u64) -> u64 {
+    10_000 * reference / (reference + value)
+}
+
+/// Composite SLA score for a facilitator, each component in bps:
+/// - success: matched settlements / all settlements (10000 with none)
+/// - latency: 10000 * 60 / (60 + avg latency s); 5000 without samples
+/// - gas: 10000 * 10 / (10 + gas cost per dollar bps)
+///
+/// sla = (5 * success + 3 * latency + 2 * gas) / 10
+fn facilitator_sla(
+    facilitator_address: String,
+    matched: u64,
+    unmatched: u64,
+    avg_latency_seconds: Option<u64>,
+    cost_per_dollar_bps: u64,
+) -> x402::FacilitatorSla {
+    let total = matched + unmatched;
+    let success_bps = (matched * 10_000).checked_div(total).unwrap_or(10_000);
+    let latency_score_bps = avg_latency_seconds
+        .map(|avg| halving_score(avg, SLA_LATENCY_REF_SECONDS))
+        .unwrap_or(5_000);
+    let gas_score_bps = halving_score(cost_per_dollar_bps, SLA_GAS_REF_BPS);
+    x402::FacilitatorSla {
+        facilitator_address,
+        sla_score_bps: (5 * success_bps + 3 * latency_score_bps + 2 * gas_score_bps) / 10,
+        success_bps,
+        latency_score_bps,
+        gas_score_bps,
+    }
+}
+
+/// Emit a composite SLA score per facilitator settling this block, combining
+/// success rate, latency and gas efficiency (see `facilitator_sla`), so
+/// integrators can compare facilitators on one number.
+#[substreams::handlers::map]
+fn map_facilitator_sla(
+    settlements: x402::Settlements,
+    count_store: StoreGetInt64,
+    unmatched_store: StoreGetInt64,
+    latency_store: StoreGetInt64,
+    gas_usd_store: StoreGetBigInt,
+    volume_store: StoreGetBigInt,
+) -> Result<x402::FacilitatorSlas, substreams::errors::Error> {
+    let mut slas = x402::FacilitatorSlas {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    for s in &settlements.settlements {
+        let facilitator = s.facilitator.to_lowercase();
+        if facilitator.is_empty() || !seen.insert(facilitator.clone()) {
+            continue;
+        }
+        let matched = count_store.get_last(&facilitator).unwrap_or(0).max(0) as u64;
+        let unmatched = unmatched_store
+            .get_last(format!("facilitator:{}", facilitator))
+            .unwrap_or(0)
+            .max(0) as u64;
+        let latency = |key: &str| {
+            latency_store
+                .get_last(format!("{}:{}", facilitator, key))
+                .unwrap_or(0)
+        };
+        let avg_latency = latency("total")
+            .checked_div(latency("count"))
+            .map(|avg| avg.max(0) as u64);
+        let cost_bps = cost_per_dollar_bps(
+            &gas_usd_store.get_last(&facilitator).unwrap_or_else(BigInt::zero),
+            &volume_store.get_last(&facilitator).unwrap_or_else(BigInt::zero),
+        );
+        slas.facilitators
+            .push(facilitator_sla(facilitator, matched, unmatched, avg_latency, cost_bps));
+    }
+
+    Ok(slas)
+}
+
+/// Share of settlements carrying a fee, in basis points
+fn fee_prevalence_bps(with_fee: i64, total: i64) -> u64 {
+    share_bps(&BigInt::from(with_fee), &BigInt::from(total))
+}
+
+/// Track how common facilitator fees are: the cumulative share of matched
+/// settlements with a fee, protocol-wide and for each facilitator active in
+/// this block.
+#[substreams::handlers::map]
+fn map_fee_prevalence(
+    settlements: x402::Settlements,
+    fee_presence_store: StoreGetInt64,
+) -> Result<x402::FeePrevalence, substreams::errors::Error> {
+    let mut prevalence = x402::FeePrevalence {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    if settlements.settlements.iter().all(is_unmatched) {
+        return Ok(prevalence);
+    }
+
+    let count = |key: &str| fee_presence_store.get_last(key).unwrap_or(0);
+    let (with_fee, total) = (count("with_fee"), count("total"));
+    prevalence.with_fee = with_fee.max(0) as u64;
+    prevalence.total = total.max(0) as u64;
+    prevalence.fee_prevalence_bps = fee_prevalence_bps(with_fee, total);
+
+    let mut seen = std::collections::HashSet::new();
+    for s in settlements.settlements.iter().filter(|s| !is_unmatched(s)) {
+        let facilitator = s.facilitator.to_lowercase();
+        if !seen.insert(facilitator.clone()) {
+            continue;
+        }
+        let with_fee = count(&format!("{}:with_fee", facilitator));
+        let total = count(&format!("{}:total", facilitator));
+        prevalence.facilitators.push(x402::FacilitatorFeePrevalence {
+            facilitator_address: facilitator,
+            with_fee: with_fee.max(0) as u64,
+            total: total.max(0) as u64,
+            fee_prevalence_bps: fee_prevalence_bps(with_fee, total),
+        });
+    }
+
+    Ok(prevalence)
+}
+
+/// Share of proxy settlements using SettledWithPermit, in basis points
+fn permit_share_bps(settled: i64, settled_with_permit: i64) -> u64 {
+    share_bps(
+        &BigInt::from(settled_with_permit),
+        &BigInt::from(settled + settled_with_permit),
+    )
+}
+
+/// Track Permit2 permit adoption in the proxy path: cumulative Settled vs
+/// SettledWithPermit counts and the permit share.
+#[substreams::handlers::map]
+fn map_proxy_type_mix(
+    settlements: x402::Settlements,
+    proxy_type_store: StoreGetInt64,
+) -> Result<x402::ProxyTypeMix, substreams::errors::Error> {
+    let mut mix = x402::ProxyTypeMix {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let has_proxy = settlements
+        .settlements
+        .iter()
+        .any(|s| s.settlement_type == "settled" || s.settlement_type == "settled_with_permit");
+    if !has_proxy {
+        return Ok(mix);
+    }
+
+    let settled = proxy_type_store.get_last("settled").unwrap_or(0);
+    let settled_with_permit = proxy_type_store.get_last("settled_with_permit").unwrap_or(0);
+    mix.settled = settled as u64;
+    mix.settled_with_permit = settled_with_permit as u64;
+    mix.permit_share_bps = permit_share_bps(settled, settled_with_permit);
+
+    Ok(mix)
+}
+
+/// Detect subscription-like renewals: the same payer paying the same recipient
+/// the same amount repeatedly. Emits the pair once it reaches the
+/// `min_occurrences` param (default 3), with the average interval between
+/// payments measured from the pair's first payment to this block.
+#[substreams::handlers::map]
+fn map_subscriptions(
+    params: String,
+    settlements: x402::Settlements,
+    pair_count_store: StoreGetInt64,
+    first_seen_store: StoreGetInt64,
+) -> Result<x402::Subscriptions, substreams::errors::Error> {
+    let mut subscriptions = x402::Subscriptions {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let min_occurrences = param_value(&params, "min_occurrences")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SUBSCRIPTION_MIN_OCCURRENCES);
+    let block_ts = match settlements.block_timestamp.as_ref() {
+        Some(t) => t.seconds,
+        None => return Ok(subscriptions),
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    for s in &settlements.settlements {
+        let key = match subscription_key(s) {
+            Some(k) if seen.insert(k.clone()) => k,
+            _ => continue,
+        };
+        let occurrences = pair_count_store.get_last(&key).unwrap_or(0);
+        let first_ts = first_seen_store
+            .get_last(format!("subscription:{}", key))
+            .unwrap_or(block_ts);
+
+        if let Some(avg_interval) =
+            subscription_interval(occurrences, first_ts, block_ts, min_occurrences)
+        {
+            subscriptions.subscriptions.push(x402::Subscription {
+                payer: s.payer.clone(),
+                recipient: s.recipient.clone(),
+                amount: s.amount.clone(),
+                occurrences: occurrences as u64,
+                avg_interval,
+                last_payment_at: settlements.block_timestamp,
+            });
+        }
+    }
+
+    Ok(subscriptions)
+}
+
+/// Buckets in the per-recipient amount histogram
+const AMOUNT_HISTOGRAM_BUCKETS: u64 = 48;
+
+/// Histogram bucket of an amount: its bit length (power-of-two bins), capped
+/// so amounts of 2^47 atomic units (~$140M USDC) and above share the last bucket.
+fn amount_bucket(amount: &BigInt) -> u64 {
+    if *amount <= BigInt::zero() {
+        return 0;
+    }
+    (amount.bits() as u64).min(AMOUNT_HISTOGRAM_BUCKETS - 1)
+}
+
+/// Amount variability score: Shannon entropy of the histogram normalized to
+/// 0..=10000 bps. 0 means every payment fell in one bucket (flat fee);
+/// higher means amounts vary (metered billing).
+fn amount_variability_bps(counts: &[i64]) -> u64 {
+    let total: i64 = counts.iter().filter(|c| **c > 0).sum();
+    if total <= 0 {
+        return 0;
+    }
+    let entropy: f64 = counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum();
+    let max_entropy = (AMOUNT_HISTOGRAM_BUCKETS as f64).log2();
+    ((entropy / max_entropy) * 10_000.0).round().clamp(0.0, 10_000.0) as u64
+}
+
+/// Score how varied each paid recipient's payment amounts are, to tell
+/// metered (varied) from flat-fee (uniform) billing.
+#[substreams::handlers::map]
+fn map_recipient_amount_entropy(
+    settlements: x402::Settlements,
+    histogram_store: StoreGetInt64,
+) -> Result<x402::RecipientAmountEntropies, substreams::errors::Error> {
+    let mut entropies = x402::RecipientAmountEntropies {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    for s in &settlements.settlements {
+        let recipient = s.recipient.to_lowercase();
+        if recipient.is_empty() || recipient == ZERO_ADDR || !seen.insert(recipient.clone()) {
+            continue;
+        }
+        let counts: Vec<i64> = (0..AMOUNT_HISTOGRAM_BUCKETS)
+            .map(|b| {
+                histogram_store
+                    .get_last(format!("{}:{}", recipient, b))
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        entropies.recipients.push(x402::RecipientAmountEntropy {
+            amount_variability: amount_variability_bps(&counts),
+            total_payments: counts.iter().sum::<i64>() as u64,
+            recipient_address: recipient,
+        });
+    }
+
+    Ok(entropies)
+}
+
+/// Gas a payment would cost as its own on-chain transaction. This is the
+/// 21,000 intrinsic gas of a plain transfer, a lower bound: a standalone
+/// ERC-20 payment costs more, so savings estimated against it are conservative.
+const BASELINE_TX_GAS: u64 = 21_000;
+
+/// Group settlements into batches by transaction, in order of first
+/// appearance. Yields (tx_hash, settlement_count, tx_gas_used).
+fn settlement_batches(settlements: &[x402::Settlement]) -> Vec<(String, u64, u64)> {
+    let mut batches: Vec<(String, u64, u64)> = Vec::new();
+    for s in settlements {
+        match batches.iter_mut().find(|(tx, _, _)| *tx == s.tx_hash) {
+            Some(batch) => batch.1 += 1,
+            None => batches.push((s.tx_hash.clone(), 1, s.gas_used.parse().unwrap_or(0))),
+        }
+    }
+    batches
+}
+
+/// Estimated gas saved by settling `count` payments in one transaction
+/// instead of `count` baseline transactions. Never negative.
+fn batch_gas_saved(count: u64, gas_used: u64) -> u64 {
+    (count * BASELINE_TX_GAS).saturating_sub(gas_used)
+}
+
+/// Estimate gas saved by batched settlements versus one on-chain transaction
+/// per payment (BASELINE_TX_GAS each), per batch and cumulatively.
+#[substreams::handlers::map]
+fn map_gas_savings(
+    settlements: x402::Settlements,
+    gas_saved_store: StoreGetInt64,
+) -> Result<x402::GasSavings, substreams::errors::Error> {
+    let batches: Vec<_> = settlement_batches(&settlements.settlements)
+        .into_iter()
+        .map(|(tx_hash, count, gas_used)| x402::BatchGasSavings {
+            tx_hash,
+            settlement_count: count,
+            gas_used,
+            gas_saved: batch_gas_saved(count, gas_used),
+        })
+        .collect();
+
+    Ok(x402::GasSavings {
+        block_number: settlements.block_number,
+        total_gas_saved: gas_saved_store.get_last("total").unwrap_or(0) as u64,
+        batches,
+    })
+}
+
+/// Settlements in single-settlement txs, settlements in batch txs, and the
+/// largest batch size
+fn batching_counts(settlements: &[x402::Settlement]) -> (u64, u64, u64) {
+    let mut counts = (0, 0, 0);
+    for (_, count, _) in settlement_batches(settlements) {
+        if count == 1 {
+            counts.0 += 1;
+        } else {
+            counts.1 += count;
+        }
+        counts.2 = counts.2.max(count);
+    }
+    counts
+}
+
+/// Count the block's settlements by whether their transaction settled one
+/// payment or a batch, to follow facilitator batching behavior.
+#[substreams::handlers::map]
+fn map_batching_stats(
+    settlements: x402::Settlements,
+) -> Result<x402::BatchingStats, substreams::errors::Error> {
+    let (single_count, batched_count, largest_batch) = batching_counts(&settlements.settlements);
+    Ok(x402::BatchingStats {
+        block_number: settlements.block_number,
+        single_count,
+        batched_count,
+        largest_batch,
+    })
+}
+
+/// Default number of facilitators ranked by `map_ranked_settlements`
+const DEFAULT_FACILITATOR_TOP_K: usize = 10;
+
+/// Rank facilitators by cumulative volume, descending, keeping the top `k`
+/// as (facilitator, 1-based rank). Ties are broken by address.
+fn top_k_facilitator_ranks(mut volumes: Vec<(String, BigInt)>, k: usize) -> Vec<(String, u32)> {
+    volumes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    volumes
+        .into_iter()
+        .take(k)
+        .enumerate()
+        .map(|(i, (facilitator, _))| (facilitator, i as u32 + 1))
+        .collect()
+}
+
+/// Set each settlement's `facilitator_rank` from the top-K ranking; 0 for
+/// facilitators outside it
+fn apply_facilitator_ranks(settlements: &mut [x402::Settlement], ranks: &[(String, u32)]) {
+    for s in settlements {
+        let facilitator = s.facilitator.to_lowercase();
+        s.facilitator_rank = ranks
+            .iter()
+            .find(|(f, _)| *f == facilitator)
+            .map(|(_, rank)| *rank)
+            .unwrap_or(0);
+    }
+}
+
+/// Re-emit the block's settlements annotated with their facilitator's
+/// cumulative volume rank, for leaderboard-aware UIs. Only the top `top_k`
+/// (param, default 10) are ranked; others carry rank 0 (">K"). Ranking reads
+/// every facilitator's volume, which stays cheap since facilitators are few.
+#[substreams::handlers::map]
+fn map_ranked_settlements(
+    params: String,
+    settlements: x402::Settlements,
+    roster_store: StoreGetArray<String>,
+    volume_store: StoreGetBigInt,
+) -> Result<x402::Settlements, substreams::errors::Error> {
+    let mut settlements = settlements;
+    if settlements.settlements.is_empty() {
+        return Ok(settlements);
+    }
+    let top_k = param_value(&params, "top_k")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FACILITATOR_TOP_K);
+
+    let volumes = roster_store
+        .get_last("all")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| {
+            let volume = volume_store.get_last(&f).unwrap_or_else(BigInt::zero);
+            (f, volume)
+        })
+        .collect();
+    let ranks = top_k_facilitator_ranks(volumes, top_k);
+    apply_facilitator_ranks(&mut settlements.settlements, &ranks);
+
+    Ok(settlements)
+}
+
+/// Default number of recipients emitted by `map_top_recipients_by_reach`
+const DEFAULT_TOP_N: usize = 10;
+
+/// Rank recipients by unique payers, descending, keeping the top `n`.
+/// Ties are broken by address so the ranking is deterministic.
+fn top_by_reach(mut reach: Vec<(String, i64)>, n: usize) -> Vec<(String, i64)> {
+    reach.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    reach.truncate(n);
+    reach
+}
+
+/// Rank recipients by reach (unique payers) rather than volume, surfacing
+/// broadly adopted resources, and emit the all-time top `top_n` (param,
+/// default 10). Like `map_leaderboard`, only the recipients in the top reach
+/// tiers are re-ranked, and blocks without settlements emit nothing.
+#[substreams::handlers::map]
+fn map_top_recipients_by_reach(
+    params: String,
+    settlements: x402::Settlements,
+    tier_count_store: StoreGetInt64,
+    roster_store: StoreGetArray<String>,
+    unique_payers_store: StoreGetInt64,
+) -> Result<x402::RecipientsReach, substreams::errors::Error> {
+    let top_n = param_value(&params, "top_n")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TOP_N);
+
+    let reach = if settlements.settlements.is_empty() {
+        vec![]
+    } else {
+        leaderboard_candidates("reach", top_n, &tier_count_store, &roster_store)
+            .into_iter()
+            .map(|recipient| {
+                let unique_payers = unique_payers_store.get_last(&recipient).unwrap_or(0);
+                (recipient, unique_payers)
+            })
+            .collect()
+    };
+
+    let recipients = top_by_reach(reach, top_n)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (recipient, unique_payers))| x402::RecipientReach {
+            recipient_address: recipient,
+            unique_payers: unique_payers as u64,
+            rank: i as u32 + 1,
+        })
+        .collect();
+
+    Ok(x402::RecipientsReach {
+        recipients,
+        block_number: settlements.block_number,
+    })
+}
+
+/// Default number of addresses per role emitted by `map_leaderboard`
+const DEFAULT_LEADERBOARD_SIZE: usize = 25;
+
+/// Rank a role's addresses by cumulative volume, descending, keeping the top
+/// `n`. Ties are broken by address so the ranking is deterministic.
+fn leaderboard_entries(
+    role: &str,
+    mut volumes: Vec<(String, BigInt)>,
+    n: usize,
+) -> Vec<x402::LeaderboardEntry> {
+    volumes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    volumes
+        .into_iter()
+        .take(n)
+        .enumerate()
+        .map(|(i, (address, volume))| x402::LeaderboardEntry {
+            rank: i as u32 + 1,
+            address,
+            role: role.to_string(),
+            total_volume: volume.to_string(),
+        })
+        .collect()
+}
+
+/// Roster entries a leaderboard may read per ranked address: a role's read
+/// stops before a tier that would take it past `LEADERBOARD_READ_FACTOR * n`
+const LEADERBOARD_READ_FACTOR: usize = 8;
+
+/// The highest volume tiers that together hold at least `n` addresses, from
+/// the top down. Every address below them has less volume than all `n`, so
+/// only these tiers can place on the leaderboard.
+///
+/// Rosters are append-only, so a tier's roster (`listed`) also keeps every
+/// address that has since moved up, and a low boundary tier can list most
+/// addresses ever seen. The walk stops before a tier whose roster would take
+/// the read past `LEADERBOARD_READ_FACTOR * n` entries, which leaves the
+/// leaderboard short (the tiers above still rank exactly) instead of reading
+/// it. The first occupied tier is always read, so the worst case is one
+/// tier's roster.
+fn leaderboard_candidate_tiers(
+    tier_count: impl Fn(u64) -> i64,
+    listed: impl Fn(u64) -> i64,
+    n: usize,
+) -> Vec<u64> {
+    let read_limit = n.saturating_mul(LEADERBOARD_READ_FACTOR);
+    let mut tiers = Vec::new();
+    let mut covered = 0usize;
+    let mut read = 0usize;
+    for tier in (0..LEADERBOARD_TIERS).rev() {
+        if covered >= n {
+            break;
+        }
+        let count = tier_count(tier);
+        if count <= 0 {
+            continue;
+        }
+        let roster_len = listed(tier).max(0) as usize;
+        if !tiers.is_empty() && read + roster_len > read_limit {
+            break;
+        }
+        tiers.push(tier);
+        covered += count as usize;
+        read += roster_len;
+    }
+    tiers
+}
+
+/// Every address listed in a role's top tiers covering `n` addresses, once:
+/// the only ones that can rank in its top `n` (see `leaderboard_candidate_tiers`)
+fn leaderboard_candidates(
+    role: &str,
+    n: usize,
+    tier_count_store: &StoreGetInt64,
+    roster_store: &StoreGetArray<String>,
+) -> Vec<String> {
+    let tiers = leaderboard_candidate_tiers(
+        |tier| {
+            tier_count_store
+                .get_last(format!("{}:{}", role, tier))
+                .unwrap_or(0)
+        },
+        |tier| {
+            tier_count_store
+                .get_last(format!("listed:{}:{}", role, tier))
+                .unwrap_or(0)
+        },
+        n,
+    );
+    let mut seen = std::collections::HashSet::new();
+    let mut candidates: Vec<String> = Vec::new();
+    for tier in tiers {
+        let roster = roster_store
+            .get_last(format!("{}:{}", role, tier))
+            .unwrap_or_default();
+        for address in roster {
+            if seen.insert(address.clone()) {
+                candidates.push(address);
+            }
+        }
+    }
+    candidates
+}
+
+/// Emit the current top `top_n` (param, default 25) payers and recipients by
+/// cumulative volume.
+///
+/// The volume deltas only cover addresses touched in this block, and an
+/// untouched address can still be pushed down a rank by one that was, so
+/// ranking the deltas alone would drift. A map can't keep the previous
+/// leaderboard either: feeding it back through a store would be a cycle.
+/// Instead addresses are bucketed into volume tiers
+/// (`store_leaderboard_tier_count`, `store_leaderboard_roster`), and each
+/// block with settlements re-ranks only the top tiers that cover `top_n`
+/// addresses, which already hold this block's touched addresses. The read is
+/// capped at `LEADERBOARD_READ_FACTOR * top_n` roster entries past the first
+/// occupied tier; a boundary tier over the cap is left out, shortening the
+/// leaderboard rather than reading every address (see
+/// `leaderboard_candidate_tiers`).
+/// Blocks without settlements emit nothing, as the ranking can't have changed.
+#[substreams::handlers::map]
+fn map_leaderboard(
+    params: String,
+    settlements: x402::Settlements,
+    tier_count_store: StoreGetInt64,
+    roster_store: StoreGetArray<String>,
+    payer_volume_store: StoreGetBigInt,
+    recipient_volume_store: StoreGetBigInt,
+) -> Result<x402::Leaderboard, substreams::errors::Error> {
+    let mut leaderboard = x402::Leaderboard {
+        entries: vec![],
+        block_number: settlements.block_number,
+    };
+    if settlements.settlements.is_empty() {
+        return Ok(leaderboard);
+    }
+    let top_n = param_value(&params, "top_n")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LEADERBOARD_SIZE);
+
+    for (role, volume_store) in [
+        ("payer", &payer_volume_store),
+        ("recipient", &recipient_volume_store),
+    ] {
+        let volumes = leaderboard_candidates(role, top_n, &tier_count_store, &roster_store)
+            .into_iter()
+            .map(|address| {
+                let volume = volume_store.get_last(&address).unwrap_or_else(BigInt::zero);
+                (address, volume)
+            })
+            .collect();
+        leaderboard.entries.extend(leaderboard_entries(role, volumes, top_n));
+    }
+
+    Ok(leaderboard)
+}
+
+/// HyperLogLog precision: 2^10 = 1024 registers per counted kind
+const HLL_PRECISION: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// 64-bit hash for HLL: FNV-1a followed by the murmur3 finalizer, so
+/// similar inputs (sequential addresses) still spread evenly.
+fn hll_hash(value: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in value.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+/// HLL register index and rank (1-based position of the first set bit after
+/// the index bits) for a value.
+fn hll_observe(value: &str) -> (usize, u32) {
+    let h = hll_hash(value);
+    let register = (h >> (64 - HLL_PRECISION)) as usize;
+    let rank = ((h << HLL_PRECISION).leading_zeros() + 1).min(64 - HLL_PRECISION + 1);
+    (register, rank)
+}
+
+/// HLL cardinality estimate from the harmonic sum of 2^-register over all
+/// registers and the number of zero registers, with the small-range
+/// (linear counting) correction.
+///
+/// Standard error is 1.04 / sqrt(HLL_REGISTERS), about 3.25%: roughly two
+/// in three estimates are within 3.25% of the true count, and nearly all
+/// within 10%.
+fn hll_estimate(harmonic_sum: f64, zero_registers: usize) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let estimate = alpha * m * m / harmonic_sum;
+    if estimate <= 2.5 * m && zero_registers > 0 {
+        return (m * (m / zero_registers as f64).ln()).round() as u64;
+    }
+    estimate.round() as u64
+}
+
+/// Approximate distinct count for a kind from the incremental HLL sums
+fn hll_count(sums_store: &StoreGetBigInt, kind: &str) -> u64 {
+    let scaled = sums_store
+        .get_last(format!("{}:sum", kind))
+        .unwrap_or_else(BigInt::zero);
+    let nonzero = sums_store
+        .get_last(format!("{}:nonzero", kind))
+        .and_then(|n| usize::try_from(u64::try_from(n).ok()?).ok())
+        .unwrap_or(0);
+    if nonzero == 0 {
+        return 0;
+    }
+    // Untouched registers are 0, each contributing 2^-0 = 1
+    let delta = scaled.to_string().parse::<f64>().unwrap_or(0.0) / 2f64.powi(64);
+    hll_estimate(HLL_REGISTERS as f64 + delta, HLL_REGISTERS.saturating_sub(nonzero))
+}
+
+/// Width of a fee rate histogram bucket, in bps
+const FEE_RATE_BUCKET_BPS: u64 = 25;
+/// Fee rate buckets: 0-25 bps up to 1000 bps (10%), then one bucket above
+const FEE_RATE_BUCKETS: u64 = 41;
+
+/// Histogram bucket of a fee rate
+fn fee_rate_bucket(bps: u64) -> u64 {
+    (bps / FEE_RATE_BUCKET_BPS).min(FEE_RATE_BUCKETS - 1)
+}
+
+/// Approximate median fee rate from the bucket counts: the lower bound of
+/// the bucket holding the median settlement. None with no settlements.
+fn histogram_median_bps(counts: &[i64]) -> Option<u64> {
+    let total: i64 = counts.iter().filter(|c| **c > 0).sum();
+    if total <= 0 {
+        return None;
+    }
+    let mut seen = 0;
+    for (bucket, count) in counts.iter().enumerate() {
+        seen += (*count).max(0);
+        if seen * 2 >= total {
+            return Some(bucket as u64 * FEE_RATE_BUCKET_BPS);
+        }
+    }
+    None
+}
+
+/// Flag facilitators pricing below the protocol: average fee rate below the
+/// median's bucket, so undercutting is clear rather than a rounding artifact.
+fn is_low_fee(avg_fee_bps: u64, median_fee_bps: Option<u64>) -> bool {
+    median_fee_bps.is_some_and(|median| avg_fee_bps < median)
+}
+
+/// Compare each active facilitator's average fee rate with the protocol-wide
+/// median (from the fee rate histogram) and flag those below it, for
+/// competitive analysis of aggressive pricing.
+#[substreams::handlers::map]
+fn map_fee_competition(
+    settlements: x402::Settlements,
+    fee_rate_store: StoreGetInt64,
+    histogram_store: StoreGetInt64,
+) -> Result<x402::FeeCompetition, substreams::errors::Error> {
+    let mut competition = x402::FeeCompetition {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    if !settlements.settlements.iter().any(|s| settlement_fee_bps(s).is_some()) {
+        return Ok(competition);
+    }
+
+    let counts: Vec<i64> = (0..FEE_RATE_BUCKETS)
+        .map(|b| histogram_store.get_last(b.to_string()).unwrap_or(0))
+        .collect();
+    let median = histogram_median_bps(&counts);
+    competition.median_fee_bps = median.unwrap_or(0);
+
+    let mut seen = std::collections::HashSet::new();
+    for s in &settlements.settlements {
+        let facilitator = s.facilitator.to_lowercase();
+        if settlement_fee_bps(s).is_none() || !seen.insert(facilitator.clone()) {
+            continue;
+        }
+        let total = fee_rate_store
+            .get_last(format!("{}:total_bps", facilitator))
+            .unwrap_or(0);
+        let samples = fee_rate_store
+            .get_last(format!("{}:count", facilitator))
+            .unwrap_or(0);
+        if samples <= 0 {
+            continue;
+        }
+        let avg_fee_bps = (total / samples) as u64;
+        competition.facilitators.push(x402::FacilitatorFeeRate {
+            facilitator_address: facilitator,
+            avg_fee_bps,
+            samples: samples as u64,
+            low_fee_facilitator: is_low_fee(avg_fee_bps, median),
+        });
+    }
+
+    Ok(competition)
+}
+
+/// Approximate Gini coefficient of payer spend in bps (0 = everyone spent
+/// the same, 10000 = one payer spent everything), from (payers, spend) per
+/// spend tier in ascending tier order.
+///
+/// Payers within a tier are treated as spending equally, so this is a lower
+/// bound on the exact Gini; power-of-two tiers keep the gap small.
+fn tier_gini_bps(tiers: &[(f64, f64)]) -> u64 {
+    let payers: f64 = tiers.iter().map(|(n, _)| n).sum();
+    let spend: f64 = tiers.iter().map(|(_, v)| v).sum();
+    if payers <= 0.0 || spend <= 0.0 {
+        return 0;
+    }
+    // 1 - sum of each tier's population share times the trapezoid of the
+    // Lorenz curve over that tier
+    let mut lorenz = 0.0;
+    let mut area = 0.0;
+    for (n, v) in tiers {
+        let next = lorenz + v / spend;
+        area += (n / payers) * (lorenz + next);
+        lorenz = next;
+    }
+    ((1.0 - area) * 10_000.0).round().clamp(0.0, 10_000.0) as u64
+}
+
+/// Compute the approximate Gini coefficient of cumulative payer spend from
+/// the spend-tier histogram, for concentration analysis of the payer base.
+/// Payers with zero spend are excluded.
+#[substreams::handlers::map]
+fn map_payer_gini(
+    settlements: x402::Settlements,
+    tiers_store: StoreGetBigInt,
+) -> Result<x402::PayerGini, substreams::errors::Error> {
+    let value = |key: String| {
+        tiers_store
+            .get_last(key)
+            .unwrap_or_else(BigInt::zero)
+            .to_string()
+            .parse::<f64>()
+            .unwrap_or(0.0)
+    };
+    let tiers: Vec<(f64, f64)> = (1..AMOUNT_HISTOGRAM_BUCKETS)
+        .map(|tier| (value(format!("count:{}", tier)), value(format!("spend:{}", tier))))
+        .collect();
+
+    Ok(x402::PayerGini {
+        block_number: settlements.block_number,
+        gini_bps: tier_gini_bps(&tiers),
+    })
+}
+
+/// Protocol subsidy rate: the gas facilitators pay on payers' behalf, in USD,
+/// as a share of total volume settled, in basis points.
+#[substreams::handlers::map]
+fn map_subsidy_rate(
+    settlements: x402::Settlements,
+    gas_usd_store: StoreGetBigInt,
+    totals_store: StoreGetBigInt,
+) -> Result<x402::SubsidyRate, substreams::errors::Error> {
+    let total_gas_usd = gas_usd_store.get_last("gas_usd").unwrap_or_else(BigInt::zero);
+    let total_volume = totals_store.get_last("volume").unwrap_or_else(BigInt::zero);
+    Ok(x402::SubsidyRate {
+        block_number: settlements.block_number,
+        subsidy_rate_bps: cost_per_dollar_bps(&total_gas_usd, &total_volume),
+        total_gas_usd: total_gas_usd.to_string(),
+    })
+}
+
+/// Emit the protocol-wide totals as of this block, on every block so the
+/// sink can snapshot them on its own cadence. Unique payer and recipient
+/// counts are HyperLogLog approximations with bounded state.
+#[substreams::handlers::map]
+fn map_protocol_totals(
+    settlements: x402::Settlements,
+    payer_gini: x402::PayerGini,
+    subsidy_rate: x402::SubsidyRate,
+    totals_store: StoreGetBigInt,
+    hll_sums_store: StoreGetBigInt,
+) -> Result<x402::ProtocolTotals, substreams::errors::Error> {
+    let total = |key: &str| totals_store.get_last(key).unwrap_or_else(BigInt::zero);
+    Ok(x402::ProtocolTotals {
+        block_number: settlements.block_number,
+        total_volume: total("volume").to_string(),
+        total_fees: total("fees").to_string(),
+        total_settlements: u64::try_from(total("settlements")).unwrap_or(0),
+        approx_unique_payers: hll_count(&hll_sums_store, "payer"),
+        approx_unique_recipients: hll_count(&hll_sums_store, "recipient"),
+        payer_gini_bps: payer_gini.gini_bps,
+        subsidy_rate_bps: subsidy_rate.subsidy_rate_bps,
+    })
+}
+
+/// Emit the day's volume split into new-payer vs returning-payer volume,
+/// showing whether growth is acquisition- or retention-driven.
+#[substreams::handlers::map]
+fn map_new_vs_returning(
+    settlements: x402::Settlements,
+    daily_store: StoreGetBigInt,
+) -> Result<x402::NewVsReturning, substreams::errors::Error> {
+    let mut split = x402::NewVsReturning {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) if !settlements.settlements.is_empty() => t.seconds,
+        _ => return Ok(split),
+    };
+
+    let date = unix_to_date(ts);
+    let volume = |cohort: &str| {
+        daily_store
+            .get_last(format!("{}:{}", date, cohort))
+            .unwrap_or_else(BigInt::zero)
+            .to_string()
+    };
+    split.new_volume = volume("new");
+    split.returning_volume = volume("returning");
+    split.date = date;
+
+    Ok(split)
+}
+
+/// Blocks built on top of a settlement's block, given the head block.
+/// Zero for the head block itself (and if the head is somehow behind).
+fn confirmations(head_block: u64, block_number: u64) -> u64 {
+    head_block.saturating_sub(block_number)
+}
+
+/// Annotate each settlement with its confirmation depth for real-time UIs.
+///
+/// A module only sees the block it's processing, so in live mode the clock is
+/// the head and fresh settlements have 0 confirmations. When replaying
+/// history, set param `head_block=N` to measure against a known head instead.
+#[substreams::handlers::map]
+fn map_settlement_confirmations(
+    params: String,
+    clock: Clock,
+    settlements: x402::Settlements,
+) -> Result<x402::SettlementConfirmations, substreams::errors::Error> {
+    let head_block = param_value(&params, "head_block")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0)
+        .max(clock.number);
+
+    let confirmations = settlements
+        .settlements
+        .iter()
+        .map(|s| x402::SettlementConfirmation {
+            settlement_id: s.id.clone(),
+            block_number: s.block_number,
+            confirmations: confirmations(head_block, s.block_number),
+        })
+        .collect();
+
+    Ok(x402::SettlementConfirmations {
+        head_block,
+        confirmations,
+    })
+}
+
+/// Emit the day's settlement count per settlement type, with each type's
+/// share of the day, to follow how the settlement-path mix evolves.
+#[substreams::handlers::map]
+fn map_daily_type_mix(
+    settlements: x402::Settlements,
+    type_count_store: StoreGetInt64,
+) -> Result<x402::DailyTypeMix, substreams::errors::Error> {
+    let mut mix = x402::DailyTypeMix {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) if !settlements.settlements.is_empty() => t.seconds,
+        _ => return Ok(mix),
+    };
+
+    let date = unix_to_date(ts);
+    let counts: Vec<(&str, i64)> = SETTLEMENT_TYPES
+        .iter()
+        .map(|t| {
+            let count = type_count_store.get_last(format!("{}:{}", date, t)).unwrap_or(0);
+            (*t, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    let total = BigInt::from(counts.iter().map(|(_, c)| c).sum::<i64>());
+
+    mix.types = counts
+        .into_iter()
+        .map(|(settlement_type, count)| x402::DailyTypeCount {
+            settlement_type: settlement_type.to_string(),
+            count: count as u64,
+            share_bps: share_bps(&BigInt::from(count), &total),
+        })
+        .collect();
+    mix.date = date;
+
+    Ok(mix)
+}
+
+/// Rules checked by `map_invariants`, in reporting order
+const INVARIANT_RULES: &[&str] =
+    &["amount_not_uint", "payment_without_recipient", "matched_without_payer"];
+
+/// Whether a settlement violates an invariant rule
+fn violates_invariant(rule: &str, s: &x402::Settlement) -> bool {
+    match rule {
+        // An unsigned integer in atomic units
+        "amount_not_uint" => s.amount.is_empty() || !s.amount.bytes().all(|b| b.is_ascii_digit()),
+        // A payment that moved value must say who received it
+        "payment_without_recipient" => {
+            s.recipient.is_empty() && s.amount.bytes().any(|b| b != b'0')
+        }
+        // A matched payment must have a real payer
+        "matched_without_payer" => {
+            !is_unmatched(s) && (s.payer.is_empty() || s.payer == ZERO_ADDR)
+        }
+        _ => false,
+    }
+}
+
+/// Count violations of each invariant, keeping only rules that were violated
+fn invariant_violations(settlements: &[x402::Settlement]) -> Vec<(&'static str, u64)> {
+    INVARIANT_RULES
+        .iter()
+        .map(|rule| {
+            let count = settlements.iter().filter(|s| violates_invariant(rule, s)).count();
+            (*rule, count as u64)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+/// Check per-block data invariants and emit a row per violated rule, so
+/// silent extraction issues become observable in CI and monitoring.
+#[substreams::handlers::map]
+fn map_invariants(
+    settlements: x402::Settlements,
+) -> Result<x402::Invariants, substreams::errors::Error> {
+    let violations = invariant_violations(&settlements.settlements)
+        .into_iter()
+        .map(|(rule, count)| x402::Invariant {
+            block_number: settlements.block_number,
+            rule: rule.to_string(),
+            count,
+        })
+        .collect();
+
+    Ok(x402::Invariants {
+        block_number: settlements.block_number,
+        violations,
+    })
+}
+
+/// Emit cumulative volume and payment counts for each recipient category
+/// paid in this block, for sector breakdowns of the market. Both keys of a
+/// category move together, so the store's deltas carry the full totals.
+#[substreams::handlers::map]
+fn map_category_stats(
+    settlements: x402::Settlements,
+    category_deltas: Deltas<DeltaBigInt>,
+) -> Result<x402::CategoryStats, substreams::errors::Error> {
+    let mut stats = x402::CategoryStats {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    for delta in category_deltas.deltas {
+        let (category, field) = match delta.key.rsplit_once(':') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let index = match stats.stats.iter().position(|c| c.category == category) {
+            Some(i) => i,
+            None => {
+                stats.stats.push(x402::CategoryStat {
+                    category: category.to_string(),
+                    ..Default::default()
+                });
+                stats.stats.len() - 1
+            }
+        };
+        let stat = &mut stats.stats[index];
+        match field {
+            "volume" => stat.total_volume = delta.new_value.to_string(),
+            "count" => stat.total_payments = u64::try_from(delta.new_value).unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Day-over-day change in settlement count, in basis points.
+/// Returns None when there is no prior day to compare against.
+fn growth_rate_bps(prev_count: i64, count: i64) -> Option<i64> {
+    if prev_count <= 0 {
+        return None;
+    }
+    Some((count - prev_count) * 10_000 / prev_count)
+}
+
+/// Compute the protocol growth rate: today's settlement count versus the
+/// previous UTC day's, from the daily count store.
+#[substreams::handlers::map]
+fn map_growth_rate(
+    settlements: x402::Settlements,
+    daily_count_store: StoreGetInt64,
+) -> Result<x402::GrowthRate, substreams::errors::Error> {
+    let mut growth = x402::GrowthRate {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) if !settlements.settlements.is_empty() => t.seconds,
+        _ => return Ok(growth),
+    };
+
+    let date = unix_to_date(ts);
+    let count = daily_count_store.get_last(&date).unwrap_or(0);
+    let prev_count = daily_count_store
+        .get_last(unix_to_date(ts - 86400))
+        .unwrap_or(0);
+
+    growth.count = count as u64;
+    if let Some(bps) = growth_rate_bps(prev_count, count) {
+        growth.pct_change_bps = bps;
+        growth.has_prior = true;
+    }
+    growth.date = date;
+
+    Ok(growth)
+}
+
+/// Second difference of three consecutive daily volumes: positive when
+/// growth is accelerating. None until both prior days have volume.
+fn volume_momentum(
+    day_before: Option<BigInt>,
+    yesterday: Option<BigInt>,
+    today: &BigInt,
+) -> Option<BigInt> {
+    let (day_before, yesterday) = (day_before?, yesterday?);
+    Some(today.clone() - yesterday * BigInt::from(2) + day_before)
+}
+
+/// Settlements per second, in thousandths, over a gap of `seconds`. None when
+/// the gap isn't positive (same-second or out-of-order timestamps).
+fn throughput_milli(settlements: u64, seconds: i64) -> Option<u64> {
+    if seconds <= 0 {
+        return None;
+    }
+    Some(settlements * 1_000 / seconds as u64)
+}
+
+/// Estimate instantaneous throughput: this block's settlement count over the
+/// seconds since the previous block with settlements.
+#[substreams::handlers::map]
+fn map_throughput(
+    settlements: x402::Settlements,
+    last_ts_deltas: Deltas<DeltaInt64>,
+) -> Result<x402::Throughput, substreams::errors::Error> {
+    let mut throughput = x402::Throughput {
+        block_number: settlements.block_number,
+        settlements: settlements.settlements.len() as u64,
+        ..Default::default()
+    };
+
+    if let Some(gap) = last_ts_deltas.deltas.last().and_then(prior_block_gap) {
+        throughput.seconds_since_prior = gap;
+        if let Some(rate) = throughput_milli(throughput.settlements, gap) {
+            throughput.settlements_per_second_milli = rate;
+            throughput.has_rate = true;
+        }
+    }
+
+    Ok(throughput)
+}
+
+/// Compute volume momentum: the change in day-over-day volume growth, from
+/// today's volume and the two previous UTC days' in the daily volume store.
+#[substreams::handlers::map]
+fn map_volume_momentum(
+    settlements: x402::Settlements,
+    daily_volume_store: StoreGetBigInt,
+) -> Result<x402::VolumeMomentum, substreams::errors::Error> {
+    let mut momentum = x402::VolumeMomentum {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+
+    let ts = match settlements.block_timestamp.as_ref() {
+        Some(t) if !settlements.settlements.is_empty() => t.seconds,
+        _ => return Ok(momentum),
+    };
+
+    let date = unix_to_date(ts);
+    let volume = daily_volume_store.get_last(&date).unwrap_or_else(BigInt::zero);
+    let yesterday = daily_volume_store.get_last(unix_to_date(ts - 86400));
+    let day_before = daily_volume_store.get_last(unix_to_date(ts - 2 * 86400));
+
+    if let Some(m) = volume_momentum(day_before, yesterday, &volume) {
+        momentum.momentum = m.to_string();
+        momentum.has_momentum = true;
+    }
+    momentum.volume = volume.to_string();
+    momentum.date = date;
+
+    Ok(momentum)
+}
+
+/// Emit a 30-day activity bitmap for each payer active in this block, for
+/// sparkline-style "active on these days" displays without a row per day.
+#[substreams::handlers::map]
+fn map_payer_activity(
+    settlements: x402::Settlements,
+    activity_store: StoreGetProto<x402::ActivityDay>,
+) -> Result<x402::PayerActivity, substreams::errors::Error> {
+    let mut activity = x402::PayerActivity {
+        block_number: settlements.block_number,
+        ..Default::default()
+    };
+    let today = match settlements.block_timestamp.as_ref() {
+        Some(t) => unix_day(t.seconds),
+        None => return Ok(activity),
+    };
+
+    let mut payers: Vec<String> = settlements
+        .settlements
+        .iter()
+        .filter(|s| !s.payer.is_empty() && s.payer != ZERO_ADDR)
+        .map(|s| s.payer.to_lowercase())
+        .collect();
+    payers.sort();
+    payers.dedup();
+
+    for payer in payers {
+        let slot_days: Vec<Option<i64>> = (0..ACTIVITY_WINDOW_DAYS)
+            .map(|slot| {
+                activity_store
+                    .get_last(format!("{}:{}", payer, slot))
+                    .map(|d| d.day)
+            })
+            .collect();
+        let bitmap = activity_bitmap(today, &slot_days);
+        activity.payers.push(x402::PayerActivityBitmap {
+            payer_address: payer,
+            bitmap,
+            active_days: bitmap.count_ones(),
+        });
+    }
+
+    Ok(activity)
+}
+
+/// Transfers in which a settlement's recipient forwards the payment onward in
+/// the same transaction: transfers out of the recipient after its payment
+/// transfer (the first payer->recipient transfer), to anyone but the
+/// facilitator (forwarded fees are the facilitator's cut, not a pass-through)
+fn passthrough_transfers<'a>(
+    s: &x402::Settlement,
+    transfers: &'a [TransferEvent],
+) -> Vec<&'a TransferEvent> {
+    let addr = |bytes: &[u8]| format_address(bytes).to_lowercase();
+    let (payer, recipient) = (s.payer.to_lowercase(), s.recipient.to_lowercase());
+    let facilitator = s.facilitator.to_lowercase();
+    let payment = transfers
+        .iter()
+        .filter(|t| addr(&t.from) == payer && addr(&t.to) == recipient)
+        .min_by_key(|t| t.log_index);
+    let payment = match payment {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+    transfers
+        .iter()
+        .filter(|t| t.log_index > payment.log_index && addr(&t.from) == recipient)
+        .filter(|t| addr(&t.to) != facilitator && addr(&t.to) != recipient)
+        .collect()
+}
+
+/// Detect pass-through recipients: settlements whose recipient forwards the
+/// USDC it received to another address within the same transaction, which
+/// reveals aggregators sitting in front of the real merchant.
+#[substreams::handlers::map]
+fn map_passthrough_recipients(
+    blk: eth::Block,
+    settlements: x402::Settlements,
+) -> Result<x402::Passthroughs, substreams::errors::Error> {
+    let mut passthroughs = x402::Passthroughs {
+        block_number: blk.number,
+        ..Default::default()
+    };
+    let usdc = format_address(&USDC).to_lowercase();
+
+    for trx in blk.transaction_traces.iter() {
+        let tx_hash = Hex(&trx.hash).to_string();
+        let paid: Vec<&x402::Settlement> = settlements
+            .settlements
+            .iter()
+            .filter(|s| s.tx_hash == tx_hash && !is_unmatched(s))
+            .filter(|s| s.token.to_lowercase() == usdc)
+            .collect();
+        if paid.is_empty() {
+            continue;
+        }
+        let receipt = match trx.receipt.as_ref() {
+            Some(r) => r,
+            None => continue,
+        };
+        let transfers: Vec<TransferEvent> = receipt
+            .logs
+            .iter()
+            .filter(|log| log.address == USDC)
+            .filter_map(decode_erc20_transfer)
+            .collect();
+
+        // A forward is reported once even if several settlements paid the recipient
+        let mut seen = std::collections::HashSet::new();
+        for s in paid {
+            for t in passthrough_transfers(s, &transfers) {
+                if !seen.insert(t.log_index) {
+                    continue;
+                }
+                passthroughs.passthroughs.push(x402::Passthrough {
+                    id: format!("{}-{}", tx_hash, t.log_index),
+                    settlement_id: s.id.clone(),
+                    tx_hash: tx_hash.clone(),
+                    recipient: s.recipient.clone(),
+                    forwarded_to: format_address(&t.to),
+                    amount: t.amount.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(passthroughs)
+}
+
+/// Emit a record whenever the day's cumulative volume surpasses the prior
+/// all-time daily high, for a "biggest days ever" stream.
+#[substreams::handlers::map]
+fn map_volume_records(
+    settlements: x402::Settlements,
+    max_deltas: Deltas<DeltaBigInt>,
+) -> Result<x402::VolumeRecords, substreams::errors::Error> {
+    let date = settlements
+        .block_timestamp
+        .as_ref()
+        .map(|t| unix_to_date(t.seconds))
+        .unwrap_or_default();
+    let records = max_deltas
+        .deltas
+        .iter()
+        .filter(|d| is_volume_record(d))
+        .map(|d| x402::VolumeRecord {
+            date: date.clone(),
+            volume: d.new_value.to_string(),
+            previous_record: d.old_value.to_string(),
+        })
+        .collect();
+
+    Ok(x402::VolumeRecords {
+        records,
+        block_number: settlements.block_number,
+    })
+}
+
+/// Default `min_authorizers`: distinct authorizers sharing a nonce in a day
+/// before it is flagged
+const DEFAULT_MIN_NONCE_AUTHORIZERS: u64 = 3;
+
+/// Report nonce values whose distinct-authorizer count grew this block. EIP-3009
+/// nonces are per authorizer, so one value used by many authorizers in a day
+/// hints at a shared, facilitator-side nonce scheme. Flagged at
+/// `min_authorizers` (param, default 3).
+#[substreams::handlers::map]
+fn map_nonce_patterns(
+    params: String,
+    settlements: x402::Settlements,
+    count_deltas: Deltas<DeltaInt64>,
+) -> Result<x402::NoncePatterns, substreams::errors::Error> {
+    let min_authorizers = param_value(&params, "min_authorizers")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MIN_NONCE_AUTHORIZERS);
+
+    let patterns = count_deltas
+        .deltas
+        .into_iter()
+        .filter_map(|d| {
+            let (date, nonce) = d.key.split_once('/')?;
+            let distinct_authorizers = d.new_value.max(0) as u64;
+            Some(x402::NoncePattern {
+                date: date.to_string(),
+                nonce: nonce.to_string(),
+                distinct_authorizers,
+                shared: distinct_authorizers >= min_authorizers,
+            })
+        })
+        .collect();
+
+    Ok(x402::NoncePatterns {
+        patterns,
+        block_number: settlements.block_number,
+    })
+}
+
+/// Emit the protocol-wide Pearson correlation between gas price and
+/// settlement amount, in bps: positive when larger settlements pay more gas,
+/// e.g. facilitators prioritizing them.
+#[substreams::handlers::map]
+fn map_gas_amount_correlation(
+    settlements: x402::Settlements,
+    sums_store: StoreGetBigInt,
+) -> Result<x402::GasAmountCorrelation, substreams::errors::Error> {
+    let sums =
+        CORRELATION_SUMS.map(|key| sums_store.get_last(key).unwrap_or_else(BigInt::zero));
+    let corr = pearson_bps(&sums);
+    Ok(x402::GasAmountCorrelation {
+        block_number: settlements.block_number,
+        gas_amount_corr_bps: corr.unwrap_or(0),
+        has_correlation: corr.is_some(),
+        samples: u64::try_from(&sums[0]).unwrap_or(0),
+    })
+}
+
+/// Emit protocol-wide USDC settlement counts per denomination (sub_cent,
+/// cents, dollars, tens, hundreds, thousands_plus), a quick read on typical
+/// payment sizes without a raw histogram.
+#[substreams::handlers::map]
+fn map_denomination_stats(
+    settlements: x402::Settlements,
+    denomination_store: StoreGetInt64,
+) -> Result<x402::DenominationStats, substreams::errors::Error> {
+    let names = DENOMINATIONS.iter().map(|(name, _)| *name).chain(["thousands_plus"]);
+    Ok(x402::DenominationStats {
+        block_number: settlements.block_number,
+        denominations: names
+            .map(|name| x402::DenominationCount {
+                denomination: name.to_string(),
+                count: denomination_store.get_last(name).unwrap_or(0) as u64,
+            })
+            .collect(),
+    })
+}
+
+// =============================================
+// LAYER 4: SQL Sink
+// =============================================
+
+/// How `db_out` renders values for the target database (`sink` param).
+/// Add a dialect here to support another warehouse.
+trait SinkDialect {
+    /// Render a Unix-seconds time for a timestamp column
+    fn timestamp(&self, secs: i64) -> String;
+    /// Render an integer amount (atomic units, wei) for a numeric column
+    fn amount(&self, raw: &str) -> String;
+}
+
+/// Postgres (default): `YYYY-MM-DD HH:MM:SS` UTC timestamps, amounts as given
+struct Postgres;
+
+impl SinkDialect for Postgres {
+    fn timestamp(&self, secs: i64) -> String {
+        unix_to_timestamp(secs)
+    }
+
+    fn amount(&self, raw: &str) -> String {
+        raw.to_string()
+    }
+}
+
+/// ClickHouse: `DateTime` as Unix seconds, which unlike a date string is not
+/// read in the server's timezone, and amounts always a plain integer that
+/// `toUInt256` / `toDecimal256` parse (empty or malformed become "0")
+struct ClickHouse;
+
+impl SinkDialect for ClickHouse {
+    fn timestamp(&self, secs: i64) -> String {
+        secs.to_string()
+    }
+
+    fn amount(&self, raw: &str) -> String {
+        parse_amount(raw).to_string()
+    }
+}
+
+/// Parsed `db_out` params, given as `key=value&key=value`
+struct DbOutParams {
+    /// Settlements below this amount (atomic units) are not written
+    min_amount: BigInt,
+    /// Per-token minimums by token address (`min_amount_usdc`,
+    /// `min_amount_eurc`), overriding `min_amount` for that token
+    token_min_amounts: Vec<(String, BigInt)>,
+    /// Emit only each payer's first-ever settlement, to `first_payments`
+    first_payment_only: bool,
+    /// Also emit settlements that beat their payer's prior max, to `personal_records`
+    personal_records: bool,
+    /// Re-emit the `protocol_totals` row only every N blocks
+    snapshot_every: u64,
+    /// Block that always gets a snapshot, e.g. the last block of a bounded run
+    snapshot_final_block: Option<u64>,
+    /// Emit per-block volume changes to `aggregate_deltas` instead of `settlements`
+    aggregate_deltas_only: bool,
+    /// Emit a `heartbeat` row once per UTC hour, with or without settlements
+    heartbeat: bool,
+    /// Emit addresses EIP-55 checksummed instead of lowercase
+    checksum: bool,
+    /// Human-readable names stamped on settlements, by lowercase address
+    address_labels: std::collections::HashMap<String, String>,
+    /// Route settlements to `settlements_{settlement_type}` tables
+    split_tables_by_type: bool,
+    /// Emit only the first settlement per authorization; later ones go to
+    /// `duplicate_authorizations`
+    dedup_authorizations: bool,
+    /// Columns to keep in settlement rows (the primary key always is); all
+    /// when unset
+    fields: Option<std::collections::HashSet<String>>,
+    /// Leave self-payments (payer == recipient) out of the `blocks` rollup;
+    /// their `settlements` rows are still written, flagged `is_self_payment`
+    exclude_self: bool,
+    /// Target database formatting: `postgres` (default) or `clickhouse`
+    sink: &'static dyn SinkDialect,
+    /// Also write `blocks` rows for blocks without settlements
+    empty_blocks: bool,
+}
+
+impl DbOutParams {
+    fn parse(params: &str) -> Self {
+        DbOutParams {
+            min_amount: parse_min_amount(param_value(params, "min_amount")),
+            token_min_amounts: [("min_amount_usdc", USDC), ("min_amount_eurc", EURC)]
+                .iter()
+                .filter_map(|(key, token)| {
+                    param_value(params, key)
+                        .map(|v| (format_address(token), parse_min_amount(Some(v))))
+                })
+                .collect(),
+            first_payment_only: param_value(params, "first_payment_only") == Some("true"),
+            personal_records: param_value(params, "personal_records") == Some("true"),
+            snapshot_every: param_value(params, "snapshot_every")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1),
+            snapshot_final_block: param_value(params, "snapshot_final_block")
+                .and_then(|v| v.parse().ok()),
+            aggregate_deltas_only: param_value(params, "aggregate_deltas_only") == Some("true"),
+            heartbeat: param_value(params, "heartbeat") == Some("true"),
+            checksum: param_value(params, "checksum") == Some("true"),
+            address_labels: parse_address_labels(
+                param_value(params, "address_labels").unwrap_or(""),
+            ),
+            split_tables_by_type: param_value(params, "split_tables_by_type") == Some("true"),
+            dedup_authorizations: param_value(params, "dedup_authorizations") == Some("true"),
+            fields: param_value(params, "fields").map(|v| {
+                v.split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect()
+            }),
+            exclude_self: param_value(params, "exclude_self") == Some("true"),
+            sink: match param_value(params, "sink") {
+                Some("clickhouse") => &ClickHouse,
+                _ => &Postgres,
+            },
+            empty_blocks: param_value(params, "empty_blocks") == Some("true"),
+        }
+    }
+
+    /// Minimum amount (atomic units) a settlement in `token` needs to be written
+    fn min_amount_for(&self, token: &str) -> &BigInt {
+        self.token_min_amounts
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(token))
+            .map(|(_, min)| min)
+            .unwrap_or(&self.min_amount)
+    }
+
+    /// Whether a settlement meets its token's minimum amount
+    fn keeps_amount(&self, s: &x402::Settlement) -> bool {
+        parse_amount(&s.amount) >= *self.min_amount_for(&s.token)
+    }
+
+    /// Whether the `protocol_totals` singleton is emitted at this block
+    fn should_snapshot(&self, block_number: u64) -> bool {
+        self.snapshot_every <= 1
+            || block_number.is_multiple_of(self.snapshot_every)
+            || self.snapshot_final_block == Some(block_number)
+    }
+
+    /// Whether snapshots are spaced out with no final block set, so a bounded
+    /// run that stops between snapshots leaves `protocol_totals` stale
+    fn snapshot_may_end_stale(&self) -> bool {
+        self.snapshot_every > 1 && self.snapshot_final_block.is_none()
+    }
+}
+
+/// Parse a minimum amount param in BigInt, so values beyond i64 are fine. A
+/// missing or malformed value means no minimum (0) rather than one that
+/// drops every settlement.
+fn parse_min_amount(value: Option<&str>) -> BigInt {
+    value
+        .and_then(|v| v.trim().parse::<BigInt>().ok())
+        .filter(|min| *min >= BigInt::zero())
+        .unwrap_or_else(BigInt::zero)
+}
+
+/// Per-block rollup for the `blocks` table: settlement count, total token
+/// volume (atomic units, native ETH left out), and gas used, counted once per
+/// transaction so a batch isn't summed once per settlement
+fn block_rollup(settlements: &[x402::Settlement]) -> (u64, BigInt, u64) {
+    let volume = settlements
+        .iter()
+        .filter(|s| is_token_amount(s))
+        .fold(BigInt::zero(), |total, s| total + parse_amount(&s.amount));
+    let gas = settlement_batches(settlements)
+        .iter()
+        .map(|(_, _, gas_used)| gas_used)
+        .sum();
+    (settlements.len() as u64, volume, gas)
+}
+
+/// Collapse a block's volume store deltas to one (key, change, new total) per
+/// key, in order of first change. A store emits a delta per add, so a key
+/// touched several times in a block has several.
+fn collapse_deltas(deltas: &[DeltaBigInt]) -> Vec<(&str, BigInt, BigInt)> {
+    let mut collapsed: Vec<(&str, BigInt, BigInt)> = Vec::new();
+    for delta in deltas {
+        match collapsed.iter_mut().find(|(key, _, _)| *key == delta.key) {
+            Some((_, _, after)) => *after = delta.new_value.clone(),
+            None => {
+                let before = if delta.operation == Operation::Create {
+                    BigInt::zero()
+                } else {
+                    delta.old_value.clone()
+                };
+                collapsed.push((&delta.key, before, delta.new_value.clone()));
+            }
+        }
+    }
+    collapsed
+        .into_iter()
+        .map(|(key, before, after)| (key, after.clone() - before, after))
+        .collect()
+}
+
+/// Select the settlements that are their payer's first-ever payment.
+///
+/// A payer is new in this block when its first-seen timestamp equals the
+/// block timestamp; within the block only its first settlement counts.
+fn first_payments(
+    settlements: &[x402::Settlement],
+    block_ts: i64,
+    first_seen: impl Fn(&str) -> Option<i64>,
+) -> Vec<&x402::Settlement> {
+    let mut seen = std::collections::HashSet::new();
+    settlements
+        .iter()
+        .filter(|s| !s.payer.is_empty() && s.payer != ZERO_ADDR)
+        .filter(|s| seen.insert(s.payer.to_lowercase()))
+        .filter(|s| first_seen(&s.payer.to_lowercase()) == Some(block_ts))
+        .collect()
+}
+
+/// Select settlements that exceed their payer's maximum so far: the max
+/// before this block (`store_payment_max`), then the running max within it.
+/// A payer with no prior payment sets no record on its first settlement.
+/// Only payments that store counts can set one (see `payment_extreme_keys`).
+fn personal_records(
+    settlements: &[x402::Settlement],
+    prior_max: impl Fn(&str) -> Option<BigInt>,
+) -> Vec<&x402::Settlement> {
+    let mut running: std::collections::HashMap<String, Option<BigInt>> =
+        std::collections::HashMap::new();
+    let mut records = Vec::new();
+    for s in settlements {
+        if s.payer.is_empty() || s.payer == ZERO_ADDR {
+            continue;
+        }
+        if is_unmatched(s) || !is_token_amount(s) {
+            continue;
+        }
+        let payer = s.payer.to_lowercase();
+        let amount = parse_amount(&s.amount);
+        let max = running.entry(payer.clone()).or_insert_with(|| prior_max(&payer));
+        match max {
+            Some(m) if amount > *m => {
+                records.push(s);
+                *max = Some(amount);
+            }
+            Some(_) => {}
+            None => *max = Some(amount),
+        }
+    }
+    records
+}
+
+/// An address as emitted to the database: EIP-55 checksummed with the
+/// `checksum` param, lowercase otherwise. Store keys stay lowercase either way
+/// so accumulation dedups; values that aren't addresses (empty, "native")
+/// pass through unchanged.
+fn display_address(address: &str, checksum: bool) -> String {
+    let bytes = address
+        .strip_prefix("0x")
+        .and_then(|hex| Hex::decode(hex).ok())
+        .filter(|b| b.len() == 20);
+    match bytes {
+        Some(b) if checksum => format_address_checksummed(&b),
+        Some(b) => format_address(&b),
+        None => address.to_string(),
+    }
+}
+
+/// Decimals of each settlement token, for scaling atomic amounts
+const TOKEN_DECIMALS: &[([u8; 20], u32)] = &[(USDC, 6), (EURC, 6)];
+
+/// Decimals assumed for a token missing from `TOKEN_DECIMALS`
+const DEFAULT_TOKEN_DECIMALS: u32 = 6;
+
+/// Decimals of a settlement's `token` (18 for native ETH). An unknown token
+/// gets `DEFAULT_TOKEN_DECIMALS` with a warning, so a newly settleable token
+/// with other decimals shows up in the logs instead of scaling silently wrong.
+fn token_decimals(token: &str) -> u32 {
+    if token == NATIVE_TOKEN {
+        return 18;
+    }
+    TOKEN_DECIMALS
+        .iter()
+        .find(|(address, _)| format_address(address) == token.to_lowercase())
+        .map(|(_, decimals)| *decimals)
+        .unwrap_or_else(|| {
+            substreams::log::info!(
+                "warning: unknown decimals for token {}, assuming {}",
+                token,
+                DEFAULT_TOKEN_DECIMALS
+            );
+            DEFAULT_TOKEN_DECIMALS
+        })
+}
+
+/// Render an atomic integer amount as a decimal string with `decimals`
+/// fractional digits, e.g. "1000000" at 6 decimals is "1.000000". Works on
+/// the digits directly, so amounts of any size are exact. Input that isn't
+/// an integer passes through unchanged.
+fn scale_amount(raw: &str, decimals: u32) -> String {
+    let (sign, digits) = match raw.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", raw),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return raw.to_string();
+    }
+    let decimals = decimals as usize;
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - decimals);
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// The first settlement of `s`'s authorization, when `s` is not it
+fn duplicate_of(s: &x402::Settlement, first_settlement: Option<String>) -> Option<String> {
+    first_settlement.filter(|id| *id != s.id)
+}
+
+/// Record a settlement dropped because its authorization was already settled
+fn write_duplicate_authorization_row(
+    tables: &mut Tables,
+    s: &x402::Settlement,
+    authorization_id: &str,
+    first_settlement_id: &str,
+) {
+    tables
+        .create_row("duplicate_authorizations", &s.id)
+        .set("authorization_id", authorization_id)
+        .set("first_settlement_id", first_settlement_id)
+        .set("block_number", s.block_number)
+        .set("tx_hash", &s.tx_hash)
+        .set("amount", &s.amount);
+}
+
+/// Set a TIMESTAMP column from an optional time. A missing one is logged and
+/// the column left unset, NULL on insert and unchanged on upsert, rather
+/// than written as 1970-01-01: blocks always carry a timestamp in practice.
+fn set_timestamp(
+    row: &mut Row,
+    column: &str,
+    ts: Option<&prost_types::Timestamp>,
+    sink: &dyn SinkDialect,
+) {
+    match ts {
+        Some(t) => {
+            row.set(column, sink.timestamp(t.seconds));
+        }
+        None => substreams::log::info!("warning: {} missing, left NULL", column),
+    }
+}
+
+/// Insert a settlement row. A missing timestamp leaves `block_timestamp`
+/// NULL and sets `timestamp_missing`, rather than writing 1970-01-01.
+/// `amount_decimal` is set when the token's decimals are known. With
+/// `split_tables_by_type` the row goes to `settlements_{settlement_type}`, and
+/// with `fields` only the listed columns are kept.
+fn write_settlement_row(tables: &mut Tables, s: &x402::Settlement, params: &DbOutParams) {
+    let addr = |a: &str| display_address(a, params.checksum);
+    let table = if params.split_tables_by_type {
+        format!("settlements_{}", s.settlement_type)
+    } else {
+        "settlements".to_string()
+    };
+    let row = tables
+        .create_row(&table, &s.id)
+        .set("block_number", s.block_number)
+        .set("tx_hash", &s.tx_hash)
+        .set("log_index", s.log_index)
+        .set("payer", addr(&s.payer))
+        .set("recipient", addr(&s.recipient))
+        .set("token", addr(&s.token))
+        .set("amount", params.sink.amount(&s.amount))
+        .set("settlement_type", &s.settlement_type)
+        .set("facilitator", addr(&s.facilitator))
+        .set("gas_used", params.sink.amount(&s.gas_used))
+        .set("gas_price", params.sink.amount(&s.gas_price))
+        .set("base_fee_per_gas", params.sink.amount(&s.base_fee_per_gas))
+        .set("priority_fee", params.sink.amount(&s.priority_fee))
+        .set("from_calldata", s.from_calldata)
+        .set("is_self_payment", s.is_self_payment)
+        .set("nonce", &s.nonce)
+        .set("fee_amount", params.sink.amount(&s.fee_amount))
+        .set("fee_recipient", addr(&s.fee_recipient))
+        .set("global_seq", &s.global_seq)
+        .set("timestamp_missing", s.timestamp_missing)
+        .set("valid_after", s.valid_after)
+        .set("valid_before", s.valid_before)
+        .set("protocol_version", &s.protocol_version)
+        .set("uuid", &s.uuid)
+        .set("infra", s.infra)
+        .set("via_multicall", s.via_multicall)
+        .set("multicall_address", &s.multicall_address);
+    if let Some(t) = s.timestamp.as_ref() {
+        row.set("block_timestamp", params.sink.timestamp(t.seconds));
+    }
+    if !s.gas_cost_usd.is_empty() {
+        row.set("gas_cost_usd", params.sink.amount(&s.gas_cost_usd));
+    }
+    row.set("amount_decimal", scale_amount(&s.amount, token_decimals(&s.token)));
+    if let Some(label) = params.address_labels.get(&s.payer.to_lowercase()) {
+        row.set("payer_label", label);
+    }
+    if let Some(label) = params.address_labels.get(&s.recipient.to_lowercase()) {
+        row.set("recipient_label", label);
+    }
+    if let Some(fields) = params.fields.as_ref() {
+        row.columns.retain(|name, _| fields.contains(name));
+    }
+}
+
+// =============================================
+// LAYER 4: Parquet Sink
+// =============================================
+
+/// Column schema of `SettlementRow`: (name, type, nullable), in field order.
+/// Types are Parquet logical types; nullable columns are proto optionals.
+pub fn settlement_row_schema() -> Vec<(&'static str, &'static str, bool)> {
+    vec![
+        ("id", "STRING", false),
+        ("tx_hash", "STRING", false),
+        ("log_index", "UINT32", false),
+        ("block_number", "UINT64", false),
+        ("block_timestamp", "INT64", true),
+        ("payer", "STRING", false),
+        ("recipient", "STRING", false),
+        ("token", "STRING", false),
+        ("amount", "STRING", false),
+        ("amount_u64", "UINT64", true),
+        ("settlement_type", "STRING", false),
+        ("facilitator", "STRING", false),
+        ("gas_used", "UINT64", false),
+        ("gas_price", "UINT64", true),
+        ("nonce", "STRING", true),
+        ("fee_amount", "UINT64", true),
+        ("fee_recipient", "STRING", true),
+        ("global_seq", "STRING", false),
+        ("amount_decimal", "STRING", true),
+        ("fee_amount_decimal", "STRING", true),
+    ]
+}
+
+/// Flatten a settlement into a typed Parquet row. Numeric strings become
+/// integers where they fit; values that don't, and empty strings, are null
+/// rather than a placeholder. Amount and fee are also given scaled by the
+/// token's decimals (see `token_decimals`).
+fn settlement_row(s: &x402::Settlement) -> x402::SettlementRow {
+    let non_empty = |v: &str| (!v.is_empty()).then(|| v.to_string());
+    let decimals = token_decimals(&s.token);
+    let scaled = |v: &str| Some(scale_amount(v, decimals));
+    x402::SettlementRow {
+        id: s.id.clone(),
+        tx_hash: s.tx_hash.clone(),
+        log_index: s.log_index,
+        block_number: s.block_number,
+        block_timestamp: s.timestamp.as_ref().map(|t| t.seconds),
+        payer: s.payer.clone(),
+        recipient: s.recipient.clone(),
+        token: s.token.clone(),
+        amount: s.amount.clone(),
+        amount_u64: s.amount.parse().ok(),
+        settlement_type: s.settlement_type.clone(),
+        facilitator: s.facilitator.clone(),
+        gas_used: s.gas_used.parse().unwrap_or(0),
+        gas_price: s.gas_price.parse().ok(),
+        nonce: non_empty(&s.nonce),
+        fee_amount: s.fee_amount.parse().ok(),
+        fee_recipient: non_empty(&s.fee_recipient),
+        global_seq: s.global_seq.clone(),
+        amount_decimal: scaled(&s.amount),
+        fee_amount_decimal: scaled(&s.fee_amount),
+    }
+}
+
+/// Settlements kept for export, under the `db_out` min-amount rules
+fn export_rows(settlements: &[x402::Settlement], params: &DbOutParams) -> Vec<x402::SettlementRow> {
+    settlements
+        .iter()
+        .filter(|s| params.keeps_amount(s))
+        .map(settlement_row)
+        .collect()
+}
+
+/// Output settlements as flat, typed rows for a Parquet sink, e.g. for bulk
+/// historical export. Takes `min_amount` / `min_amount_{token}` like `db_out`.
+#[substreams::handlers::map]
+fn parquet_out(
+    params: String,
+    settlements: x402::Settlements,
+) -> Result<x402::SettlementRows, substreams::errors::Error> {
+    Ok(x402::SettlementRows {
+        rows: export_rows(&settlements.settlements, &DbOutParams::parse(&params)),
+        block_number: settlements.block_number,
+    })
+}
+
+// =============================================
+// LAYER 4: Compliance Sink
+// =============================================
+
+/// Parse an address-list param such as `flagged_addresses` or
+/// `infra_addresses`: comma-separated addresses, lowercased for
+/// case-insensitive matching.
+fn parse_address_list(value: &str) -> std::collections::HashSet<String> {
+    value
+        .split(',')
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+/// Settlements whose payer or recipient is flagged, with the side that
+/// matched: "payer", "recipient" or "both".
+fn flag_settlements<'a>(
+    settlements: &'a [x402::Settlement],
+    flagged: &std::collections::HashSet<String>,
+) -> Vec<(&'a x402::Settlement, &'static str)> {
+    settlements
+        .iter()
+        .filter_map(|s| {
+            let payer = flagged.contains(&s.payer.to_lowercase());
+            let recipient = flagged.contains(&s.recipient.to_lowercase());
+            let side = match (payer, recipient) {
+                (true, true) => "both",
+                (true, false) => "payer",
+                (false, true) => "recipient",
+                (false, false) => return None,
+            };
+            Some((s, side))
+        })
+        .collect()
+}
+
+/// Output database changes for settlements touching a denylist of addresses
+/// (`flagged_addresses` param), for compliance review.
+#[substreams::handlers::map]
+fn compliance_out(
+    params: String,
+    settlements: x402::Settlements,
+) -> Result<DatabaseChanges, substreams::errors::Error> {
+    let mut tables = Tables::new();
+    let flagged = param_value(&params, "flagged_addresses")
+        .map(parse_address_list)
+        .unwrap_or_default();
+    if flagged.is_empty() {
+        return Ok(tables.to_database_changes());
+    }
+
+    for (s, side) in flag_settlements(&settlements.settlements, &flagged) {
+        let row = tables
+            .create_row("flagged_settlements", &s.id)
+            .set("block_number", s.block_number)
+            .set("tx_hash", &s.tx_hash)
+            .set("payer", &s.payer)
+            .set("recipient", &s.recipient)
+            .set("token", &s.token)
+            .set("amount", &s.amount)
+            .set("facilitator", &s.facilitator)
+            .set("matched_side", side);
+        if let Some(t) = s.timestamp.as_ref() {
+            row.set("block_timestamp", unix_to_timestamp(t.seconds));
+        }
+    }
+
+    Ok(tables.to_database_changes())
+}
+
+// =============================================
+// LAYER 4: Wide Sink
+// =============================================
+
+/// Store key a settlement adds to for an address, matching the per-address
+/// stores: empty for a missing or zero address (the stores skip those).
+fn stat_key(address: &str) -> String {
+    if address.is_empty() || address == ZERO_ADDR {
+        return String::new();
+    }
+    address.to_lowercase()
+}
+
+/// Rewind end-of-block totals to each settlement. Get stores already hold the
+/// whole block, so walking the block backwards and subtracting what each
+/// settlement added yields the total as of that settlement (inclusive).
+/// `contributions` are (store key, amount added) in block order; an empty key
+/// reads as zero.
+fn rewind_totals(
+    contributions: &[(String, BigInt)],
+    end_total: impl Fn(&str) -> BigInt,
+) -> Vec<BigInt> {
+    let mut running: std::collections::HashMap<&str, BigInt> = std::collections::HashMap::new();
+    let mut as_of = vec![BigInt::zero(); contributions.len()];
+    for (i, (key, added)) in contributions.iter().enumerate().rev() {
+        if key.is_empty() {
+            continue;
+        }
+        let total = running
+            .entry(key.as_str())
+            .or_insert_with(|| end_total(key));
+        as_of[i] = total.clone();
+        *total = total.clone() - added.clone();
+    }
+    as_of
+}
+
+/// (store key, amount added) per settlement, in block order
+type Contributions = Vec<(String, BigInt)>;
+
+/// Contributions of each settlement to a volume store and its count store
+fn volume_and_count_contributions(
+    settlements: &[x402::Settlement],
+    key: impl Fn(&x402::Settlement) -> String,
+) -> (Contributions, Contributions) {
+    settlements
+        .iter()
+        .map(|s| {
+            let k = key(s);
+            let amount = if is_token_amount(s) {
+                parse_amount(&s.amount)
+            } else {
+                BigInt::zero()
+            };
+            let counted = if is_unmatched(s) { 0 } else { 1 };
+            ((k.clone(), amount), (k, BigInt::from(counted)))
+        })
+        .unzip()
+}
+
+/// Output one denormalized row per settlement to `settlements_wide`, joined
+/// with its payer, recipient and facilitator cumulative totals as of that
+/// settlement, for BI tools that prefer a single wide table.
+#[substreams::handlers::map]
+fn wide_out(
+    settlements: x402::Settlements,
+    payer_volume_store: StoreGetBigInt,
+    payer_count_store: StoreGetInt64,
+    recipient_volume_store: StoreGetBigInt,
+    recipient_count_store: StoreGetInt64,
+    facilitator_volume_store: StoreGetBigInt,
+    facilitator_count_store: StoreGetInt64,
+) -> Result<DatabaseChanges, substreams::errors::Error> {
+    let mut tables = Tables::new();
+    let list = &settlements.settlements;
+    let volume =
+        |store: &StoreGetBigInt, key: &str| store.get_last(key).unwrap_or_else(BigInt::zero);
+    let count =
+        |store: &StoreGetInt64, key: &str| BigInt::from(store.get_last(key).unwrap_or(0));
+
+    let (payer_volume, payer_count) = volume_and_count_contributions(list, |s| stat_key(&s.payer));
+    let (recipient_volume, recipient_count) =
+        volume_and_count_contributions(list, |s| stat_key(&s.recipient));
+    let (facilitator_volume, facilitator_count) =
+        volume_and_count_contributions(list, |s| s.facilitator.to_lowercase());
+
+    let payer_spent = rewind_totals(&payer_volume, |k| volume(&payer_volume_store, k));
+    let payer_payments = rewind_totals(&payer_count, |k| count(&payer_count_store, k));
+    let recipient_received =
+        rewind_totals(&recipient_volume, |k| volume(&recipient_volume_store, k));
+    let recipient_payments =
+        rewind_totals(&recipient_count, |k| count(&recipient_count_store, k));
+    let facilitator_volume_settled =
+        rewind_totals(&facilitator_volume, |k| volume(&facilitator_volume_store, k));
+    let facilitator_settlements =
+        rewind_totals(&facilitator_count, |k| count(&facilitator_count_store, k));
+
+    for (i, s) in list.iter().enumerate() {
+        let row = tables
+            .create_row("settlements_wide", &s.id)
+            .set("block_number", s.block_number)
+            .set("tx_hash", &s.tx_hash)
+            .set("payer", &s.payer)
+            .set("recipient", &s.recipient)
+            .set("token", &s.token)
+            .set("amount", &s.amount)
+            .set("settlement_type", &s.settlement_type)
+            .set("facilitator", &s.facilitator)
+            .set("payer_total_spent", payer_spent[i].to_string())
+            .set("payer_total_payments", payer_payments[i].to_string())
+            .set("recipient_total_received", recipient_received[i].to_string())
+            .set("recipient_total_payments", recipient_payments[i].to_string())
+            .set(
+                "facilitator_total_volume_settled",
+                facilitator_volume_settled[i].to_string(),
+            )
+            .set("facilitator_total_settlements", facilitator_settlements[i].to_string());
+        if let Some(t) = s.timestamp.as_ref() {
+            row.set("block_timestamp", unix_to_timestamp(t.seconds));
+        }
+    }
+
+    Ok(tables.to_database_changes())
+}
+
+/// Output database changes for PostgreSQL
+#[substreams::handlers::map]
+fn db_out(
+    params: String,
+    settlements: x402::Settlements,
+    payer_stats: x402::PayerStats,
+    recipient_stats: x402::RecipientStats,
+    facilitator_stats: x402::FacilitatorStats,
+    growth: x402::GrowthRate,
+    value_split: x402::ValueSplit,
+    subscriptions: x402::Subscriptions,
+    top_reach: x402::RecipientsReach,
+    gas_savings: x402::GasSavings,
+    amount_entropy: x402::RecipientAmountEntropies,
+    proxy_mix: x402::ProxyTypeMix,
+    latency: x402::FacilitatorLatencies,
+    protocol_totals: x402::ProtocolTotals,
+    new_vs_returning: x402::NewVsReturning,
+    type_mix: x402::DailyTypeMix,
+    fee_competition: x402::FeeCompetition,
+    category_stats: x402::CategoryStats,
+    usdc_upgrades: x402::UsdcUpgrades,
+    facilitator_sla: x402::FacilitatorSlas,
+    volume_momentum: x402::VolumeMomentum,
+    fee_prevalence: x402::FeePrevalence,
+    throughput: x402::Throughput,
+    leaderboard: x402::Leaderboard,
+    first_seen_store: StoreGetInt64,
+    payment_max_deltas: Deltas<DeltaBigInt>,
+    payer_volume_deltas: Deltas<DeltaBigInt>,
+    recipient_volume_deltas: Deltas<DeltaBigInt>,
+    facilitator_volume_deltas: Deltas<DeltaBigInt>,
+    heartbeat_deltas: Deltas<DeltaInt64>,
+    payer_daily_deltas: Deltas<DeltaBigInt>,
+    authorization_first_store: StoreGetString,
+    net_flow_deltas: Deltas<DeltaBigInt>,
+    hourly_volume_deltas: Deltas<DeltaBigInt>,
+    hourly_count_deltas: Deltas<DeltaInt64>,
+    period_volume_deltas: Deltas<DeltaBigInt>,
+    period_count_deltas: Deltas<DeltaInt64>,
+) -> Result<DatabaseChanges, substreams::errors::Error> {
+    let mut tables = Tables::new();
+    let params = DbOutParams::parse(&params);
+    let addr = |a: &str| display_address(a, params.checksum);
+
+    // One heartbeat per UTC hour, so a quiet pipeline is told apart from a dead one
+    if params.heartbeat {
+        for (hour, secs) in new_heartbeat_hours(&heartbeat_deltas.deltas) {
+            tables
+                .create_row("heartbeat", hour)
+                .set("block_number", settlements.block_number)
+                .set("block_timestamp", params.sink.timestamp(secs));
+        }
+    }
+
+    // One row per block with settlements (every block with `empty_blocks`)
+    let counted: Vec<x402::Settlement> = settlements
+        .settlements
+        .iter()
+        .filter(|s| counts_toward_volume(s, params.exclude_self))
+        .cloned()
+        .collect();
+    let (settlement_count, total_volume, total_gas) = block_rollup(&counted);
+    if settlement_count > 0 || params.empty_blocks {
+        let row = tables
+            .create_row("blocks", settlements.block_number.to_string())
+            .set("settlement_count", settlement_count as i64)
+            .set("total_volume", params.sink.amount(&total_volume.to_string()))
+            .set("total_gas", total_gas as i64);
+        set_timestamp(row, "block_timestamp", settlements.block_timestamp.as_ref(), params.sink);
+    }
+
+    // Upsert signed net flow (received minus paid) per address
+    for delta in &net_flow_deltas.deltas {
+        tables
+            .create_row("net_flow", addr(&delta.key))
+            .set("net_flow", delta.new_value.to_string())
+            .set("block_number", settlements.block_number);
+    }
+
+    // Upsert the running volume and count for the block's UTC hour
+    let hourly = hourly_rollup(&hourly_volume_deltas.deltas, &hourly_count_deltas.deltas);
+    if let Some((hour, volume, count)) = hourly {
+        tables
+            .create_row("settlements_hourly", hour)
+            .set("volume", volume.to_string())
+            .set("count", count)
+            .set("block_number", settlements.block_number);
+    }
+
+    // Upsert the running volume and count for the block's ISO week and month
+    for (key, volume, count) in
+        period_rollups(&period_volume_deltas.deltas, &period_count_deltas.deltas)
+    {
+        let (table, period) = match key.split_once(':') {
+            Some(("week", week)) => ("settlements_weekly", week),
+            Some(("month", month)) => ("settlements_monthly", month),
+            _ => continue,
+        };
+        tables
+            .create_row(table, period)
+            .set("volume", volume.to_string())
+            .set("count", count)
+            .set("block_number", settlements.block_number);
+    }
+
+    // Upsert each payer's running volume for the day
+    for delta in &payer_daily_deltas.deltas {
+        if let Some((payer, date)) = delta.key.rsplit_once(':') {
+            let payer = addr(payer);
+            tables
+                .create_row("payer_daily", payer_day_key(&payer, date))
+                .set("payer", payer)
+                .set("date", date)
+                .set("volume", delta.new_value.to_string())
+                .set("block_number", settlements.block_number);
+        }
+    }
+
+    // Insert USDC implementation upgrades (decoders need re-verifying)
+    for u in &usdc_upgrades.upgrades {
+        let row = tables
+            .create_row("usdc_upgrades", &u.id)
+            .set("block_number", usdc_upgrades.block_number)
+            .set("tx_hash", &u.tx_hash)
+            .set("log_index", u.log_index)
+            .set("implementation", addr(&u.implementation));
+        if let Some(t) = usdc_upgrades.block_timestamp.as_ref() {
+            row.set("block_timestamp", params.sink.timestamp(t.seconds));
+        }
+    }
+
+    // Insert canceled authorizations (aborted payments)
+    for c in &settlements.canceled_authorizations {
+        tables
+            .create_row("canceled_authorizations", &c.id)
+            .set("block_number", c.block_number)
+            .set("tx_hash", &c.tx_hash)
+            .set("log_index", c.log_index)
+            .set("token", addr(&c.token))
+            .set("authorizer", addr(&c.authorizer))
+            .set("nonce", &c.nonce);
+    }
+
+    // Insert USDC transfers into the proxies (pre-funding, not payments)
+    for f in &settlements.proxy_fundings {
+        tables
+            .create_row("proxy_funding", &f.id)
+            .set("block_number", f.block_number)
+            .set("tx_hash", &f.tx_hash)
+            .set("log_index", f.log_index)
+            .set("from_address", addr(&f.from))
+            .set("proxy", addr(&f.proxy))
+            .set("amount", &f.amount);
+    }
+
+    if params.personal_records {
+        // A payer's first delta this block carries its max before the block
+        let mut prior = std::collections::HashMap::new();
+        for delta in payment_max_deltas.deltas {
+            let Some(payer) = delta.key.strip_prefix("payer:") else {
+                continue;
+            };
+            let old = (delta.operation != Operation::Create).then_some(delta.old_value);
+            prior.entry(payer.to_string()).or_insert(old);
+        }
+        for s in personal_records(&settlements.settlements, |payer| {
+            prior.get(payer).cloned().flatten()
+        }) {
+            let row = tables
+                .create_row("personal_records", &s.id)
+                .set("payer", addr(&s.payer))
+                .set("amount", &s.amount)
+                .set("recipient", addr(&s.recipient))
+                .set("block_number", s.block_number)
+                .set("tx_hash", &s.tx_hash);
+            if let Some(t) = s.timestamp.as_ref() {
+                row.set("block_timestamp", params.sink.timestamp(t.seconds));
+            }
+        }
+    }
+
+    if params.first_payment_only {
+        // Acquisition mode: only first-ever payments, settlements table suppressed
+        let block_ts = settlements
+            .block_timestamp
+            .as_ref()
+            .map(|t| t.seconds)
+            .unwrap_or(0);
+        let firsts = first_payments(&settlements.settlements, block_ts, |payer| {
+            first_seen_store.get_last(format!("payer:{}", payer))
+        });
+        for s in firsts {
+            tables
+                .create_row("first_payments", addr(&s.payer))
+                .set("settlement_id", &s.id)
+                .set("block_number", s.block_number)
+                .set("block_timestamp", params.sink.timestamp(block_ts))
+                .set("tx_hash", &s.tx_hash)
+                .set("recipient", addr(&s.recipient))
+                .set("token", addr(&s.token))
+                .set("amount", &s.amount)
+                .set("facilitator", addr(&s.facilitator));
+        }
+    } else if params.aggregate_deltas_only {
+        // Change-driven mode: which totals moved this block and by how much,
+        // settlements table suppressed
+        for (kind, deltas) in [
+            ("payer", &payer_volume_deltas),
+            ("recipient", &recipient_volume_deltas),
+            ("facilitator", &facilitator_volume_deltas),
+        ] {
+            for (address, change, new_total) in collapse_deltas(&deltas.deltas) {
+                tables
+                    .create_row(
+                        "aggregate_deltas",
+                        format!("{}:{}:{}", settlements.block_number, kind, address),
+                    )
+                    .set("block_number", settlements.block_number)
+                    .set("kind", kind)
+                    .set("address", addr(address))
+                    .set("change", change.to_string())
+                    .set("new_total", new_total.to_string());
+            }
+        }
+    } else {
+        // Insert settlements
+        for s in settlements.settlements {
+            if !params.keeps_amount(&s) {
+                continue;
+            }
+
+            if params.dedup_authorizations {
+                if let Some(auth_id) = authorization_id(&s) {
+                    let first = authorization_first_store.get_last(&auth_id);
+                    if let Some(first_id) = duplicate_of(&s, first) {
+                        write_duplicate_authorization_row(&mut tables, &s, &auth_id, &first_id);
+                        continue;
+                    }
+                }
+            }
+
+            write_settlement_row(&mut tables, &s, &params);
+        }
+    }
+
+    // Upsert payer stats
+    for stat in payer_stats.stats {
+        let row = tables
+            .create_row("payers", addr(&stat.payer_address))
+            .set("total_spent", stat.total_spent.as_str())
+            .set("total_payments", stat.total_payments as i64)
+            .set("avg_payment", stat.avg_payment.as_str())
+            .set("min_payment", stat.min_payment.as_str())
+            .set("max_payment", stat.max_payment.as_str())
+            .set("distinct_tokens", stat.distinct_tokens as i64);
+        set_timestamp(row, "first_payment_at", stat.first_payment_at.as_ref(), params.sink);
+        set_timestamp(row, "last_payment_at", stat.last_payment_at.as_ref(), params.sink);
+    }
+
+    // Upsert recipient stats
+    for stat in recipient_stats.stats {
+        let row = tables
+            .create_row("recipients", addr(&stat.recipient_address))
+            .set("total_received", stat.total_received.as_str())
+            .set("total_payments", stat.total_payments as i64)
+            .set("avg_payment", stat.avg_payment.as_str())
+            .set("min_payment", stat.min_payment.as_str())
+            .set("max_payment", stat.max_payment.as_str())
+            .set("ewma_payment_size", stat.ewma_payment_size.as_str())
+            .set("unique_payers", stat.unique_payers as i64);
+        set_timestamp(row, "first_payment_at", stat.first_payment_at.as_ref(), params.sink);
+        set_timestamp(row, "last_payment_at", stat.last_payment_at.as_ref(), params.sink);
+        if let Some(gap) = stat.time_to_second_payment_seconds {
+            row.set("time_to_second_payment_seconds", gap);
+        }
+    }
+
+    // Upsert facilitator stats
+    for stat in facilitator_stats.stats {
+        let row = tables
+            .create_row(
+                "facilitators",
+                addr(&stat.facilitator_address),
+            )
+            .set("name", &stat.name)
+            .set("url", &stat.url)
+            .set("is_active", stat.is_active)
+            .set("total_settlements", stat.total_settlements as i64)
+            .set("total_volume_settled", stat.total_volume_settled.as_str())
+            .set("total_gas_spent", stat.total_gas_spent.as_str())
+            .set("total_gas_spent_usd", stat.total_gas_spent_usd.as_str())
+            .set("total_fees_earned", stat.total_fees_earned.as_str())
+            .set("eip3009_count", stat.eip3009_count as i64)
+            .set("proxy_count", stat.proxy_count as i64)
+            .set("cost_per_dollar_bps", stat.cost_per_dollar_bps);
+        set_timestamp(row, "first_settlement_at", stat.first_settlement_at.as_ref(), params.sink);
+        set_timestamp(row, "last_settlement_at", stat.last_settlement_at.as_ref(), params.sink);
+    }
+
+    // Upsert day-over-day growth (pct_change_bps stays NULL on the first day)
+    if !growth.date.is_empty() {
+        let row = tables
+            .create_row("growth", &growth.date)
+            .set("count", growth.count as i64);
+        if growth.has_prior {
+            row.set("pct_change_bps", growth.pct_change_bps);
+        }
+    }
+
+    // Upsert the day's new vs returning payer volume
+    if !new_vs_returning.date.is_empty() {
+        tables
+            .create_row("new_vs_returning", &new_vs_returning.date)
+            .set("new_volume", new_vs_returning.new_volume.as_str())
+            .set("returning_volume", new_vs_returning.returning_volume.as_str());
+    }
+
+    // Upsert the day's count per settlement type
+    for t in &type_mix.types {
+        tables
+            .create_row("daily_type_mix", format!("{}:{}", type_mix.date, t.settlement_type))
+            .set("date", &type_mix.date)
+            .set("settlement_type", &t.settlement_type)
+            .set("count", t.count as i64)
+            .set("share_bps", t.share_bps);
+    }
+
+    // Upsert the protocol-wide value split singleton
+    if !value_split.settlements.is_empty() {
+        tables
+            .create_row("value_split", "protocol")
+            .set("recipient_total", value_split.recipient_total.as_str())
+            .set("facilitator_total", value_split.facilitator_total.as_str())
+            .set("recipient_share_bps", value_split.recipient_share_bps)
+            .set("facilitator_share_bps", value_split.facilitator_share_bps)
+            .set("block_number", value_split.block_number);
+    }
+
+    // Upsert detected subscriptions
+    for sub in subscriptions.subscriptions {
+        let row = tables
+            .create_row(
+                "subscriptions",
+                format!("{}:{}:{}", sub.payer, sub.recipient, sub.amount),
+            )
+            .set("payer", addr(&sub.payer))
+            .set("recipient", addr(&sub.recipient))
+            .set("amount", &sub.amount)
+            .set("occurrences", sub.occurrences as i64)
+            .set("avg_interval_seconds", sub.avg_interval);
+        set_timestamp(row, "last_payment_at", sub.last_payment_at.as_ref(), params.sink);
+    }
+
+    // Upsert recipient reach (rank across blocks with ORDER BY unique_payers)
+    for r in top_reach.recipients {
+        tables
+            .create_row("top_recipients_reach", addr(&r.recipient_address))
+            .set("unique_payers", r.unique_payers as i64)
+            .set("block_number", top_reach.block_number);
+    }
+
+    // Upsert the leaderboard by rank slot. Addresses are never removed, so a
+    // filled slot is rewritten on every emit and never left stale
+    for e in leaderboard.entries {
+        tables
+            .create_row("leaderboard", format!("{}:{}", e.role, e.rank))
+            .set("role", &e.role)
+            .set("rank", e.rank as i64)
+            .set("address", addr(&e.address))
+            .set("total_volume", e.total_volume.as_str())
+            .set("block_number", leaderboard.block_number);
+    }
+
+    // Upsert the protocol-wide gas savings singleton
+    if !gas_savings.batches.is_empty() {
+        tables
+            .create_row("gas_savings", "protocol")
+            .set("total_gas_saved", gas_savings.total_gas_saved)
+            .set("block_number", gas_savings.block_number);
+    }
+
+    // Upsert the protocol totals singleton, on the snapshot cadence
+    if params.should_snapshot(protocol_totals.block_number) {
+        if params.snapshot_may_end_stale() {
+            substreams::log::info!(
+                "warning: snapshot_every={} without snapshot_final_block; a run ending \
+                 between snapshots leaves protocol_totals up to {} blocks stale",
+                params.snapshot_every,
+                params.snapshot_every - 1
+            );
+        }
+        tables
+            .create_row("protocol_totals", "protocol")
+            .set("total_volume", protocol_totals.total_volume.as_str())
+            .set("total_fees", protocol_totals.total_fees.as_str())
+            .set("total_settlements", protocol_totals.total_settlements)
+            .set("approx_unique_payers", protocol_totals.approx_unique_payers)
+            .set("approx_unique_recipients", protocol_totals.approx_unique_recipients)
+            .set("payer_gini_bps", protocol_totals.payer_gini_bps)
+            .set("subsidy_rate_bps", protocol_totals.subsidy_rate_bps)
+            .set("block_number", protocol_totals.block_number);
+    }
+
+    // Upsert facilitator settlement latency
+    for l in latency.facilitators {
+        tables
+            .create_row("facilitator_latency", addr(&l.facilitator_address))
+            .set("avg_latency_seconds", l.avg_latency_seconds)
+            .set("max_latency_seconds", l.max_latency_seconds)
+            .set("samples", l.samples as i64)
+            .set("block_number", latency.block_number);
+    }
+
+    // Upsert recipient category totals
+    for c in category_stats.stats {
+        tables
+            .create_row("category_stats", &c.category)
+            .set("total_volume", c.total_volume.as_str())
+            .set("total_payments", c.total_payments as i64)
+            .set("block_number", category_stats.block_number);
+    }
+
+    // Insert block throughput (rate NULL on the first block or a bad gap)
+    if throughput.settlements > 0 {
+        let row = tables
+            .create_row("throughput", throughput.block_number.to_string())
+            .set("settlements", throughput.settlements)
+            .set("seconds_since_prior", throughput.seconds_since_prior);
+        if throughput.has_rate {
+            row.set("settlements_per_second_milli", throughput.settlements_per_second_milli);
+        }
+    }
+
+    // Upsert daily volume momentum (NULL until two prior days have volume)
+    if !volume_momentum.date.is_empty() {
+        let row = tables
+            .create_row("volume_momentum", &volume_momentum.date)
+            .set("volume", volume_momentum.volume.as_str())
+            .set("block_number", volume_momentum.block_number);
+        if volume_momentum.has_momentum {
+            row.set("momentum", volume_momentum.momentum.as_str());
+        }
+    }
+
+    // Upsert fee prevalence, protocol-wide and per facilitator
+    if fee_prevalence.total > 0 {
+        tables
+            .create_row("fee_prevalence", "protocol")
+            .set("with_fee", fee_prevalence.with_fee)
+            .set("total", fee_prevalence.total)
+            .set("fee_prevalence_bps", fee_prevalence.fee_prevalence_bps)
+            .set("block_number", fee_prevalence.block_number);
+    }
+    for f in fee_prevalence.facilitators {
+        tables
+            .create_row("fee_prevalence", addr(&f.facilitator_address))
+            .set("with_fee", f.with_fee)
+            .set("total", f.total)
+            .set("fee_prevalence_bps", f.fee_prevalence_bps)
+            .set("block_number", fee_prevalence.block_number);
+    }
+
+    // Upsert facilitator SLA scores
+    for f in facilitator_sla.facilitators {
+        tables
+            .create_row("facilitator_sla", addr(&f.facilitator_address))
+            .set("sla_score_bps", f.sla_score_bps)
+            .set("success_bps", f.success_bps)
+            .set("latency_score_bps", f.latency_score_bps)
+            .set("gas_score_bps", f.gas_score_bps)
+            .set("block_number", facilitator_sla.block_number);
+    }
+
+    // Upsert facilitator fee rates against the protocol median
+    for f in fee_competition.facilitators {
+        tables
+            .create_row("fee_competition", addr(&f.facilitator_address))
+            .set("avg_fee_bps", f.avg_fee_bps)
+            .set("median_fee_bps", fee_competition.median_fee_bps)
+            .set("low_fee_facilitator", f.low_fee_facilitator)
+            .set("samples", f.samples as i64)
+            .set("block_number", fee_competition.block_number);
+    }
+
+    // Upsert the protocol-wide proxy type mix singleton
+    if proxy_mix.settled + proxy_mix.settled_with_permit > 0 {
+        tables
+            .create_row("proxy_type_mix", "protocol")
+            .set("settled", proxy_mix.settled as i64)
+            .set("settled_with_permit", proxy_mix.settled_with_permit as i64)
+            .set("permit_share_bps", proxy_mix.permit_share_bps)
+            .set("block_number", proxy_mix.block_number);
+    }
+
+    // Upsert recipient amount variability (metered vs flat-fee)
+    for r in amount_entropy.recipients {
+        tables
+            .create_row("recipient_amount_variability", addr(&r.recipient_address))
+            .set("amount_variability", r.amount_variability)
+            .set("total_payments", r.total_payments as i64)
+            .set("block_number", amount_entropy.block_number);
+    }
+
+    Ok(tables.to_database_changes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_to_date() {
+        assert_eq!(unix_to_date(0), "1970-01-01");
+        assert_eq!(unix_to_date(1_709_251_199), "2024-02-29");
+        assert_eq!(unix_to_date(1_709_251_200), "2024-03-01");
+    }
+
+    #[test]
+    fn test_unix_to_timestamp_known_values() {
+        assert_eq!(unix_to_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(unix_to_timestamp(1_609_459_200), "2021-01-01 00:00:00");
+        assert_eq!(unix_to_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(unix_to_timestamp(1_709_251_199), "2024-02-29 23:59:59");
+        assert_eq!(unix_to_timestamp(4_102_444_799), "2099-12-31 23:59:59");
+
+        // Pre-1970 input clamps to the epoch instead of going negative
+        assert_eq!(unix_to_timestamp(-1), "1970-01-01 00:00:00");
+        assert_eq!(unix_to_timestamp(i64::MIN), "1970-01-01 00:00:00");
+        assert_eq!(unix_to_date(-86_401), "1970-01-01");
+        assert_eq!(unix_to_hour(-1), "1970-01-01T00");
+    }
+
+    #[test]
+    fn test_payer_day_bucket_matches_timestamp() {
+        for secs in [0, 86_399, 86_400, 1_709_251_199, 1_709_251_200, 1_735_689_599] {
+            assert!(unix_to_timestamp(secs).starts_with(&unix_to_date(secs)));
+        }
+        let key = payer_day_key("0xABC", &unix_to_date(1_709_251_199));
+        assert_eq!(key, "0xabc:2024-02-29");
+        assert_eq!(key.rsplit_once(':'), Some(("0xabc", "2024-02-29")));
+    }
+
+    #[test]
+    fn test_growth_rate_across_two_days() {
+        // Day 1: 4 settlements, day 2: 5 settlements => +25.00%
+        assert_eq!(growth_rate_bps(4, 5), Some(2500));
+        // Day 1: 4 settlements, day 2: 3 settlements => -25.00%
+        assert_eq!(growth_rate_bps(4, 3), Some(-2500));
+    }
+
+    fn settlement(id: &str, payer: &str) -> x402::Settlement {
+        x402::Settlement {
+            id: id.to_string(),
+            payer: payer.to_string(),
+            amount: "1000000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_db_out_params() {
+        let params = DbOutParams::parse("min_amount=500&first_payment_only=true");
+        assert_eq!(params.min_amount, BigInt::from(500));
+        assert!(params.first_payment_only);
+
+        let params = DbOutParams::parse("min_amount=0");
+        assert_eq!(params.min_amount, BigInt::zero());
+        assert!(!params.first_payment_only);
+    }
+
+    #[test]
+    fn test_parse_amount_beyond_i64() {
+        // 1,000 USDC whale payment and a raw amount far past i64::MAX
+        assert_eq!(parse_amount("1000000000"), BigInt::from(1_000_000_000u64));
+        let huge = parse_amount("50000000000000000000000");
+        assert_eq!(huge.to_string(), "50000000000000000000000");
+        assert!(huge > BigInt::from(i64::MAX));
+        assert_eq!(parse_amount(""), BigInt::zero());
+        assert_eq!(parse_amount("12abc"), BigInt::zero());
+
+        // Still clears the min-amount filter instead of becoming 0
+        let params = DbOutParams::parse("min_amount=1000000");
+        assert!(huge >= *params.min_amount_for(&format_address(&USDC)));
+    }
+
+    #[test]
+    fn test_db_out_min_amount_per_token() {
+        let usdc = format_address(&USDC);
+        let eurc = format_address(&EURC);
+
+        let params = DbOutParams::parse("");
+        assert_eq!(*params.min_amount_for(&usdc), BigInt::zero());
+        assert_eq!(*params.min_amount_for(&eurc), BigInt::zero());
+
+        // Beyond i64, and a token without its own minimum falls back to min_amount
+        let params = DbOutParams::parse("min_amount_usdc=10000000000000000000000");
+        assert_eq!(
+            params.min_amount_for(&usdc.to_uppercase()).to_string(),
+            "10000000000000000000000"
+        );
+        assert_eq!(*params.min_amount_for(&eurc), BigInt::zero());
+
+        let params = DbOutParams::parse("min_amount=7&min_amount_usdc=500&min_amount_eurc=300");
+        assert_eq!(*params.min_amount_for(&usdc), BigInt::from(500));
+        assert_eq!(*params.min_amount_for(&eurc), BigInt::from(300));
+        assert_eq!(*params.min_amount_for(NATIVE_TOKEN), BigInt::from(7));
+
+        // Garbage means no minimum, not one that drops everything
+        let params = DbOutParams::parse("min_amount=lots&min_amount_usdc=-5");
+        assert_eq!(*params.min_amount_for(&usdc), BigInt::zero());
+        assert_eq!(params.min_amount, BigInt::zero());
+    }
+
+    #[test]
+    fn test_protocol_totals_snapshot_cadence() {
+        let params = DbOutParams::parse("snapshot_every=5");
+        let emitted: Vec<u64> = (1..=12).filter(|b| params.should_snapshot(*b)).collect();
+        assert_eq!(emitted, vec![5, 10]);
+
+        // The final block of a range always gets a snapshot
+        let params = DbOutParams::parse("snapshot_every=5&snapshot_final_block=12");
+        let emitted: Vec<u64> = (1..=12).filter(|b| params.should_snapshot(*b)).collect();
+        assert_eq!(emitted, vec![5, 10, 12]);
+
+        // Default: every block
+        assert!(DbOutParams::parse("").should_snapshot(7));
+
+        // Only a spaced-out cadence without a final block can end stale
+        assert!(DbOutParams::parse("snapshot_every=5").snapshot_may_end_stale());
+        assert!(!DbOutParams::parse("snapshot_every=5&snapshot_final_block=12")
+            .snapshot_may_end_stale());
+        assert!(!DbOutParams::parse("").snapshot_may_end_stale());
+    }
+
+    #[test]
+    fn test_first_payments_only_first_of_payer() {
+        let payer = "0x00000000000000000000000000000000000000aa";
+        let settlements = vec![settlement("tx1-0", payer), settlement("tx2-3", payer)];
+
+        // Payer first seen in this block: only its first settlement is an acquisition
+        let firsts = first_payments(&settlements, 1_700_000_000, |_| Some(1_700_000_000));
+        assert_eq!(firsts.len(), 1);
+        assert_eq!(firsts[0].id, "tx1-0");
+
+        // Payer first seen in an earlier block: nothing to emit
+        let firsts = first_payments(&settlements, 1_700_000_000, |_| Some(1_690_000_000));
+        assert!(firsts.is_empty());
+    }
+
+    #[test]
+    fn test_global_seq_sorts_across_blocks() {
+        let earlier = global_seq(30_000_000, 250);
+        let later = global_seq(30_000_001, 3);
+        assert!(earlier < later);
+        assert_eq!(earlier.len(), later.len());
+        assert_eq!(earlier, "00128849018880000250");
+        assert!(global_seq(30_000_000, 9) < global_seq(30_000_000, 10));
+    }
+
+    #[test]
+    fn test_subscription_detected_after_three_periodic_payments() {
+        let s = x402::Settlement {
+            payer: "0x00000000000000000000000000000000000000aa".to_string(),
+            recipient: "0x00000000000000000000000000000000000000bb".to_string(),
+            amount: "5000000".to_string(),
+            ..Default::default()
+        };
+        assert!(subscription_key(&s).is_some());
+
+        // Three weekly 5 USDC payments
+        let first = 1_700_000_000;
+        let week = 7 * 86400;
+        assert_eq!(subscription_interval(2, first, first + week, 3), None);
+        assert_eq!(subscription_interval(3, first, first + 2 * week, 3), Some(week));
+    }
+
+    #[test]
+    fn test_unmatched_settlement_is_not_a_subscription() {
+        let s = x402::Settlement {
+            payer: "0x00000000000000000000000000000000000000aa".to_string(),
+            amount: "0".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(subscription_key(&s), None);
+    }
+
+    #[test]
+    fn test_settlement_gas_priced_in_usd() {
+        // 100k gas at 10 gwei = 1e15 wei; at $3,000/ETH that's $3.00
+        let mut settlements = vec![x402::Settlement {
+            gas_used: "100000".to_string(),
+            gas_price: "10000000000".to_string(),
+            ..Default::default()
+        }];
+        price_gas_usd(&mut settlements, None);
+        assert_eq!(settlements[0].gas_cost_usd, "");
+
+        price_gas_usd(&mut settlements, Some(&BigInt::from(300_000_000_000u64)));
+        assert_eq!(settlements[0].gas_cost_usd, "3000000");
+    }
+
+    #[test]
+    fn test_facilitator_settlement_paths() {
+        // One facilitator's block: two plain EIP-3009, one hybrid, two proxy
+        let types = ["eip3009", "eip3009", "eip3009_proxy", "settled", "settled_with_permit"];
+        let eip3009 = types.iter().filter(|t| is_eip3009_path(t)).count();
+        let proxy = types.iter().filter(|t| is_proxy_path(t)).count();
+        assert_eq!((eip3009, proxy), (3, 3));
+        // The hybrid is in both, so the two overlap by one
+        assert_eq!(eip3009 + proxy, types.len() + 1);
+        assert!(!is_eip3009_path("") && !is_proxy_path(""));
+    }
+
+    #[test]
+    fn test_cost_per_dollar_known_gas_and_volume() {
+        // 100k gas at 0.01 gwei = 1e15 wei; at $3,000/ETH that's $3.00
+        let gas_wei = BigInt::from(100_000u64) * BigInt::from(10_000_000u64) * BigInt::from(1_000u64);
+        let price = BigInt::from(300_000_000_000u64);
+        let gas_usd = gas_cost_usd(&gas_wei, &price);
+        assert_eq!(gas_usd, BigInt::from(3_000_000u64));
+
+        // $3.00 of gas to settle $1,000.00 of volume = 0.30% = 30 bps
+        let volume = BigInt::from(1_000_000_000u64);
+        assert_eq!(cost_per_dollar_bps(&gas_usd, &volume), 30);
+    }
+
+    #[test]
+    fn test_subsidy_rate_known_gas_and_volume() {
+        // Two facilitators spend $2.00 and $1.00 of gas in the block
+        let delta = |old: u64, new: u64| DeltaBigInt {
+            operation: Operation::Update,
+            ordinal: 0,
+            key: String::new(),
+            old_value: BigInt::from(old),
+            new_value: BigInt::from(new),
+        };
+        let deltas = vec![delta(0, 2_000_000), delta(5_000_000, 6_000_000)];
+        let gas_usd = gas_usd_added(&deltas);
+        assert_eq!(gas_usd, BigInt::from(3_000_000u64));
+
+        // $3.00 of gas subsidized across $1,000.00 settled = 30 bps
+        let volume = BigInt::from(1_000_000_000u64);
+        assert_eq!(cost_per_dollar_bps(&gas_usd, &volume), 30);
+    }
+
+    #[test]
+    fn test_cost_per_dollar_zero_volume() {
+        assert_eq!(cost_per_dollar_bps(&BigInt::from(5), &BigInt::zero()), 0);
+    }
+
+    #[test]
+    fn test_top_by_reach_keeps_broadest() {
+        let reach = vec![
+            ("0xaaa".to_string(), 5),  // few payers, maybe high value
+            ("0xbbb".to_string(), 40),
+            ("0xccc".to_string(), 12),
+            ("0xddd".to_string(), 1),
+        ];
+        let top = top_by_reach(reach, 2);
+        assert_eq!(
+            top,
+            vec![("0xbbb".to_string(), 40), ("0xccc".to_string(), 12)]
+        );
+    }
+
+    #[test]
+    fn test_leaderboard_entries_ranked_by_volume() {
+        // Equal volumes rank by address; the top n are kept
+        let volumes = vec![
+            ("0xaaa".to_string(), BigInt::from(500)),
+            ("0xbbb".to_string(), BigInt::from(9_000)),
+            ("0xccc".to_string(), BigInt::from(500)),
+            ("0xddd".to_string(), BigInt::from(10)),
+        ];
+        let entries = leaderboard_entries("payer", volumes, 3);
+        let ranked: Vec<_> = entries
+            .iter()
+            .map(|e| (e.rank, e.address.as_str(), e.total_volume.as_str()))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![(1, "0xbbb", "9000"), (2, "0xaaa", "500"), (3, "0xccc", "500")]
+        );
+        assert!(entries.iter().all(|e| e.role == "payer"));
+    }
+
+    #[test]
+    fn test_leaderboard_tiers_bound_candidates() {
+        let delta = |operation, old: u64, new: u64| DeltaBigInt {
+            operation,
+            ordinal: 0,
+            key: "0xaaa".to_string(),
+            old_value: BigInt::from(old),
+            new_value: BigInt::from(new),
+        };
+        assert_eq!(leaderboard_tier_move(&delta(Operation::Create, 0, 5)), Some((None, 3)));
+        assert_eq!(leaderboard_tier_move(&delta(Operation::Update, 5, 7)), None);
+        assert_eq!(leaderboard_tier_move(&delta(Operation::Update, 7, 9)), Some((Some(3), 4)));
+
+        // Tier 20 holds 2 addresses, tier 12 holds 3, tier 4 holds 100:
+        // a top 4 never needs to read tier 4
+        let counts = |tier: u64| match tier {
+            20 => 2,
+            12 => 3,
+            4 => 100,
+            _ => 0,
+        };
+        // Rosters list at least the current residents, plus stale entries
+        let listed = |tier: u64| match tier {
+            20 => 2,
+            12 => 5,
+            4 => 3000,
+            _ => 0,
+        };
+        assert_eq!(leaderboard_candidate_tiers(counts, listed, 4), vec![20, 12]);
+        assert_eq!(leaderboard_candidate_tiers(counts, listed, 2), vec![20]);
+        assert_eq!(leaderboard_candidate_tiers(counts, listed, 500), vec![20, 12, 4]);
+        // A top 10 may read 80 entries: tier 4's 3000 are left out
+        assert_eq!(leaderboard_candidate_tiers(counts, listed, 10), vec![20, 12]);
+        // The first occupied tier is read whatever its size
+        assert_eq!(leaderboard_candidate_tiers(|_| 100, |_| 3000, 1), vec![95]);
+
+        // Reach counts share the tiers: 3 -> 4 unique payers moves tier 2 -> 3
+        let reach = Deltas {
+            deltas: vec![DeltaInt64 {
+                operation: Operation::Update,
+                ordinal: 0,
+                key: "0xbbb".to_string(),
+                old_value: 3,
+                new_value: 4,
+            }],
+        };
+        let empty = || Deltas { deltas: vec![] };
+        let [_, _, (role, deltas)] = leaderboard_role_deltas(empty(), empty(), reach);
+        assert_eq!(role, "reach");
+        assert_eq!(leaderboard_tier_move(&deltas[0]), Some((Some(2), 3)));
+    }
+
+    #[test]
+    fn test_extra_proxy_sig_classification() {
+        let sig = [0xabu8; 32];
+        let extra = parse_proxy_sigs(&format!("0x{}:settled_with_permit", Hex(&sig)));
+        assert_eq!(extra.len(), 1);
+
+        let log = eth::Log {
+            address: X402_PROXY.to_vec(),
+            topics: vec![sig.to_vec()],
+            ..Default::default()
+        };
+        assert_eq!(
+            classify_proxy_log(&log, &extra).as_deref(),
+            Some("settled_with_permit")
+        );
+        assert_eq!(classify_proxy_log(&log, &[]), None);
+    }
+
+    #[test]
+    fn test_parse_proxy_sigs_skips_malformed() {
+        let extra = parse_proxy_sigs("0x1234:settled,notahex:settled,0x00:unknown");
+        assert!(extra.is_empty());
+    }
+
+    #[test]
+    fn test_proxy_addresses_param() {
+        let mainnet = "0x4020aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa0003";
+        let proxies = parse_proxy_addresses(Some(mainnet));
+        assert_eq!(proxies.len(), 1);
+        assert!(is_proxy(&proxies, &Hex::decode(&mainnet[2..]).unwrap()));
+        assert!(!is_proxy(&proxies, &X402_PROXY));
+
+        // Unset, empty, or any bad entry keeps the built-in proxies
+        for value in [None, Some(""), Some(" "), Some("0x1234")] {
+            assert_eq!(parse_proxy_addresses(value), PROXY_ADDRESSES.to_vec());
+        }
+        let mixed = format!("{},0xnothex0000000000000000000000000000000000", mainnet);
+        assert_eq!(parse_proxy_addresses(Some(&mixed)), PROXY_ADDRESSES.to_vec());
+
+        // A custom proxy's transfers count as funding, not payments
+        let transfer = |to: Vec<u8>| TransferEvent {
+            from: vec![0x11; 20],
+            to,
+            amount: "100".to_string(),
+            log_index: 0,
+        };
+        let (payments, fundings) = split_proxy_funding(
+            vec![transfer(proxies[0].to_vec()), transfer(X402_PROXY.to_vec())],
+            &proxies,
+        );
+        assert_eq!((payments.len(), fundings.len()), (1, 1));
+    }
+
+    #[test]
+    fn test_gas_savings_batch_of_three() {
+        let settlement = |tx: &str| x402::Settlement {
+            tx_hash: tx.to_string(),
+            gas_used: "45000".to_string(),
+            ..Default::default()
+        };
+        let settlements = vec![settlement("0x01"), settlement("0x01"), settlement("0x01")];
+
+        let batches = settlement_batches(&settlements);
+        assert_eq!(batches, vec![("0x01".to_string(), 3, 45_000)]);
+        // 3 x 21,000 baseline = 63,000 vs 45,000 actual
+        assert_eq!(batch_gas_saved(3, 45_000), 18_000);
+        // A lone settlement costing more than the baseline saves nothing
+        assert_eq!(batch_gas_saved(1, 80_000), 0);
+    }
+
+    #[test]
+    fn test_block_timestamp_absent_or_zero() {
+        let mut blk = eth::Block::default();
+        assert_eq!(block_timestamp(&blk), None);
+
+        blk.header = Some(eth::BlockHeader {
+            timestamp: Some(prost_types::Timestamp { seconds: 0, nanos: 0 }),
+            ..Default::default()
+        });
+        assert_eq!(block_timestamp(&blk), None);
+
+        blk.header.as_mut().unwrap().timestamp =
+            Some(prost_types::Timestamp { seconds: 1_700_000_000, nanos: 0 });
+        assert_eq!(block_timestamp(&blk).map(|t| t.seconds), Some(1_700_000_000));
+    }
+
+    #[test]
+    fn test_display_address_checksum_param() {
+        let lower = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        assert_eq!(
+            display_address(lower, true),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert_eq!(display_address(lower, false), lower);
+        // Mixed-case input still emits lowercase without the param
+        assert_eq!(display_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", false), lower);
+        // Non-addresses pass through
+        assert_eq!(display_address(NATIVE_TOKEN, true), NATIVE_TOKEN);
+        assert_eq!(display_address("", true), "");
+        assert!(DbOutParams::parse("checksum=true").checksum);
+    }
+
+    #[test]
+    fn test_clickhouse_sink_formatting() {
+        let s = x402::Settlement {
+            id: "0xabc-1".to_string(),
+            amount: "50000000000000000000000".to_string(),
+            fee_amount: String::new(),
+            timestamp: Some(prost_types::Timestamp { seconds: 1_735_689_600, nanos: 0 }),
+            ..Default::default()
+        };
+        let column = |params: &str, name: &str| {
+            let mut tables = Tables::new();
+            write_settlement_row(&mut tables, &s, &DbOutParams::parse(params));
+            let changes = tables.to_database_changes();
+            changes.table_changes[0]
+                .fields
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| f.new_value.clone())
+        };
+
+        // Postgres stays the default
+        assert_eq!(column("", "block_timestamp").as_deref(), Some("2025-01-01 00:00:00"));
+        assert_eq!(column("", "fee_amount").as_deref(), Some(""));
+
+        let ch = "sink=clickhouse";
+        assert_eq!(column(ch, "block_timestamp").as_deref(), Some("1735689600"));
+        assert_eq!(column(ch, "amount").as_deref(), Some("50000000000000000000000"));
+        assert_eq!(column(ch, "fee_amount").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn test_stat_row_without_timestamp_is_null() {
+        let mut tables = Tables::new();
+        let row = tables.create_row("payers", "0xpayer").set("total_payments", 1i64);
+        set_timestamp(row, "first_payment_at", None, &Postgres);
+        let day_two = prost_types::Timestamp { seconds: 86_400, nanos: 0 };
+        set_timestamp(row, "last_payment_at", Some(&day_two), &Postgres);
+
+        let changes = tables.to_database_changes();
+        let fields = &changes.table_changes[0].fields;
+        let field = |name: &str| fields.iter().find(|f| f.name == name);
+        assert!(field("first_payment_at").is_none());
+        assert_eq!(
+            field("last_payment_at").map(|f| f.new_value.as_str()),
+            Some("1970-01-02 00:00:00")
+        );
+    }
+
+    #[test]
+    fn test_settlement_row_without_timestamp() {
+        let s = x402::Settlement {
+            id: "0xabc-1".to_string(),
+            block_number: 30_000_000,
+            timestamp: None,
+            timestamp_missing: true,
+            global_seq: global_seq(30_000_000, 1),
+            ..Default::default()
+        };
+        let mut tables = Tables::new();
+        write_settlement_row(&mut tables, &s, &DbOutParams::parse(""));
+
+        let changes = tables.to_database_changes();
+        let fields = &changes.table_changes[0].fields;
+        let field = |name: &str| fields.iter().find(|f| f.name == name);
+        assert_eq!(field("timestamp_missing").map(|f| f.new_value.as_str()), Some("true"));
+        assert!(field("block_timestamp").is_none());
+    }
+
+    #[test]
+    fn test_scale_amount() {
+        assert_eq!(scale_amount("1000000", 6), "1.000000");
+        assert_eq!(scale_amount("1", 6), "0.000001");
+        assert_eq!(scale_amount("0", 6), "0.000000");
+        assert_eq!(scale_amount("-2500", 6), "-0.002500");
+        assert_eq!(scale_amount("42", 0), "42");
+        // Beyond u64 and f64 precision
+        assert_eq!(
+            scale_amount("123456789012345678901234567890", 18),
+            "123456789012.345678901234567890"
+        );
+        assert_eq!(scale_amount("abc", 6), "abc");
+
+        assert_eq!(token_decimals(&format_address(&USDC)), 6);
+        assert_eq!(token_decimals("0x60a3E35Cc302bFA44Cb288Bc5a4F316Fdb1adb42"), 6);
+        assert_eq!(token_decimals(NATIVE_TOKEN), 18);
+        // Unknown tokens fall back to the default, with a warning
+        let unknown = format_address(&[0x42; 20]);
+        assert_eq!(token_decimals(&unknown), DEFAULT_TOKEN_DECIMALS);
+    }
+
+    #[test]
+    fn test_duplicate_authorization_is_flagged() {
+        let settlement = |id: &str| x402::Settlement {
+            id: id.to_string(),
+            payer: "0xpayer".to_string(),
+            nonce: "0x01".to_string(),
+            ..Default::default()
+        };
+        let settlements = [settlement("0xabc-1"), settlement("0xdef-4")];
+
+        // The store keeps the first settlement id per authorization
+        let mut first_seen = std::collections::HashMap::new();
+        for s in &settlements {
+            first_seen
+                .entry(authorization_id(s).unwrap())
+                .or_insert_with(|| s.id.clone());
+        }
+
+        let params = DbOutParams::parse("dedup_authorizations=true");
+        let mut tables = Tables::new();
+        for s in &settlements {
+            let auth_id = authorization_id(s).unwrap();
+            match duplicate_of(s, first_seen.get(&auth_id).cloned()) {
+                Some(first_id) => {
+                    write_duplicate_authorization_row(&mut tables, s, &auth_id, &first_id)
+                }
+                None => write_settlement_row(&mut tables, s, &params),
+            }
+        }
+
+        let changes = tables.to_database_changes();
+        let rows = |table: &str| changes.table_changes.iter().filter(|c| c.table == table).count();
+        assert_eq!(rows("settlements"), 1);
+        assert_eq!(rows("duplicate_authorizations"), 1);
+        // Proxy settlements have no authorization to dedup on
+        assert_eq!(authorization_id(&x402::Settlement::default()), None);
+    }
+
+    #[test]
+    fn test_settlement_row_field_projection() {
+        let s = x402::Settlement {
+            id: "0xabc-1".to_string(),
+            tx_hash: "0xabc".to_string(),
+            payer: "0x00000000000000000000000000000000000000aa".to_string(),
+            recipient: "0x00000000000000000000000000000000000000bb".to_string(),
+            amount: "10000".to_string(),
+            ..Default::default()
+        };
+        let params = DbOutParams::parse("fields=payer,recipient,amount,tx_hash");
+        let mut tables = Tables::new();
+        write_settlement_row(&mut tables, &s, &params);
+
+        let changes = tables.to_database_changes();
+        let mut names: Vec<&str> =
+            changes.table_changes[0].fields.iter().map(|f| f.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["amount", "payer", "recipient", "tx_hash"]);
+        assert!(DbOutParams::parse("").fields.is_none());
+    }
+
+    #[test]
+    fn test_split_tables_by_type() {
+        use substreams_database_change::pb::database::table_change::PrimaryKey;
+        let settlement = |id: &str, settlement_type: &str| x402::Settlement {
+            id: id.to_string(),
+            settlement_type: settlement_type.to_string(),
+            ..Default::default()
+        };
+        let params = DbOutParams::parse("split_tables_by_type=true");
+        let mut tables = Tables::new();
+        write_settlement_row(&mut tables, &settlement("0xabc-1", "settled"), &params);
+        write_settlement_row(&mut tables, &settlement("0xdef-2", "eip3009"), &params);
+
+        let changes = tables.to_database_changes();
+        let table_of = |id: &str| {
+            changes
+                .table_changes
+                .iter()
+                .find(|c| matches!(&c.primary_key, Some(PrimaryKey::Pk(pk)) if pk == id))
+                .map(|c| c.table.as_str())
+        };
+        assert_eq!(table_of("0xabc-1"), Some("settlements_settled"));
+        assert_eq!(table_of("0xdef-2"), Some("settlements_eip3009"));
+    }
+
+    #[test]
+    fn test_settlement_row_address_labels() {
+        let s = x402::Settlement {
+            id: "0xabc-1".to_string(),
+            payer: "0x00000000000000000000000000000000000000aa".to_string(),
+            recipient: "0x00000000000000000000000000000000000000bb".to_string(),
+            ..Default::default()
+        };
+        let params = DbOutParams::parse(
+            "address_labels=0x00000000000000000000000000000000000000AA:Alice,0xdef:Bob",
+        );
+        let mut tables = Tables::new();
+        write_settlement_row(&mut tables, &s, &params);
+
+        let changes = tables.to_database_changes();
+        let fields = &changes.table_changes[0].fields;
+        let field = |name: &str| fields.iter().find(|f| f.name == name);
+        assert_eq!(field("payer_label").map(|f| f.new_value.as_str()), Some("Alice"));
+        assert!(field("recipient_label").is_none());
+    }
+
+    #[test]
+    fn test_amount_variability_flat_vs_metered() {
+        let histogram = |amounts: &[u64]| {
+            let mut counts = vec![0i64; AMOUNT_HISTOGRAM_BUCKETS as usize];
+            for a in amounts {
+                counts[amount_bucket(&BigInt::from(*a)) as usize] += 1;
+            }
+            counts
+        };
+
+        // Flat fee: $0.01 every time
+        let flat = amount_variability_bps(&histogram(&[10_000; 20]));
+        // Metered: amounts spanning several orders of magnitude
+        let metered = amount_variability_bps(&histogram(&[
+            1_000, 3_500, 12_000, 40_000, 150_000, 600_000, 2_000_000, 9_000_000,
+        ]));
+
+        assert_eq!(flat, 0);
+        assert!(metered > 5_000, "metered score {}", metered);
+        assert_eq!(amount_variability_bps(&[]), 0);
+    }
+
+    #[test]
+    fn test_settlement_row_types() {
+        let s = x402::Settlement {
+            id: "0xabc-3".to_string(),
+            log_index: 3,
+            block_number: 30_000_000,
+            timestamp: Some(prost_types::Timestamp { seconds: 1_700_000_000, nanos: 0 }),
+            amount: "10000".to_string(),
+            gas_used: "52000".to_string(),
+            gas_price: "1000000".to_string(),
+            fee_amount: "0".to_string(),
+            ..Default::default()
+        };
+        let row = settlement_row(&s);
+        assert_eq!(row.block_timestamp, Some(1_700_000_000));
+        assert_eq!(row.amount_u64, Some(10_000));
+        assert_eq!(row.gas_used, 52_000);
+        assert_eq!(row.gas_price, Some(1_000_000));
+        assert_eq!(row.fee_amount, Some(0));
+        assert_eq!(row.nonce, None);
+        assert_eq!(row.fee_recipient, None);
+
+        // An amount beyond u64 keeps its exact string and is null as an integer
+        let big = x402::Settlement {
+            amount: "340282366920938463463374607431768211455".to_string(),
+            ..s
+        };
+        let row = settlement_row(&big);
+        assert_eq!(row.amount_u64, None);
+        assert_eq!(row.amount, big.amount);
+    }
+
+    #[test]
+    fn test_export_rows_scaled_and_filtered() {
+        let settlement = |token: &str, amount: &str| x402::Settlement {
+            token: token.to_string(),
+            amount: amount.to_string(),
+            fee_amount: "2500".to_string(),
+            ..Default::default()
+        };
+        let usdc = format_address(&USDC);
+        let settlements = vec![
+            settlement(&usdc, "1250000"),
+            settlement(&usdc, "400"),
+            settlement("0xunknown", "5000"),
+        ];
+
+        let rows = export_rows(&settlements, &DbOutParams::parse("min_amount=1000"));
+        let kept = settlements.iter().filter(|s| parse_amount(&s.amount) >= BigInt::from(1000));
+        assert_eq!(rows.len(), kept.count());
+        assert_eq!(rows[0].amount_decimal.as_deref(), Some("1.250000"));
+        assert_eq!(rows[0].fee_amount_decimal.as_deref(), Some("0.002500"));
+        // Unknown decimals fall back to the default
+        assert_eq!(rows[1].amount_decimal.as_deref(), Some("0.005000"));
+
+        assert_eq!(export_rows(&settlements, &DbOutParams::parse("")).len(), settlements.len());
+    }
+
+    #[test]
+    fn test_settlement_row_schema_lists_fields() {
+        let schema = settlement_row_schema();
+        let names: Vec<_> = schema.iter().map(|(name, _, _)| *name).collect();
+        assert_eq!(names.len(), 20);
+        for name in ["block_timestamp", "amount_u64", "gas_used", "global_seq"] {
+            assert!(names.contains(&name), "missing column {}", name);
+        }
+        assert!(schema.contains(&("gas_used", "UINT64", false)));
+        assert!(schema.contains(&("block_timestamp", "INT64", true)));
+    }
+
+    #[test]
+    fn test_permit_share_mix() {
+        // 3 plain Settled, 1 SettledWithPermit = 25%
+        assert_eq!(permit_share_bps(3, 1), 2_500);
+        assert_eq!(permit_share_bps(0, 4), 10_000);
+        assert_eq!(permit_share_bps(0, 0), 0);
+    }
+
+    #[test]
+    fn test_personal_records_only_on_increase() {
+        let payment = |id: &str, amount: &str| x402::Settlement {
+            id: id.to_string(),
+            payer: "0xPayer".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: amount.to_string(),
+            token: format_address(&USDC),
+            ..Default::default()
+        };
+        let native = x402::Settlement { token: NATIVE_TOKEN.to_string(), ..payment("n", "1") };
+        let settlements = vec![
+            payment("a", "5000"),  // below prior max of 10000
+            payment("b", "12000"), // record
+            payment("c", "12000"), // ties don't count
+            payment("d", "8000"),
+            // Native ETH never counts toward store_payment_max
+            x402::Settlement { amount: "900000000000000000".into(), ..native },
+            payment("e", "20000"), // record
+        ];
+        let records = personal_records(&settlements, |_| Some(BigInt::from(10_000)));
+        let ids: Vec<_> = records.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "e"]);
+
+        // A brand-new payer's first payment sets the bar, not a record
+        let records = personal_records(&settlements, |_| None);
+        let ids: Vec<_> = records.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "e"]);
+    }
+
+    #[test]
+    fn test_proxy_funding_excluded_from_payments() {
+        let payer = vec![0x11u8; 20];
+        let recipient = vec![0x22u8; 20];
+        let transfers = vec![
+            TransferEvent {
+                from: payer.clone(),
+                to: X402_PROXY.to_vec(),
+                amount: "5000000".to_string(),
+                log_index: 0,
+            },
+            TransferEvent {
+                from: payer.clone(),
+                to: recipient.clone(),
+                amount: "10000".to_string(),
+                log_index: 2,
+            },
+        ];
+
+        let (payments, fundings) = split_proxy_funding(transfers, PROXY_ADDRESSES);
+        // The first proxy event now pairs with the real payment, not the funding
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].to, recipient);
+        assert_eq!(payments[0].amount, "10000");
+        assert_eq!(fundings.len(), 1);
+        assert_eq!(fundings[0].amount, "5000000");
+    }
+
+    #[test]
+    fn test_passthrough_settle_then_forward() {
+        let payer = vec![0x11u8; 20];
+        let recipient = vec![0x22u8; 20];
+        let merchant = vec![0x33u8; 20];
+        let facilitator = vec![0x44u8; 20];
+        let transfer = |from: &[u8], to: &[u8], amount: &str, log_index| TransferEvent {
+            from: from.to_vec(),
+            to: to.to_vec(),
+            amount: amount.to_string(),
+            log_index,
+        };
+        let transfers = vec![
+            transfer(&payer, &recipient, "10000", 1),
+            // Recipient pays the facilitator's fee, then forwards the rest
+            transfer(&recipient, &facilitator, "100", 2),
+            transfer(&recipient, &merchant, "9900", 3),
+        ];
+        let s = x402::Settlement {
+            recipient: format_address(&recipient),
+            facilitator: format_address(&facilitator),
+            ..settlement("s", &format_address(&payer))
+        };
+
+        let forwards = passthrough_transfers(&s, &transfers);
+        assert_eq!(forwards.len(), 1);
+        assert_eq!(forwards[0].to, merchant);
+        assert_eq!(forwards[0].amount, "9900");
+
+        // A recipient that keeps the payment is not a pass-through
+        assert!(passthrough_transfers(&s, &transfers[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_block_key_cap_defers_overflow() {
+        let with = |id: &str, payer: &str, recipient: &str| x402::Settlement {
+            recipient: recipient.to_string(),
+            facilitator: "0xfac".to_string(),
+            ..settlement(id, payer)
+        };
+        // Keys: 0xfac, 0xa, 0xr1 | 0xb (0xr1 again) | 0xc, 0xr2 | 0xa, 0xr1
+        let block = vec![
+            with("s0", "0xa", "0xr1"),
+            with("s1", "0xb", "0xr1"),
+            with("s2", "0xc", "0xr2"),
+            with("s3", "0xa", "0xr1"),
+        ];
+        let ids = |list: &[x402::Settlement]| {
+            list.iter().map(|s| s.id.clone()).collect::<Vec<_>>()
+        };
+
+        let (kept, deferred) = cap_block_keys(block.clone(), 5);
+        // s2 would bring 6 keys; it and everything after it wait, even s3
+        // whose keys are already counted, so block order is preserved
+        assert_eq!(ids(&kept), vec!["s0", "s1"]);
+        assert_eq!(ids(&deferred), vec!["s2", "s3"]);
+        assert_eq!(
+            cap_warning(100, deferred.len(), 5).as_deref(),
+            Some("block 100: 2 settlements deferred to later blocks, over max_block_keys=5")
+        );
+
+        // The overflow is carried into the next processed blocks, each chunk
+        // within the cap: s2 and s3 bring 0xfac, 0xc, 0xr2, 0xa, 0xr1
+        let schedule = defer_schedule(41, deferred.clone(), 3);
+        let keyed: Vec<_> = schedule.iter().map(|(k, c)| (k.as_str(), ids(c))).collect();
+        let expected = vec![
+            ("42:1", vec!["s2".to_string()]),
+            ("43:2", vec!["s3".to_string()]),
+        ];
+        assert_eq!(keyed, expected);
+        // Every deferred settlement is scheduled exactly once
+        let scheduled: usize = schedule.iter().map(|(_, c)| c.len()).sum();
+        assert_eq!(scheduled, deferred.len());
+
+        // Far over the cap, the last chunk takes the rest instead of dropping it
+        let flood: Vec<_> = (0..40)
+            .map(|i| with(&format!("f{}", i), &format!("0xp{}", i), "0xr1"))
+            .collect();
+        let schedule = defer_schedule(0, flood, 3);
+        assert_eq!(schedule.len() as u64, MAX_DEFER_BLOCKS);
+        assert_eq!(schedule.iter().map(|(_, c)| c.len()).sum::<usize>(), 40);
+
+        // Under the default cap, nothing is deferred and no warning is logged
+        let (kept, deferred) = cap_block_keys(block, DEFAULT_MAX_BLOCK_KEYS);
+        assert_eq!(kept.len(), 4);
+        assert!(deferred.is_empty());
+        assert!(defer_schedule(41, deferred, DEFAULT_MAX_BLOCK_KEYS).is_empty());
+        assert_eq!(cap_warning(100, 0, DEFAULT_MAX_BLOCK_KEYS), None);
+        assert_eq!(max_block_keys(""), DEFAULT_MAX_BLOCK_KEYS);
+        assert_eq!(max_block_keys("max_block_keys=5"), 5);
+    }
+
+    #[test]
+    fn test_denomination_counts() {
+        let usdc = format_address(&USDC);
+        let paid = |id: &str, amount: &str| x402::Settlement {
+            recipient: "0x00000000000000000000000000000000000000cc".to_string(),
+            token: usdc.clone(),
+            amount: amount.to_string(),
+            ..settlement(id, "0x00000000000000000000000000000000000000aa")
+        };
+        let block = vec![
+            paid("a", "5000"),       // $0.005
+            paid("b", "10000"),      // $0.01
+            paid("c", "250000"),     // $0.25
+            paid("d", "2500000000"), // $2,500
+        ];
+
+        let counts = denomination_counts(&block);
+        assert_eq!(counts, vec![("sub_cent", 1), ("cents", 2), ("thousands_plus", 1)]);
+        assert_eq!(denomination(&BigInt::from(999_999_999u64)), "hundreds");
+    }
+
+    #[test]
+    fn test_rollup_counts_share_one_filter() {
+        let usdc = format_address(&USDC);
+        let paid = |recipient: &str, token: &str| x402::Settlement {
+            recipient: recipient.to_string(),
+            amount: "100".to_string(),
+            token: token.to_string(),
+            ..settlement("s", "0xaaa")
+        };
+        let mut block = vec![
+            paid("0xbbb", &usdc),
+            paid("0xbbb", NATIVE_TOKEN),
+            paid("0xaaa", &usdc),
+            paid("", &usdc),
+        ];
+        block[3].amount = "0".into();
+        tag_self_payments(&mut block);
+
+        // The unmatched one never counts; the self-payment only without
+        // exclude_self. Native ETH counts as a settlement but adds no volume.
+        assert_eq!(rollup_count(&block, false), 3);
+        assert_eq!(rollup_count(&block, true), 2);
+        let volume_settlements = block
+            .iter()
+            .filter(|s| counts_toward_rollup(s, true) && is_token_amount(s))
+            .count();
+        assert_eq!(volume_settlements, 1);
+    }
+
+    #[test]
+    fn test_hourly_rollup_buckets() {
+        // 2024-02-29 23:59:59 UTC, leap day rolling into March
+        assert_eq!(unix_to_hour(1_709_251_199), "2024-02-29T23");
+        assert_eq!(unix_to_hour(1_709_251_200), "2024-03-01T00");
+        // 2024-12-31 23:30:00 UTC into the new year
+        assert_eq!(unix_to_hour(1_735_687_800), "2024-12-31T23");
+        assert_eq!(unix_to_hour(1_735_689_600), "2025-01-01T00");
+        assert_eq!(&unix_to_hour(1_735_689_600)[..10], unix_to_date(1_735_689_600));
+
+        let hour = "2025-01-01T00".to_string();
+        let volume = |old: u64, new: u64| DeltaBigInt {
+            operation: Operation::Update,
+            ordinal: 0,
+            key: hour.clone(),
+            old_value: BigInt::from(old),
+            new_value: BigInt::from(new),
+        };
+        let volume_deltas = vec![volume(500, 600), volume(600, 650)];
+        let count_deltas = vec![DeltaInt64 {
+            operation: Operation::Update,
+            ordinal: 0,
+            key: hour.clone(),
+            old_value: 3,
+            new_value: 5,
+        }];
+
+        let (key, total, count) = hourly_rollup(&volume_deltas, &count_deltas).unwrap();
+        assert_eq!((key, total.to_string(), count), ("2025-01-01T00", "650".to_string(), 5));
+        assert!(hourly_rollup(&[], &count_deltas).is_none());
+    }
+
+    #[test]
+    fn test_iso_week_and_month_buckets() {
+        assert_eq!(unix_to_iso_week(0), "1970-W01"); // Thursday 1970-01-01
+        // 2020 has 53 ISO weeks: Thursday 2020-12-31 through Sunday 2021-01-03
+        assert_eq!(unix_to_iso_week(1_609_372_800), "2020-W53");
+        assert_eq!(unix_to_iso_week(1_609_718_399), "2020-W53"); // 2021-01-03 23:59:59
+        assert_eq!(unix_to_iso_week(1_609_718_400), "2021-W01"); // Monday 2021-01-04
+        // Late-December Mondays can open week 1 of the next year
+        assert_eq!(unix_to_iso_week(1_577_664_000), "2020-W01"); // 2019-12-30
+        assert_eq!(unix_to_iso_week(1_735_516_800), "2025-W01"); // 2024-12-30
+        assert_eq!(unix_to_iso_week(1_735_430_399), "2024-W52"); // 2024-12-29 23:59:59
+
+        assert_eq!(unix_to_month(1_709_251_199), "2024-02");
+        assert_eq!(unix_to_month(1_735_689_600), "2025-01");
+
+        let delta = |key: &str, new: u64| DeltaBigInt {
+            operation: Operation::Update,
+            ordinal: 0,
+            key: key.to_string(),
+            old_value: BigInt::zero(),
+            new_value: BigInt::from(new),
+        };
+        let count = |key: &str, new: i64| DeltaInt64 {
+            operation: Operation::Update,
+            ordinal: 0,
+            key: key.to_string(),
+            old_value: 0,
+            new_value: new,
+        };
+        let volume_deltas = vec![
+            delta("week:2025-W01", 100),
+            delta("month:2025-01", 900),
+            delta("week:2025-W01", 150),
+            delta("month:2025-01", 950),
+        ];
+        let count_deltas = vec![count("week:2025-W01", 4), count("month:2025-01", 12)];
+        let rollups: Vec<_> = period_rollups(&volume_deltas, &count_deltas)
+            .into_iter()
+            .map(|(key, volume, count)| (key, volume.to_string(), count))
+            .collect();
+        assert_eq!(
+            rollups,
+            vec![
+                ("week:2025-W01", "150".to_string(), 4),
+                ("month:2025-01", "950".to_string(), 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_rollup_counts_batch_gas_once() {
+        let batch = |tx: &str, amount: &str, gas_used: &str| x402::Settlement {
+            tx_hash: tx.to_string(),
+            amount: amount.to_string(),
+            gas_used: gas_used.to_string(),
+            ..Default::default()
+        };
+        let block = vec![
+            batch("0xaa", "1000000", "90000"),
+            batch("0xaa", "250000", "90000"),
+            batch("0xbb", "5", "52000"),
+        ];
+        assert_eq!(block_rollup(&block), (3, BigInt::from(1_250_005), 142_000));
+        assert_eq!(block_rollup(&[]), (0, BigInt::zero(), 0));
+
+        assert!(!DbOutParams::parse("").empty_blocks);
+        assert!(DbOutParams::parse("empty_blocks=true").empty_blocks);
+    }
+
+    #[test]
+    fn test_native_eth_left_out_of_token_volume() {
+        let usdc = x402::Settlement {
+            tx_hash: "0xaa".to_string(),
+            payer: "0xaaa".to_string(),
+            recipient: "0xbbb".to_string(),
+            amount: "1000000".to_string(),
+            token: format_address(&USDC),
+            ..Default::default()
+        };
+        // 0.5 ETH in wei would dwarf any USDC volume if summed with it
+        let eth = x402::Settlement {
+            tx_hash: "0xbb".to_string(),
+            amount: "500000000000000000".to_string(),
+            token: NATIVE_TOKEN.to_string(),
+            ..usdc.clone()
+        };
+        assert!(is_token_amount(&usdc));
+        assert!(!is_token_amount(&eth));
+
+        let block = vec![usdc.clone(), eth.clone()];
+        assert_eq!(block_rollup(&block).0, 2);
+        assert_eq!(block_rollup(&block).1, BigInt::from(1_000_000));
+        assert_eq!(
+            net_flow_changes(&block),
+            vec![
+                ("0xbbb".to_string(), BigInt::from(1_000_000)),
+                ("0xaaa".to_string(), BigInt::from(-1_000_000)),
+            ]
+        );
+        assert!(payment_extreme_keys(&eth).is_empty());
+    }
+
+    #[test]
+    fn test_heartbeat_once_per_hour() {
+        // Four quiet blocks spanning 13:00-14:59 UTC on 2024-03-01
+        let base = 1_709_298_000; // 2024-03-01 13:00:00
+        let blocks = [base + 12, base + 1_800, base + 3_602, base + 5_400];
+
+        // Emulate store_heartbeat_hours: set_if_not_exists only creates
+        let mut seen = std::collections::HashSet::new();
+        let mut rows = Vec::new();
+        for secs in blocks {
+            let hour = unix_to_hour(secs);
+            let deltas: Vec<DeltaInt64> = seen
+                .insert(hour.clone())
+                .then_some(DeltaInt64 {
+                    operation: Operation::Create,
+                    ordinal: 0,
+                    key: hour,
+                    old_value: 0,
+                    new_value: secs,
+                })
+                .into_iter()
+                .collect();
+            for (hour, secs) in new_heartbeat_hours(&deltas) {
+                rows.push((hour.to_string(), secs));
+            }
+        }
+
+        assert_eq!(
+            rows,
+            vec![
+                ("2024-03-01T13".to_string(), base + 12),
+                ("2024-03-01T14".to_string(), base + 3_602),
+            ]
+        );
+        assert!(DbOutParams::parse("heartbeat=true").heartbeat);
+        assert!(!DbOutParams::parse("").heartbeat);
+    }
+
+    #[test]
+    fn test_gas_amount_correlation_sign() {
+        let sums = |points: &[(u64, u64)]| {
+            points.iter().fold(CORRELATION_SUMS.map(|_| BigInt::zero()), |acc, (x, y)| {
+                let inc = correlation_increments(&BigInt::from(*x), &BigInt::from(*y));
+                std::array::from_fn(|i| acc[i].clone() + inc[i].clone())
+            })
+        };
+        // Gas price in wei against amount in atomic units
+        let correlated = [(1_000_000, 10_000), (2_000_000, 25_000), (3_000_000, 29_000)];
+        let anti = [(1_000_000, 30_000), (2_000_000, 18_000), (3_000_000, 9_000)];
+
+        let r = pearson_bps(&sums(&correlated)).unwrap();
+        assert!(r > 0 && r <= 10_000, "r = {}", r);
+        let r = pearson_bps(&sums(&anti)).unwrap();
+        assert!((-10_000..0).contains(&r), "r = {}", r);
+
+        // Perfectly linear data correlates fully
+        assert_eq!(pearson_bps(&sums(&[(1, 2), (2, 4), (3, 6)])), Some(10_000));
+        // A constant gas price has no correlation
+        assert_eq!(pearson_bps(&sums(&[(5, 2), (5, 4)])), None);
+    }
+
+    #[test]
+    fn test_settlement_uuid_stable_v5() {
+        assert_eq!(Hex(sha1(b"abc")).to_string(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        let uuid = settlement_uuid("ab12-3");
+        assert_eq!(uuid, "b92919eb-8971-588f-a064-e2c785fcdd24");
+        assert_eq!(settlement_uuid("ab12-3"), uuid);
+        assert_ne!(settlement_uuid("ab12-4"), uuid);
+
+        // 8-4-4-4-12 hex, version 5, RFC 4122 variant
+        let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&uuid[14..15], "5");
+        assert!("89ab".contains(&uuid[19..20]));
+    }
+
+    #[test]
+    fn test_nonce_shared_by_two_authorizers() {
+        let nonce = "0x00000000000000000000000000000000000000000000000000000000000000aa";
+        let with_nonce = |id: &str, payer: &str| x402::Settlement {
+            nonce: nonce.to_string(),
+            ..settlement(id, payer)
+        };
+        let block = vec![
+            with_nonce("a", "0xalice"),
+            with_nonce("b", "0xbob"),
+            with_nonce("c", "0xalice"),
+            settlement("proxy", "0xcarol"), // Proxy path: no nonce
+        ];
+
+        let markers = nonce_authorizer_markers(&block, "2024-03-01");
+        // Emulate store_nonce_authorizers: only the first of each pair is created
+        let mut seen = std::collections::HashSet::new();
+        let deltas: Vec<DeltaInt64> = markers
+            .into_iter()
+            .filter(|key| seen.insert(key.clone()))
+            .map(|key| DeltaInt64 {
+                operation: Operation::Create,
+                ordinal: 0,
+                key,
+                old_value: 0,
+                new_value: 1,
+            })
+            .collect();
+
+        let owners = new_marker_owners(&deltas);
+        let key = format!("2024-03-01/{}", nonce);
+        assert_eq!(owners, vec![key.as_str(), key.as_str()]);
+    }
+
+    #[test]
+    fn test_volume_records_only_on_new_highs() {
+        // End-of-day volumes; emulate store_max_daily_volume's deltas
+        let days = [100u64, 50, 200, 150, 200, 300];
+        let mut high: Option<u64> = None;
+        let mut records = Vec::new();
+        for volume in days {
+            let delta = DeltaBigInt {
+                operation: if high.is_none() { Operation::Create } else { Operation::Update },
+                ordinal: 0,
+                key: "all_time".to_string(),
+                old_value: BigInt::from(high.unwrap_or(0)),
+                new_value: BigInt::from(high.unwrap_or(0).max(volume)),
+            };
+            if is_volume_record(&delta) {
+                records.push(delta.new_value.to_string());
+            }
+            high = Some(high.unwrap_or(0).max(volume));
+        }
+        // Day 1 sets the bar; ties and dips are not records
+        assert_eq!(records, vec!["200", "300"]);
+    }
+
+    #[test]
+    fn test_proxy_payment_and_fee_attribution() {
+        let payer = vec![0x11u8; 20];
+        let recipient = vec![0x22u8; 20];
+        let facilitator = vec![0x44u8; 20];
+        // One Settled event; the fee transfer comes first in the logs
+        let transfers = vec![
+            TransferEvent {
+                from: payer.clone(),
+                to: facilitator.clone(),
+                amount: "50000".to_string(),
+                log_index: 1,
+            },
+            TransferEvent {
+                from: payer.clone(),
+                to: recipient.clone(),
+                amount: "1000000".to_string(),
+                log_index: 2,
+            },
+        ];
+
+        let pairs = pair_proxy_transfers(&transfers, &facilitator, &[3]);
+        assert_eq!(pairs.len(), 1);
+        let (payment, fee) = pairs[0];
+        assert_eq!(payment.unwrap().to, recipient);
+        assert_eq!(payment.unwrap().amount, "1000000");
+        assert_eq!(fee.unwrap().to, facilitator);
+        assert_eq!(fee.unwrap().amount, "50000");
+
+        // Without a fee transfer, only the payment is attributed
+        let pairs = pair_proxy_transfers(&transfers[1..], &facilitator, &[3]);
+        assert_eq!(pairs[0].0.unwrap().amount, "1000000");
+        assert!(pairs[0].1.is_none());
+    }
+
+    #[test]
+    fn test_proxy_settlements_paired_by_log_index() {
+        let facilitator = vec![0x44u8; 20];
+        let transfer = |from: u8, to: u8, amount: &str, log_index| TransferEvent {
+            from: vec![from; 20],
+            to: vec![to; 20],
+            amount: amount.to_string(),
+            log_index,
+        };
+        // Settled at 2 and 5, each right after its own transfer; the second
+        // payment's transfer is emitted first in the list
+        let transfers = vec![transfer(0x12, 0x33, "9000", 4), transfer(0x11, 0x22, "10000", 1)];
+
+        let pairs: Vec<_> = pair_proxy_transfers(&transfers, &facilitator, &[2, 5])
+            .into_iter()
+            .map(|(payment, _)| {
+                let p = payment.unwrap();
+                (p.from[0], p.to[0], p.amount.as_str())
+            })
+            .collect();
+        assert_eq!(pairs, vec![(0x11, 0x22, "10000"), (0x12, 0x33, "9000")]);
+
+        // Events emitted before their transfers pair forward
+        let transfers = vec![transfer(0x11, 0x22, "10000", 1), transfer(0x12, 0x33, "9000", 3)];
+        let pairs = pair_proxy_transfers(&transfers, &facilitator, &[0, 2]);
+        assert_eq!(pairs[0].0.unwrap().amount, "10000");
+        assert_eq!(pairs[1].0.unwrap().amount, "9000");
+    }
+
+    #[test]
+    fn test_facilitator_sla_ranks_reliable_above_poor() {
+        // Never misses, settles in 6s, gas 1 bps of volume
+        let good = facilitator_sla("good".to_string(), 1_000, 0, Some(6), 1);
+        // Misses one in five, settles in 10 minutes, gas 40 bps of volume
+        let poor = facilitator_sla("poor".to_string(), 800, 200, Some(600), 40);
+
+        assert!(good.sla_score_bps > poor.sla_score_bps);
+        assert_eq!(good.success_bps, 10_000);
+        assert_eq!(poor.success_bps, 8_000);
+        // 10000 * 60 / 66 = 9090, 10000 * 10 / 11 = 9090
+        assert_eq!(good.sla_score_bps, (5 * 10_000 + 3 * 9_090 + 2 * 9_090) / 10);
+        // Unknown latency scores as neutral
+        assert_eq!(facilitator_sla("new".to_string(), 1, 0, None, 0).latency_score_bps, 5_000);
+    }
+
+    #[test]
+    fn test_settlement_latency() {
+        // Signed (valid_after) 42s before the settlement block
+        assert_eq!(settlement_latency(1_700_000_042, 1_700_000_000), Some(42));
+        // Unknown valid_after, or one in the future, yields no sample
+        assert_eq!(settlement_latency(1_700_000_042, 0), None);
+        assert_eq!(settlement_latency(1_700_000_042, 1_700_000_100), None);
+    }
+
+    #[test]
+    fn test_distinct_tokens_usdc_then_eurc() {
+        let marker = |key: &str| DeltaInt64 {
+            operation: Operation::Create,
+            ordinal: 0,
+            key: key.to_string(),
+            old_value: 0,
+            new_value: 1,
+        };
+        // Block 1: pays in USDC. Block 2: USDC again (marker exists, so an
+        // Update at most) and EURC for the first time.
+        let usdc = "0x833589fcd6edb6e08f4c7c32d4f71b54bda02913";
+        let eurc = "0x60a3e35cc302bfa44cb288bc5a4f316fdb1adb42";
+        let block1 = vec![marker(&format!("0xpayer:{}", usdc))];
+        let block2 = vec![
+            DeltaInt64 { operation: Operation::Update, ..marker(&format!("0xpayer:{}", usdc)) },
+            marker(&format!("0xpayer:{}", eurc)),
+        ];
+
+        let distinct = new_marker_owners(&block1).len() + new_marker_owners(&block2).len();
+        assert_eq!(distinct, 2);
+        assert_eq!(new_marker_owners(&block2), vec!["0xpayer"]);
+    }
+
+    #[test]
+    fn test_flagged_recipient_captured() {
+        let flagged = parse_address_list(" 0xBAD0000000000000000000000000000000000001 ,");
+        let settlements = vec![
+            x402::Settlement {
+                id: "clean".to_string(),
+                payer: "0x00000000000000000000000000000000000000aa".to_string(),
+                recipient: "0x00000000000000000000000000000000000000bb".to_string(),
+                ..Default::default()
+            },
+            x402::Settlement {
+                id: "hit".to_string(),
+                payer: "0x00000000000000000000000000000000000000aa".to_string(),
+                recipient: "0xbad0000000000000000000000000000000000001".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        let hits = flag_settlements(&settlements, &flagged);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0.id, "hit");
+        assert_eq!(hits[0].1, "recipient");
+    }
+
+    #[test]
+    fn test_hll_estimate_within_error() {
+        for true_count in [500usize, 20_000] {
+            let mut registers = vec![0u32; HLL_REGISTERS];
+            for i in 0..true_count {
+                let (register, rank) = hll_observe(&format!("0x{:040x}", i));
+                registers[register] = registers[register].max(rank);
+            }
+            let harmonic_sum: f64 = registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+            let zeros = registers.iter().filter(|r| **r == 0).count();
+
+            let estimate = hll_estimate(harmonic_sum, zeros) as f64;
+            let error = (estimate - true_count as f64).abs() / true_count as f64;
+            // Within 3 standard errors (3 x 3.25%)
+            assert!(error < 0.0975, "estimate {} for {}", estimate, true_count);
+        }
+    }
+
+    fn padded(addr: &[u8]) -> Vec<u8> {
+        let mut topic = vec![0u8; 12];
+        topic.extend_from_slice(addr);
+        topic
+    }
+
+    fn auth_log(token: &[u8], authorizer: &[u8], nonce: u8, index: u32) -> eth::Log {
+        let authorizer: [u8; 20] = authorizer.try_into().unwrap();
+        eth::Log {
+            address: token.to_vec(),
+            index,
+            block_index: index,
+            ..abi::authorization_used_log(&authorizer, &[nonce; 32])
+        }
+    }
+
+    fn transfer_log(token: &[u8], from: &[u8], to: &[u8], amount: u64, index: u32) -> eth::Log {
+        let mut data = vec![0u8; 24];
+        data.extend_from_slice(&amount.to_be_bytes());
+        eth::Log {
+            address: token.to_vec(),
+            topics: vec![abi::TRANSFER_TOPIC.to_vec(), padded(from), padded(to)],
+            data,
+            index,
+            block_index: index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_canceled_authorization_is_not_a_settlement() {
+        let payer = [0x11u8; 20];
+        let mut canceled = auth_log(&EURC, &payer, 5, 0);
+        canceled.topics[0] = abi::AUTHORIZATION_CANCELED_TOPIC.to_vec();
+        // A transfer from the same payer in the same tx must not pair with it
+        let logs = vec![canceled, transfer_log(&EURC, &payer, &[0x22u8; 20], 10_000, 1)];
+
+        let found = canceled_authorizations(&logs);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, EURC);
+        assert_eq!(found[0].1.authorizer, payer);
+        assert!(group_token_logs(&logs, SETTLEMENT_TOKENS).is_empty());
+    }
+
+    #[test]
+    fn test_calldata_fallback_catches_unlogged_authorizations() {
+        let payer = [0x11u8; 20];
+        let twa_input = |nonce: u8| {
+            let mut input = abi::TRANSFER_WITH_AUTHORIZATION_SELECTOR.to_vec();
+            input.extend(padded(&payer));
+            input.extend(padded(&[0x22; 20]));
+            input.extend(vec![0u8; 28]);
+            input.extend(10_000u32.to_be_bytes());
+            input.extend(vec![0u8; 2 * 32]);
+            input.extend(vec![nonce; 32]);
+            input.extend(vec![0u8; 3 * 32]);
+            input
+        };
+        let call = |index: u32, nonce: u8, state_reverted: bool| eth::Call {
+            index,
+            address: USDC.to_vec(),
+            input: twa_input(nonce),
+            state_reverted,
+            ..Default::default()
+        };
+        // A router makes three authorization calls; only nonce 1 logged its
+        // AuthorizationUsed, and the nonce 3 call reverted
+        let trx = eth::TransactionTrace {
+            calls: vec![
+                eth::Call { index: 1, address: vec![0x44; 20], ..Default::default() },
+                call(2, 1, false),
+                call(3, 2, false),
+                call(4, 3, true),
+            ],
+            ..Default::default()
+        };
+        let logs = vec![auth_log(&USDC, &payer, 1, 0)];
+        let groups = group_token_logs(&logs, SETTLEMENT_TOKENS);
+
+        let unlogged = unlogged_authorization_transfers(&trx, &groups);
+        assert_eq!(unlogged.len(), 1);
+        let (token, call_index, transfer) = &unlogged[0];
+        assert_eq!((*token, *call_index), (USDC.as_slice(), 3));
+        assert_eq!(transfer.from, payer);
+        assert_eq!(transfer.to, vec![0x22; 20]);
+        assert_eq!(transfer.value, "10000");
+        assert_eq!(transfer.nonce, vec![2; 32]);
+
+        // Without any logs, every live authorization call is settled from calldata
+        assert_eq!(unlogged_authorization_transfers(&trx, &[]).len(), 2);
+    }
+
+    #[test]
+    fn test_unlogged_calls_get_distinct_positions() {
+        let log = |index, block_index| eth::Log {
+            index,
+            block_index,
+            ..Default::default()
+        };
+        let first_tx = eth::TransactionTrace {
+            receipt: Some(eth::TransactionReceipt {
+                logs: vec![log(0, 4), log(1, 5)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let second_tx = eth::TransactionTrace {
+            receipt: Some(eth::TransactionReceipt {
+                logs: vec![log(0, 6)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let blk = eth::Block {
+            number: 30_000_000,
+            transaction_traces: vec![first_tx, second_tx],
+            ..Default::default()
+        };
+        let mut next = block_log_end(&blk);
+        assert_eq!(next, 7);
+
+        // Two unlogged calls in the first tx, one in a tx without logs
+        let first = unlogged_positions(&[log(0, 4), log(1, 5)], 2, &mut next);
+        assert_eq!(first, vec![(2, 7), (3, 8)]);
+        assert_eq!(unlogged_positions(&[], 1, &mut next), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn test_reverted_transactions_are_skipped() {
+        let payer = [0x11u8; 20];
+        let trace = |status: eth::TransactionTraceStatus| eth::TransactionTrace {
+            status: status as i32,
+            receipt: Some(eth::TransactionReceipt {
+                logs: vec![
+                    auth_log(&USDC, &payer, 1, 0),
+                    transfer_log(&USDC, &payer, &[0x22u8; 20], 10_000, 1),
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let blk = eth::Block {
+            transaction_traces: vec![trace(eth::TransactionTraceStatus::Reverted)],
+            ..Default::default()
+        };
+
+        let auths: usize = succeeded_traces(&blk)
+            .filter_map(|trx| trx.receipt.as_ref())
+            .flat_map(|r| group_token_logs(&r.logs, SETTLEMENT_TOKENS))
+            .map(|group| group.auths.len())
+            .sum();
+        assert_eq!(auths, 0);
+
+        let blk = eth::Block {
+            transaction_traces: vec![trace(eth::TransactionTraceStatus::Succeeded)],
+            ..Default::default()
+        };
+        assert_eq!(succeeded_traces(&blk).count(), 1);
+    }
+
+    #[test]
+    fn test_base_and_priority_fee_split() {
+        let blk = eth::Block {
+            header: Some(eth::BlockHeader {
+                // 0x0f4240 = 1_000_000 wei
+                base_fee_per_gas: Some(eth::BigInt { bytes: vec![0x0f, 0x42, 0x40] }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let base_fee = block_base_fee(&blk);
+        assert_eq!(base_fee, "1000000");
+        assert_eq!(priority_fee("1250000", &base_fee), "250000");
+        // A price at or under the base fee (or unparsable) has no tip
+        assert_eq!(priority_fee("900000", &base_fee), "0");
+        assert_eq!(priority_fee("", &base_fee), "0");
+
+        // Pre-London headers have no base fee
+        assert_eq!(block_base_fee(&eth::Block::default()), "0");
+        assert_eq!(priority_fee("1250000", "0"), "1250000");
+    }
+
+    #[test]
+    fn test_self_payments_flagged_and_excluded() {
+        let settlement = |payer: &str, recipient: &str, amount: &str| x402::Settlement {
+            payer: payer.to_string(),
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            ..Default::default()
+        };
+        let mut settlements = vec![
+            settlement("0xaaa", "0xbbb", "100"),
+            // Same address, different case
+            settlement("0xAAA", "0xaaa", "5000"),
+            settlement("", "", "0"),
+        ];
+        tag_self_payments(&mut settlements);
+        let flags: Vec<bool> = settlements.iter().map(|s| s.is_self_payment).collect();
+        assert_eq!(flags, vec![false, true, false]);
+
+        let volume = |exclude_self: bool| -> BigInt {
+            settlements
+                .iter()
+                .filter(|s| counts_toward_volume(s, exclude_self))
+                .fold(BigInt::zero(), |total, s| total + parse_amount(&s.amount))
+        };
+        assert_eq!(volume(false), BigInt::from(5100));
+        assert_eq!(volume(true), BigInt::from(100));
+        assert!(DbOutParams::parse("exclude_self=true").exclude_self);
+    }
+
+    #[test]
+    fn test_multicall_transaction_is_tagged() {
+        let facilitator = vec![0x33u8; 20];
+        // Facilitator batches two settlements through Multicall3
+        let batched = eth::TransactionTrace {
+            from: facilitator.clone(),
+            to: MULTICALL3.to_vec(),
+            calls: vec![
+                eth::Call { address: MULTICALL3.to_vec(), ..Default::default() },
+                eth::Call { address: USDC.to_vec(), ..Default::default() },
+                eth::Call { address: USDC.to_vec(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        let aggregator = multicall_aggregator(&batched).map(format_address);
+        assert_eq!(
+            aggregator.as_deref(),
+            Some("0xca11bde05977b3631167028862be2a173976ca11")
+        );
+
+        // Reached through an inner call, e.g. from a facilitator's own router
+        let nested = eth::TransactionTrace {
+            to: vec![0x44u8; 20],
+            calls: vec![
+                eth::Call { address: vec![0x44u8; 20], ..Default::default() },
+                eth::Call { address: MULTICALL3.to_vec(), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(multicall_aggregator(&nested), Some(MULTICALL3.as_slice()));
+
+        let direct = eth::TransactionTrace {
+            from: facilitator,
+            to: USDC.to_vec(),
+            calls: vec![eth::Call { address: USDC.to_vec(), ..Default::default() }],
+            ..Default::default()
+        };
+        assert_eq!(multicall_aggregator(&direct), None);
+    }
+
+    #[test]
+    fn test_interleaved_tokens_pair_within_token() {
+        let payer = [0x11u8; 20];
+        let usdc_recipient = [0x22u8; 20];
+        let eurc_recipient = [0x33u8; 20];
+        let facilitator = [0x44u8; 20];
+
+        // Same payer authorizes on both tokens; the EURC transfer comes first,
+        // so pairing across tokens would hand it to the USDC authorization.
+        let logs = vec![
+            auth_log(&USDC, &payer, 1, 0),
+            auth_log(&EURC, &payer, 2, 1),
+            transfer_log(&EURC, &payer, &eurc_recipient, 9_000, 2),
+            transfer_log(&USDC, &payer, &usdc_recipient, 10_000, 3),
+        ];
+
+        let groups = group_token_logs(&logs, SETTLEMENT_TOKENS);
+        assert_eq!(groups.len(), 2);
+        let tokens: std::collections::HashSet<String> =
+            groups.iter().map(|g| format_address(&g.token)).collect();
+        assert_eq!(tokens.len(), 2);
+        for group in &groups {
+            assert_eq!(group.auths.len(), 1);
+            let index = TransferIndex::new(&group.transfers, &facilitator);
+            let (payment, _) = pair_group_transfers(&group.auths, &index)[0];
+            let payment = payment.unwrap();
+            if group.token == USDC {
+                assert_eq!(payment.to, usdc_recipient);
+                assert_eq!(payment.amount, "10000");
+            } else {
+                assert_eq!(payment.to, eurc_recipient);
+                assert_eq!(payment.amount, "9000");
+            }
+        }
+    }
+
+    #[test]
+    fn test_proxy_settlements_carry_their_token() {
+        let facilitator = [0x44u8; 20];
+        let logs = vec![
+            // Each Settled event (logs 1 and 3) follows its transfer
+            transfer_log(&USDC, &[0x11u8; 20], &[0x22u8; 20], 10_000, 0),
+            transfer_log(&EURC, &[0x12u8; 20], &[0x33u8; 20], 9_000, 2),
+        ];
+        let transfers: Vec<TransferEvent> = logs
+            .iter()
+            .filter(|log| SETTLEMENT_TOKENS.iter().any(|t| log.address == t))
+            .filter_map(decode_erc20_transfer)
+            .collect();
+
+        let tokens: Vec<String> = pair_proxy_transfers(&transfers, &facilitator, &[1, 3])
+            .into_iter()
+            .map(|(payment, _)| transfer_token(&logs, payment.unwrap()))
+            .collect();
+        assert_eq!(tokens, vec![format_address(&USDC), format_address(&EURC)]);
+    }
+
+    #[test]
+    fn test_authorization_fee_split_out() {
+        let payer = vec![0x11u8; 20];
+        let recipient = vec![0x22u8; 20];
+        let facilitator = vec![0x44u8; 20];
+        let auth = AuthorizationUsedEvent {
+            authorizer: payer.clone(),
+            nonce: vec![],
+            log_index: 0,
+            block_index: 0,
+        };
+        let transfer = |from: &[u8], to: &[u8], amount: &str, log_index| TransferEvent {
+            from: from.to_vec(),
+            to: to.to_vec(),
+            amount: amount.to_string(),
+            log_index,
+        };
+
+        // Recipient forwards the facilitator's cut: it keeps 9,900 of 10,000
+        let transfers = vec![
+            transfer(&payer, &recipient, "10000", 1),
+            transfer(&recipient, &facilitator, "100", 2),
+        ];
+        let index = TransferIndex::new(&transfers, &facilitator);
+        let (payment, fee) = pair_authorization_transfers(&auth, &index, &Default::default());
+        let (payment, fee) = (payment.unwrap(), fee.unwrap());
+        assert_eq!(payment.to, recipient);
+        assert_eq!((fee.amount.as_str(), fee.to.clone()), ("100", facilitator.clone()));
+        assert_eq!(net_payment_amount(payment, Some(fee)), BigInt::from(9_900));
+
+        // Payer pays the fee directly: the payment is untouched
+        let transfers = vec![
+            transfer(&payer, &facilitator, "100", 1),
+            transfer(&payer, &recipient, "10000", 2),
+        ];
+        let index = TransferIndex::new(&transfers, &facilitator);
+        let (payment, fee) = pair_authorization_transfers(&auth, &index, &Default::default());
+        let payment = payment.unwrap();
+        assert_eq!(payment.to, recipient);
+        assert_eq!(fee.map(|f| f.amount.as_str()), Some("100"));
+        assert_eq!(net_payment_amount(payment, fee), BigInt::from(10_000));
+    }
+
+    #[test]
+    fn test_same_authorizer_twice_gets_distinct_transfers() {
+        let payer = vec![0x11u8; 20];
+        let facilitator = vec![0x44u8; 20];
+        let auth = |log_index| AuthorizationUsedEvent {
+            authorizer: payer.clone(),
+            nonce: vec![],
+            log_index,
+            block_index: log_index,
+        };
+        let transfer = |to: u8, amount: &str, log_index| TransferEvent {
+            from: payer.clone(),
+            to: vec![to; 20],
+            amount: amount.to_string(),
+            log_index,
+        };
+
+        // Both authorizations precede both transfers, so every transfer
+        // follows every authorization
+        let auths = vec![auth(0), auth(1)];
+        let transfers = vec![transfer(0x22, "10000", 3), transfer(0x33, "25000", 4)];
+        let index = TransferIndex::new(&transfers, &facilitator);
+        let amounts: Vec<_> = pair_group_transfers(&auths, &index)
+            .into_iter()
+            .map(|(payment, _)| payment.unwrap().amount.as_str())
+            .collect();
+        assert_eq!(amounts, vec!["10000", "25000"]);
+
+        // Pairing follows log order, not the order authorizations are given in
+        let reversed = vec![auth(1), auth(0)];
+        let amounts: Vec<_> = pair_group_transfers(&reversed, &index)
+            .into_iter()
+            .map(|(payment, _)| payment.unwrap().amount.as_str())
+            .collect();
+        assert_eq!(amounts, vec!["25000", "10000"]);
+
+        // A third authorization finds both transfers taken and stays unmatched
+        let pairs = pair_group_transfers(&[auth(0), auth(1), auth(2)], &index);
+        assert!(pairs[2].0.is_none());
+    }
+
+    #[test]
+    fn test_pairing_many_transfers() {
+        // A batch of 2,000 authorizations, each followed by its payment and a
+        // fee to the facilitator: 6,000 logs. Pairing goes through the sender
+        // index, so this stays near-linear instead of 2,000 x 4,000 scans.
+        let facilitator = vec![0xffu8; 20];
+        let n = 2_000u32;
+        let payer = |i: u32| {
+            let mut a = vec![0u8; 20];
+            a[16..].copy_from_slice(&i.to_be_bytes());
+            a
+        };
+        let mut auths = Vec::new();
+        let mut transfers = Vec::new();
+        for i in 0..n {
+            let base = i * 3;
+            auths.push(AuthorizationUsedEvent {
+                authorizer: payer(i),
+                nonce: vec![],
+                log_index: base,
+                block_index: base,
+            });
+            transfers.push(TransferEvent {
+                from: payer(i),
+                to: vec![0xaa; 20],
+                amount: (1_000 + i).to_string(),
+                log_index: base + 1,
+            });
+            transfers.push(TransferEvent {
+                from: payer(i),
+                to: facilitator.clone(),
+                amount: "10".into(),
+                log_index: base + 2,
+            });
+        }
+
+        let index = TransferIndex::new(&transfers, &facilitator);
+        let pairs = pair_group_transfers(&auths, &index);
+        for (i, (auth, (payment, fee))) in auths.iter().zip(pairs).enumerate() {
+            let payment = payment.unwrap();
+            assert_eq!(payment.amount, (1_000 + i).to_string());
+            assert_eq!(payment.log_index, auth.log_index + 1);
+            assert_eq!(fee.unwrap().log_index, auth.log_index + 2);
+        }
+    }
+
+    #[test]
+    fn test_new_vs_returning_same_day() {
+        let day = 1_700_006_400; // 2023-11-15 00:00:00 UTC
+        let newcomer = "0x00000000000000000000000000000000000000aa";
+        let regular = "0x00000000000000000000000000000000000000bb";
+        let pay = |payer: &str, amount: &str| x402::Settlement {
+            payer: payer.to_string(),
+            amount: amount.to_string(),
+            ..Default::default()
+        };
+        let settlements = vec![pay(newcomer, "10000"), pay(regular, "25000")];
+
+        let (new_volume, returning_volume) =
+            new_vs_returning_volume(&settlements, day + 3_600, |payer| {
+                if payer == newcomer {
+                    Some(day + 60) // first paid earlier today
+                } else {
+                    Some(day - 86_400 * 3) // first paid three days ago
+                }
+            });
+        assert_eq!(new_volume, BigInt::from(10_000));
+        assert_eq!(returning_volume, BigInt::from(25_000));
+    }
+
+    #[test]
+    fn test_native_proxy_payment() {
+        let payer = vec![0x11u8; 20];
+        let recipient = vec![0x22u8; 20];
+        let trx = eth::TransactionTrace {
+            from: payer.clone(),
+            to: X402_PROXY.to_vec(),
+            calls: vec![
+                // payer -> proxy with the value, then proxy -> recipient
+                eth::Call {
+                    caller: payer.clone(),
+                    address: X402_PROXY.to_vec(),
+                    value: Some(eth::BigInt { bytes: vec![0x03, 0x8d, 0x7e, 0xa4, 0xc6, 0x80, 0x00] }),
+                    ..Default::default()
+                },
+                eth::Call {
+                    caller: X402_PROXY.to_vec(),
+                    address: recipient.clone(),
+                    value: Some(eth::BigInt { bytes: vec![0x03, 0x8d, 0x7e, 0xa4, 0xc6, 0x80, 0x00] }),
+                    ..Default::default()
+                },
+                // A valueless call from the proxy isn't a payment
+                eth::Call {
+                    caller: X402_PROXY.to_vec(),
+                    address: USDC.to_vec(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let payments = native_proxy_payments(&trx, PROXY_ADDRESSES);
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].payer, payer);
+        assert_eq!(payments[0].recipient, recipient);
+        assert_eq!(payments[0].amount, "1000000000000000"); // 0.001 ETH
+    }
+
+    /// Build the (payers, spend) tier histogram of payer totals
+    fn spend_tiers(totals: &[u64]) -> Vec<(f64, f64)> {
+        let mut tiers = vec![(0.0, 0.0); AMOUNT_HISTOGRAM_BUCKETS as usize];
+        for total in totals {
+            let tier = amount_bucket(&BigInt::from(*total)) as usize;
+            tiers[tier].0 += 1.0;
+            tiers[tier].1 += *total as f64;
+        }
+        tiers
+    }
+
+    #[test]
+    fn test_payer_gini_skewed_vs_uniform() {
+        // 99 payers at $0.01 and one whale at $1M
+        let mut skewed = vec![10_000u64; 99];
+        skewed.push(1_000_000_000_000);
+        assert!(tier_gini_bps(&spend_tiers(&skewed)) > 9_000);
+
+        // Everyone spends $1
+        let uniform = vec![1_000_000u64; 100];
+        assert!(tier_gini_bps(&spend_tiers(&uniform)) < 500);
+
+        assert_eq!(tier_gini_bps(&[]), 0);
+    }
+
+    #[test]
+    fn test_count_unmatched_keeps_volume_matched() {
+        let matched = x402::Settlement {
+            payer: "0xPayer".into(),
+            recipient: "0xrecipient".into(),
+            amount: "1000000".into(),
+            token: format_address(&USDC),
+            facilitator: "0xfac".into(),
+            ..Default::default()
+        };
+        let unmatched = x402::Settlement {
+            payer: "0xfac".into(),
+            amount: "0".into(),
+            facilitator: "0xfac".into(),
+            ..Default::default()
+        };
+        let settlements = [matched.clone(), unmatched.clone()];
+
+        let matched_count = settlements.iter().filter(|s| !is_unmatched(s)).count() as i64;
+        let unmatched_count = settlements.iter().filter(|s| is_unmatched(s)).count() as i64;
+        assert_eq!(stat_count(matched_count, unmatched_count, true), 2);
+        assert_eq!(stat_count(matched_count, unmatched_count, false), 1);
+
+        let volume: BigInt = settlements
+            .iter()
+            .filter(|s| counts_toward_payer_volume(s, false))
+            .map(|s| parse_amount(&s.amount))
+            .fold(BigInt::zero(), |acc, a| acc + a);
+        assert_eq!(volume.to_string(), "1000000");
+
+        // Both maps emit a row per address in the block, lowercased as the
+        // stores key it, whether or not its volume moved
+        assert_eq!(
+            stat_row_keys(&settlements, payer_key),
+            vec!["0xpayer".to_string(), "0xfac".to_string()]
+        );
+        assert_eq!(stat_row_keys(&settlements, facilitator_key), vec!["0xfac".to_string()]);
+
+        // A block of only unmatched settlements adds no volume, yet its payer
+        // and facilitator still get rows for their new unmatched counts
+        let only_unmatched = [unmatched];
+        assert!(!only_unmatched.iter().any(|s| counts_toward_payer_volume(s, false)));
+        assert_eq!(stat_row_keys(&only_unmatched, payer_key), vec!["0xfac".to_string()]);
+        assert_eq!(stat_row_keys(&only_unmatched, facilitator_key), vec!["0xfac".to_string()]);
+        let no_payer = x402::Settlement { payer: ZERO_ADDR.into(), ..matched };
+        assert!(stat_row_keys(&[no_payer], payer_key).is_empty());
+    }
+
+    #[test]
+    fn test_confirmations_below_head() {
+        assert_eq!(confirmations(25_000_012, 25_000_000), 12);
+        assert_eq!(confirmations(25_000_000, 25_000_000), 0);
+        assert_eq!(confirmations(24_999_999, 25_000_000), 0);
+    }
+
+    #[test]
+    fn test_reset_excludes_pre_reset_volume() {
+        let params = "reset_at_block=100";
+        assert!(before_reset(params, 99));
+        assert!(!before_reset(params, 100));
+        assert!(!before_reset("", 99));
+
+        // 100 spent before the reset, 50 after
+        let baseline = BigInt::from(100u64);
+        let total = BigInt::from(150u64);
+        assert_eq!(since_reset(&total, Some(baseline)).to_string(), "50");
+        assert_eq!(since_reset(&total, None).to_string(), "150");
+
+        // 4 payments before the reset, 7 in all; a stale baseline never goes negative
+        assert_eq!(count_since_reset(7, Some(BigInt::from(4))), 3);
+        assert_eq!(count_since_reset(7, None), 7);
+        assert_eq!(count_since_reset(2, Some(BigInt::from(4))), 0);
+    }
+
+    #[test]
+    fn test_daily_type_counts_two_types() {
+        let settlement = |settlement_type: &str| x402::Settlement {
+            settlement_type: settlement_type.into(),
+            ..Default::default()
+        };
+        let settlements = vec![settlement("eip3009"), settlement("settled"), settlement("eip3009")];
+
+        let counts = daily_type_counts(&settlements, "2025-01-15");
+        assert_eq!(
+            counts,
+            vec![
+                ("2025-01-15:eip3009".to_string(), 2),
+                ("2025-01-15:settled".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batching_counts_single_and_batch() {
+        let settlement = |tx: &str| x402::Settlement {
+            tx_hash: tx.into(),
+            ..Default::default()
+        };
+        let settlements = vec![
+            settlement("0xsingle"),
+            settlement("0xbatch"),
+            settlement("0xbatch"),
+            settlement("0xbatch"),
+        ];
+        assert_eq!(batching_counts(&settlements), (1, 3, 3));
+        assert_eq!(batching_counts(&[]), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_top_facilitator_ranked_first() {
+        let volumes = vec![
+            ("0xsmall".to_string(), BigInt::from(50u64)),
+            ("0xtop".to_string(), BigInt::from(500u64)),
+            ("0xmid".to_string(), BigInt::from(100u64)),
+        ];
+        let ranks = top_k_facilitator_ranks(volumes, 2);
+
+        let settlement = |facilitator: &str| x402::Settlement {
+            facilitator: facilitator.into(),
+            ..Default::default()
+        };
+        let mut settlements = vec![settlement("0xTOP"), settlement("0xmid"), settlement("0xsmall")];
+        apply_facilitator_ranks(&mut settlements, &ranks);
+
+        let ranked: Vec<u32> = settlements.iter().map(|s| s.facilitator_rank).collect();
+        assert_eq!(ranked, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_stat_deltas_skip_deletes() {
+        let delta = |operation, key: &str, old: u64, new: u64| DeltaBigInt {
+            operation,
+            ordinal: 0,
+            key: key.into(),
+            old_value: BigInt::from(old),
+            new_value: BigInt::from(new),
+        };
+        let deltas = Deltas {
+            deltas: vec![
+                delta(Operation::Update, "0xa", 100, 150),
+                delta(Operation::Delete, "0xb", 30, 0),
+                delta(Operation::Create, "0xc", 0, 20),
+            ],
+        };
+
+        let rows: Vec<_> = stat_deltas(deltas).map(|d| (d.key, d.new_value)).collect();
+        assert_eq!(
+            rows,
+            vec![("0xa".to_string(), BigInt::from(150)), ("0xc".to_string(), BigInt::from(20))]
+        );
+    }
+
+    #[test]
+    fn test_collapse_deltas_one_row_per_address() {
+        let delta = |operation, key: &str, old: u64, new: u64| DeltaBigInt {
+            operation,
+            ordinal: 0,
+            key: key.into(),
+            old_value: BigInt::from(old),
+            new_value: BigInt::from(new),
+        };
+        // Payer a pays twice this block (existing total 100), b pays once (new)
+        let deltas = vec![
+            delta(Operation::Update, "0xa", 100, 150),
+            delta(Operation::Create, "0xb", 0, 30),
+            delta(Operation::Update, "0xa", 150, 175),
+        ];
+
+        let rows: Vec<_> = collapse_deltas(&deltas)
+            .into_iter()
+            .map(|(key, change, total)| (key, change.to_string(), total.to_string()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("0xa", "75".to_string(), "175".to_string()),
+                ("0xb", "30".to_string(), "30".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fee_undercutter_flagged() {
+        // Most settlements pay a 1% fee; one facilitator charges 0.1%
+        let mut counts = vec![0i64; FEE_RATE_BUCKETS as usize];
+        counts[fee_rate_bucket(100) as usize] = 90;
+        counts[fee_rate_bucket(10) as usize] = 10;
+        let median = histogram_median_bps(&counts);
+        assert_eq!(median, Some(100));
+
+        assert!(is_low_fee(10, median));
+        assert!(!is_low_fee(100, median));
+        assert!(!is_low_fee(150, median));
+        assert!(!is_low_fee(0, histogram_median_bps(&[0; 3])));
+    }
+
+    #[test]
+    fn test_invariant_violation_reported() {
+        let good = x402::Settlement {
+            payer: "0xpayer".into(),
+            recipient: "0xrecipient".into(),
+            amount: "1000".into(),
+            ..Default::default()
+        };
+        let lost_recipient = x402::Settlement {
+            payer: "0xpayer".into(),
+            amount: "500".into(),
+            ..Default::default()
+        };
+        let garbled = x402::Settlement {
+            payer: "0xpayer".into(),
+            recipient: "0xrecipient".into(),
+            amount: "-1".into(),
+            ..Default::default()
+        };
+
+        assert!(invariant_violations(std::slice::from_ref(&good)).is_empty());
+        assert_eq!(
+            invariant_violations(&[good, lost_recipient, garbled]),
+            vec![("amount_not_uint", 1), ("payment_without_recipient", 1)]
+        );
+    }
+
+    #[test]
+    fn test_category_totals_two_categories() {
+        let categories = parse_address_labels("0xAPI:api, 0xcontent:content,malformed");
+        assert_eq!(categories.len(), 2);
+
+        let pay = |recipient: &str, amount: &str| x402::Settlement {
+            payer: "0xpayer".into(),
+            recipient: recipient.into(),
+            amount: amount.into(),
+            ..Default::default()
+        };
+        let settlements = vec![
+            pay("0xapi", "100"),
+            pay("0xcontent", "40"),
+            pay("0xApi", "50"),
+            pay("0xelse", "7"),
+        ];
+
+        let totals: Vec<_> = category_totals(&settlements, &categories)
+            .into_iter()
+            .map(|(c, volume, count)| (c, volume.to_string(), count))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("api".to_string(), "150".to_string(), 2),
+                ("content".to_string(), "40".to_string(), 1),
+                ("uncategorized".to_string(), "7".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_proxy_protocol_version() {
+        assert_eq!(proxy_protocol_version(&X402_PROXY), "exact_permit2_v1");
+        assert_eq!(proxy_protocol_version(&X402_UPTO_PROXY), "upto_permit2_v1");
+        assert_eq!(proxy_protocol_version(&USDC), "unknown");
+    }
+
+    #[test]
+    fn test_time_to_second_payment() {
+        let first_at = 1_700_000_000;
+        let second_at = first_at + 3_600;
+
+        // Count deltas for the recipient: first payment, then second
+        assert!(!reached_second_payment(0, 1));
+        assert!(reached_second_payment(1, 2));
+        assert!(!reached_second_payment(2, 3));
+        // Both payments in one block
+        assert!(reached_second_payment(0, 2));
+
+        assert_eq!(time_to_second_payment(Some(first_at), Some(second_at)), Some(3_600));
+        assert_eq!(time_to_second_payment(Some(first_at), None), None);
+    }
+
+    #[test]
+    fn test_wide_row_totals_as_of_settlement() {
+        let payer_a = "0x00000000000000000000000000000000000000aa";
+        let payer_b = "0x00000000000000000000000000000000000000bb";
+        let facilitator = "0x00000000000000000000000000000000000000ff";
+        let with = |id: &str, payer: &str, amount: &str| x402::Settlement {
+            recipient: "0x00000000000000000000000000000000000000cc".to_string(),
+            amount: amount.to_string(),
+            facilitator: facilitator.to_string(),
+            ..settlement(id, payer)
+        };
+        let block = vec![
+            with("s0", payer_a, "100"),
+            with("s1", payer_b, "10"),
+            with("s2", payer_a, "50"),
+        ];
+
+        // Stores as read in the block: payer A had spent 1000 and the
+        // facilitator settled 5 before it
+        let (payer_volume, _) = volume_and_count_contributions(&block, |s| stat_key(&s.payer));
+        let (_, facilitator_count) =
+            volume_and_count_contributions(&block, |s| s.facilitator.to_lowercase());
+        let spent = rewind_totals(&payer_volume, |k| {
+            BigInt::from(if k == payer_a { 1_150 } else { 10 })
+        });
+        let settled = rewind_totals(&facilitator_count, |_| BigInt::from(8));
+
+        assert_eq!(spent, vec![BigInt::from(1_100), BigInt::from(10), BigInt::from(1_150)]);
+        assert_eq!(settled, vec![BigInt::from(6), BigInt::from(7), BigInt::from(8)]);
+    }
+
+    #[test]
+    fn test_activity_bitmap_sets_active_days() {
+        let today = unix_day(1_700_000_000);
+        let mut slots = vec![None; ACTIVITY_WINDOW_DAYS as usize];
+        let mut record = |day: i64| {
+            slots[day.rem_euclid(ACTIVITY_WINDOW_DAYS) as usize] = Some(day);
+        };
+        // Active today, 2 days ago and 29 days ago; the slot 31 days back
+        // was overwritten by the day 1 day ago
+        record(today - 31);
+        record(today - 29);
+        record(today - 2);
+        record(today - 1);
+        record(today);
+
+        let bitmap = activity_bitmap(today, &slots);
+        assert_eq!(bitmap, (1 << 0) | (1 << 1) | (1 << 2) | (1 << 29));
+
+        // A stale slot outside the window sets no bit
+        let mut stale = vec![None; ACTIVITY_WINDOW_DAYS as usize];
+        stale[0] = Some(today - 45);
+        assert_eq!(activity_bitmap(today, &stale), 0);
+    }
+
+    #[test]
+    fn test_value_split_known_fee() {
+        // Recipient kept 9.90 USDC, facilitator kept a 0.10 USDC fee => 99% / 1%
+        let recipient = BigInt::from(9_900_000);
+        let fee = BigInt::from(100_000);
+        assert_eq!(value_split_bps(&recipient, &fee), (9900, 100));
+
+        // Aggregate across that settlement and a fee-free 10 USDC one
+        let recipient_total = recipient + BigInt::from(10_000_000);
+        assert_eq!(value_split_bps(&recipient_total, &fee), (9950, 50));
+    }
+
+    #[test]
+    fn test_value_split_empty() {
+        assert_eq!(value_split_bps(&BigInt::zero(), &BigInt::zero()), (0, 0));
+    }
+
+    #[test]
+    fn test_avg_payment() {
+        // A single payment averages to itself
+        assert_eq!(avg_payment(&BigInt::from(1_250_000), 1), BigInt::from(1_250_000));
+        // Multiple payments floor to atomic units
+        assert_eq!(avg_payment(&BigInt::from(1_000_001), 2), BigInt::from(500_000));
+        // Totals beyond i64 still divide
+        let total = parse_amount("100000000000000000000000000");
+        assert_eq!(avg_payment(&total, 4).to_string(), "25000000000000000000000000");
+        assert_eq!(avg_payment(&BigInt::from(5), 0), BigInt::zero());
+    }
+
+    #[test]
+    fn test_avg_payment_since_reset() {
+        // 4 payments of 100 before the reset, then 3 of 10: the average
+        // covers only the 3, not 30 over all 7
+        let total = since_reset(&BigInt::from(430), Some(BigInt::from(400)));
+        let payments = count_since_reset(7, Some(BigInt::from(4))) as u64;
+        assert_eq!(avg_payment(&total, payments), BigInt::from(10));
+        // No payments since the reset yet
+        let total = since_reset(&BigInt::from(400), Some(BigInt::from(400)));
+        let payments = count_since_reset(4, Some(BigInt::from(4))) as u64;
+        assert_eq!(avg_payment(&total, payments), BigInt::zero());
+    }
+
+    #[test]
+    fn test_avg_payment_counts_only_volume_settlements() {
+        let usdc = format_address(&USDC);
+        let paid = |recipient: &str, amount: &str, token: &str| x402::Settlement {
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            token: token.to_string(),
+            ..settlement("s", "0xaaa")
+        };
+        let mut block = vec![
+            paid("0xbbb", "300", &usdc),
+            paid("0xbbb", "100", &usdc),
+            // Unmatched: counted under count_unmatched, never in volume
+            paid("", "0", &usdc),
+            // Self-payment, left out of volume under exclude_self
+            paid("0xaaa", "5000", &usdc),
+            // Native ETH in wei, never in token volume
+            paid("0xbbb", "900000000000000000", NATIVE_TOKEN),
+        ];
+        tag_self_payments(&mut block);
+
+        let exclude_self = true;
+        let counted: Vec<_> = block
+            .iter()
+            .filter(|s| counts_toward_payer_volume(s, exclude_self))
+            .collect();
+        let total = counted
+            .iter()
+            .fold(BigInt::zero(), |total, s| total + parse_amount(&s.amount));
+        assert_eq!(total, BigInt::from(400));
+        assert_eq!(avg_payment(&total, counted.len() as u64), BigInt::from(200));
+
+        // total_payments with count_unmatched counts 5 here (4 matched plus
+        // the unmatched one); dividing the volume by it would give 80
+        let matched = block.iter().filter(|s| !is_unmatched(s)).count() as i64;
+        let unmatched = block.iter().filter(|s| is_unmatched(s)).count() as i64;
+        let total_payments = stat_count(matched, unmatched, true);
+        assert_eq!(total_payments, 5);
+        assert_ne!(avg_payment(&total, total_payments), BigInt::from(200));
+
+        // Recipients share the same filter shape
+        let recipient_counted = block
+            .iter()
+            .filter(|s| counts_toward_recipient_volume(s, false, exclude_self))
+            .count();
+        assert_eq!(recipient_counted, 2);
+    }
+
+    #[test]
+    fn test_payment_min_max_across_payments() {
+        // Fold each payment's keys the way the min and max stores merge them
+        let mut min: Vec<(String, BigInt)> = Vec::new();
+        let mut max: Vec<(String, BigInt)> = Vec::new();
+        for amount in ["5000", "250", "80000"] {
+            let s = x402::Settlement {
+                payer: "0xPAYER".to_string(),
+                recipient: "0xrecipient".to_string(),
+                amount: amount.to_string(),
+                ..Default::default()
+            };
+            for (key, amount) in payment_extreme_keys(&s) {
+                match min.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, v)) => *v = v.clone().min(amount.clone()),
+                    None => min.push((key.clone(), amount.clone())),
+                }
+                match max.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, v)) => *v = v.clone().max(amount),
+                    None => max.push((key, amount)),
+                }
+            }
+            if amount == "5000" {
+                // The first payment sets both min and max
+                assert_eq!(min, max);
+            }
+        }
+        for key in ["payer:0xpayer", "recipient:0xrecipient"] {
+            assert!(min.contains(&(key.to_string(), BigInt::from(250))));
+            assert!(max.contains(&(key.to_string(), BigInt::from(80_000))));
+        }
+
+        // Unmatched settlements carry no payment
+        let unmatched = x402::Settlement {
+            payer: "0xpayer".to_string(),
+            amount: "0".to_string(),
+            ..Default::default()
+        };
+        assert!(payment_extreme_keys(&unmatched).is_empty());
+    }
+
+    #[test]
+    fn test_ewma_payment_size_follows_step_change() {
+        let week = EWMA_HALF_LIFE_SECONDS;
+        let (mut weighted, mut weight) = (BigInt::zero(), BigInt::zero());
+        let mut pay = |week_index: i64, amount: u64| {
+            let w = ewma_weight(EWMA_EPOCH_SECONDS + week_index * week);
+            weighted = weighted.clone() + BigInt::from(amount) * w.clone();
+            weight = weight.clone() + w;
+            ewma_payment_size(&weighted, &weight).to_u64()
+        };
+
+        // Ten weeks at $1, then the price steps up to $5
+        for i in 0..10 {
+            assert_eq!(pay(i, 1_000_000), 1_000_000);
+        }
+        let mut last = 1_000_000;
+        for i in 10..20 {
+            let ewma = pay(i, 5_000_000);
+            assert!(ewma > last && ewma < 5_000_000);
+            last = ewma;
+        }
+        // Far closer to the new level than the plain mean ($3)
+        assert!(last > 4_990_000);
+        assert_eq!(ewma_payment_size(&BigInt::zero(), &BigInt::zero()), BigInt::zero());
+    }
+
+    #[test]
+    fn test_ewma_weight_is_continuous() {
+        let at = |secs: i64| ewma_weight(EWMA_EPOCH_SECONDS + secs);
+        let one = BigInt::from(1u64 << EWMA_FRACTION_BITS);
+        assert_eq!(at(0), one);
+        assert_eq!(at(-86400), one);
+        assert_eq!(at(EWMA_HALF_LIFE_SECONDS), one.clone() * BigInt::from(2));
+
+        // Half a half-life weighs sqrt(2), not 1 as a weekly step would
+        let ratio = |a: &BigInt, b: &BigInt| {
+            (a.clone() * BigInt::from(1_000_000) / b.clone()).to_u64()
+        };
+        assert_eq!(ratio(&at(EWMA_HALF_LIFE_SECONDS / 2), &one), 1_414_213);
+        // A day later weighs 2^(1/7) more, the same inside a week or across one,
+        // so a daily payment takes alpha = 1 - 2^(-1/7) of the average
+        let day = 86400;
+        assert_eq!(ratio(&at(day), &at(0)), 1_104_089);
+        let week = EWMA_HALF_LIFE_SECONDS;
+        assert_eq!(ratio(&at(week + day / 2), &at(week - day / 2)), 1_104_089);
+        let mut last = at(0);
+        for secs in (3600..EWMA_HALF_LIFE_SECONDS * 2).step_by(3600) {
+            let w = at(secs);
+            assert!(w > last);
+            last = w;
+        }
+    }
+
+    #[test]
+    fn test_facilitator_fee_earned_only_own_fees() {
+        let facilitator = "0x00000000000000000000000000000000000000fa";
+        let settlement = |fee_recipient: &str, fee: &str| x402::Settlement {
+            facilitator: facilitator.to_string(),
+            fee_recipient: fee_recipient.to_string(),
+            fee_amount: fee.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            facilitator_fee_earned(&settlement(&facilitator.to_uppercase(), "250")),
+            Some(BigInt::from(250))
+        );
+        // Fee to someone else, or no fee at all
+        assert_eq!(facilitator_fee_earned(&settlement("0xbb", "250")), None);
+        assert_eq!(facilitator_fee_earned(&settlement("", "0")), None);
+    }
+
+    #[test]
+    fn test_infra_recipients_tagged_and_excluded() {
+        let settlement = |recipient: &str, amount: u64| x402::Settlement {
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            ..Default::default()
+        };
+        let mut settlements = vec![
+            settlement("0xmerchant", 1_000),
+            settlement("0xtreasury", 50_000),
+        ];
+        let infra = parse_address_list("0xTREASURY");
+        tag_infra(&mut settlements, &infra);
+        assert!(!settlements[0].infra);
+        assert!(settlements[1].infra);
+
+        let merchant_volume = |exclude_infra: bool| -> u64 {
+            settlements
+                .iter()
+                .filter(|s| is_merchant_revenue(s, exclude_infra))
+                .map(|s| s.amount.parse::<u64>().unwrap())
+                .sum()
+        };
+        assert_eq!(merchant_volume(true), 1_000);
+        assert_eq!(merchant_volume(false), 51_000);
+    }
+
+    #[test]
+    fn test_net_flow_goes_negative() {
+        let settlement = |payer: &str, recipient: &str, amount: u64| x402::Settlement {
+            payer: payer.to_string(),
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            ..Default::default()
+        };
+        // An aggregator receives 1,000 and pays out 2,500
+        let changes = net_flow_changes(&[
+            settlement("0xpayer", "0xAggregator", 1_000),
+            settlement("0xaggregator", "0xmerchant", 2_500),
+        ]);
+        let change = |address: &str| {
+            changes
+                .iter()
+                .find(|(a, _)| a == address)
+                .map(|(_, c)| c.to_string())
+        };
+        assert_eq!(change("0xaggregator").as_deref(), Some("-1500"));
+        assert_eq!(change("0xpayer").as_deref(), Some("-1000"));
+        assert_eq!(change("0xmerchant").as_deref(), Some("2500"));
+    }
+
+    #[test]
+    fn test_fee_prevalence_half() {
+        let settlement = |fee: &str| x402::Settlement {
+            facilitator: "0xfac".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount: "10000".to_string(),
+            fee_amount: fee.to_string(),
+            ..Default::default()
+        };
+        let counts = fee_presence_counts(&[settlement("100"), settlement("0")]);
+        let count = |key: &str| counts.iter().find(|(k, _)| k == key).map(|(_, c)| *c);
+        assert_eq!(count("total"), Some(2));
+        assert_eq!(count("with_fee"), Some(1));
+        assert_eq!(count("0xfac:total"), Some(2));
+        assert_eq!(count("0xfac:with_fee"), Some(1));
+        assert_eq!(fee_prevalence_bps(1, 2), 5_000);
+        assert_eq!(fee_prevalence_bps(0, 0), 0);
+    }
+
+    #[test]
+    fn test_throughput_between_blocks() {
+        // Block two lands 4 seconds after block one and carries 10 settlements
+        let delta = |operation, old_value, new_value| DeltaInt64 {
+            operation,
+            ordinal: 0,
+            key: "last".to_string(),
+            old_value,
+            new_value,
+        };
+        let gap = prior_block_gap(&delta(Operation::Update, 1_700_000_000, 1_700_000_004));
+        assert_eq!(gap, Some(4));
+        assert_eq!(throughput_milli(10, gap.unwrap()), Some(2_500));
+
+        // First block has no prior; same-second or backwards gaps yield no rate
+        assert_eq!(prior_block_gap(&delta(Operation::Create, 0, 1_700_000_000)), None);
+        assert_eq!(throughput_milli(10, 0), None);
+        assert_eq!(throughput_milli(10, -2), None);
+    }
+
+    #[test]
+    fn test_volume_momentum_accelerating() {
+        let v = |n: u64| BigInt::from(n);
+        // 100 -> 150 -> 250: growth went from +50 to +100
+        assert_eq!(volume_momentum(Some(v(100)), Some(v(150)), &v(250)), Some(v(50)));
+        // 100 -> 200 -> 250: still growing, but slower
+        assert_eq!(
+            volume_momentum(Some(v(100)), Some(v(200)), &v(250)),
+            Some(BigInt::from(-50))
+        );
+        // First and second day: not enough history
+        assert_eq!(volume_momentum(None, None, &v(100)), None);
+        assert_eq!(volume_momentum(None, Some(v(100)), &v(150)), None);
+    }
+
+    #[test]
+    fn test_growth_rate_first_day() {
+        assert_eq!(growth_rate_bps(0, 7), None);
+    }
 }
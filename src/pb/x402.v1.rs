@@ -14,6 +14,60 @@ pub struct Settlements {
     pub block_number: u64,
     #[prost(message, optional, tag="3")]
     pub block_timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    /// USDC sent into the proxies, not payments
+    #[prost(message, repeated, tag="4")]
+    pub proxy_fundings: ::prost::alloc::vec::Vec<ProxyFunding>,
+    /// Over the max_block_keys cap, applied in later blocks
+    #[prost(message, repeated, tag="5")]
+    pub deferred_settlements: ::prost::alloc::vec::Vec<Settlement>,
+    /// Aborted EIP-3009 authorizations
+    #[prost(message, repeated, tag="6")]
+    pub canceled_authorizations: ::prost::alloc::vec::Vec<CanceledAuthorization>,
+}
+/// An EIP-3009 authorization canceled before use: an aborted payment, never a settlement
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CanceledAuthorization {
+    /// tx_hash-log_index
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub tx_hash: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub log_index: u32,
+    #[prost(uint64, tag="4")]
+    pub block_number: u64,
+    /// Token contract the authorization was for
+    #[prost(string, tag="5")]
+    pub token: ::prost::alloc::string::String,
+    /// The payer who canceled
+    #[prost(string, tag="6")]
+    pub authorizer: ::prost::alloc::string::String,
+    /// Hex-encoded bytes32
+    #[prost(string, tag="7")]
+    pub nonce: ::prost::alloc::string::String,
+}
+/// A USDC transfer into an x402 proxy (pre-funding), kept out of payments
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProxyFunding {
+    /// tx_hash-log_index
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub tx_hash: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub log_index: u32,
+    #[prost(uint64, tag="4")]
+    pub block_number: u64,
+    #[prost(string, tag="5")]
+    pub from: ::prost::alloc::string::String,
+    /// The proxy that received the funds
+    #[prost(string, tag="6")]
+    pub proxy: ::prost::alloc::string::String,
+    /// Atomic units
+    #[prost(string, tag="7")]
+    pub amount: ::prost::alloc::string::String,
 }
 /// A single x402 payment settlement
 ///
@@ -36,6 +90,7 @@ pub struct Settlement {
     pub log_index: u32,
     #[prost(uint64, tag="4")]
     pub block_number: u64,
+    /// Unset when the block has no timestamp
     #[prost(message, optional, tag="5")]
     pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
     /// Payment details
@@ -46,7 +101,7 @@ pub struct Settlement {
     /// Resource server (payTo)
     #[prost(string, tag="7")]
     pub recipient: ::prost::alloc::string::String,
-    /// Token address (USDC)
+    /// Token address (USDC), or "native" for ETH (amount in wei)
     #[prost(string, tag="8")]
     pub token: ::prost::alloc::string::String,
     /// Payment amount (atomic units, 6 decimals for USDC)
@@ -73,6 +128,65 @@ pub struct Settlement {
     /// bytes32 nonce, hex-encoded
     #[prost(string, tag="14")]
     pub nonce: ::prost::alloc::string::String,
+    /// Facilitator fee (a separate Transfer to the facilitator in the same tx).
+    /// When the recipient forwards the fee, amount is already net of it.
+    ///
+    /// Fee amount (atomic units), "0" if none
+    #[prost(string, tag="15")]
+    pub fee_amount: ::prost::alloc::string::String,
+    /// Who received the fee, empty if none
+    #[prost(string, tag="16")]
+    pub fee_recipient: ::prost::alloc::string::String,
+    /// Stream-wide sort key: block_number * 2^32 + block log index, zero-padded
+    #[prost(string, tag="17")]
+    pub global_seq: ::prost::alloc::string::String,
+    /// Block had no (or a zero) timestamp; timestamp is unset, order by global_seq
+    #[prost(bool, tag="18")]
+    pub timestamp_missing: bool,
+    /// EIP-3009 validity window from the transferWithAuthorization calldata
+    /// (unix seconds); 0 when unknown, including for proxy settlements
+    #[prost(uint64, tag="19")]
+    pub valid_after: u64,
+    #[prost(uint64, tag="20")]
+    pub valid_before: u64,
+    /// Facilitator's cumulative volume rank (1 = top), set by
+    /// map_ranked_settlements; 0 when outside the top K or unranked
+    #[prost(uint32, tag="21")]
+    pub facilitator_rank: u32,
+    /// Version of the proxy contract for proxy-path settlements (e.g.
+    /// "exact_permit2_v1", "unknown" if unmapped); empty for EIP-3009
+    #[prost(string, tag="22")]
+    pub protocol_version: ::prost::alloc::string::String,
+    /// Deterministic UUIDv5 of id, for sinks that need UUID primary keys
+    #[prost(string, tag="23")]
+    pub uuid: ::prost::alloc::string::String,
+    /// Recipient is protocol infrastructure (infra_addresses param), not a merchant
+    #[prost(bool, tag="24")]
+    pub infra: bool,
+    /// Sent through a known multicall / aggregator contract (e.g. Multicall3)
+    #[prost(bool, tag="25")]
+    pub via_multicall: bool,
+    /// Aggregator address, empty when not via multicall
+    #[prost(string, tag="26")]
+    pub multicall_address: ::prost::alloc::string::String,
+    /// EIP-1559 fee split of gas_price; Base's L1 data fee is not included
+    ///
+    /// Block base fee (wei)
+    #[prost(string, tag="27")]
+    pub base_fee_per_gas: ::prost::alloc::string::String,
+    /// gas_price - base_fee_per_gas, floored at 0 (wei)
+    #[prost(string, tag="28")]
+    pub priority_fee: ::prost::alloc::string::String,
+    /// Decoded from transferWithAuthorization calldata; no AuthorizationUsed log matched
+    #[prost(bool, tag="29")]
+    pub from_calldata: bool,
+    /// Payer and recipient are the same address (wash / self transfer)
+    #[prost(bool, tag="30")]
+    pub is_self_payment: bool,
+    /// Transaction gas (gas_used * gas_price) in USD, 6 decimals, at the last
+    /// known Chainlink ETH/USD answer; empty before any price is known
+    #[prost(string, tag="31")]
+    pub gas_cost_usd: ::prost::alloc::string::String,
 }
 // =============================================
 // LAYER 3: Analytics
@@ -101,6 +215,18 @@ pub struct PayerStat {
     pub first_payment_at: ::core::option::Option<::prost_types::Timestamp>,
     #[prost(message, optional, tag="5")]
     pub last_payment_at: ::core::option::Option<::prost_types::Timestamp>,
+    /// Distinct tokens this payer has spent
+    #[prost(uint64, tag="6")]
+    pub distinct_tokens: u64,
+    /// total_spent / total_payments, atomic units
+    #[prost(string, tag="7")]
+    pub avg_payment: ::prost::alloc::string::String,
+    /// Smallest single payment
+    #[prost(string, tag="8")]
+    pub min_payment: ::prost::alloc::string::String,
+    /// Largest single payment
+    #[prost(string, tag="9")]
+    pub max_payment: ::prost::alloc::string::String,
 }
 /// Aggregated recipient (resource server) statistics
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -125,6 +251,24 @@ pub struct RecipientStat {
     pub first_payment_at: ::core::option::Option<::prost_types::Timestamp>,
     #[prost(message, optional, tag="5")]
     pub last_payment_at: ::core::option::Option<::prost_types::Timestamp>,
+    /// First to second payment; unset until a second
+    #[prost(int64, optional, tag="6")]
+    pub time_to_second_payment_seconds: ::core::option::Option<i64>,
+    /// Time-weighted average payment, 7-day half-life
+    #[prost(string, tag="7")]
+    pub ewma_payment_size: ::prost::alloc::string::String,
+    /// Distinct payers ever paying this recipient
+    #[prost(uint64, tag="8")]
+    pub unique_payers: u64,
+    /// total_received / total_payments, atomic units
+    #[prost(string, tag="9")]
+    pub avg_payment: ::prost::alloc::string::String,
+    /// Smallest single payment
+    #[prost(string, tag="10")]
+    pub min_payment: ::prost::alloc::string::String,
+    /// Largest single payment
+    #[prost(string, tag="11")]
+    pub max_payment: ::prost::alloc::string::String,
 }
 /// Facilitator gas economics
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -161,6 +305,490 @@ pub struct FacilitatorStat {
     /// Facilitator endpoint URL
     #[prost(string, tag="9")]
     pub url: ::prost::alloc::string::String,
+    /// Gas cost in USD per dollar settled, basis points
+    #[prost(uint64, tag="10")]
+    pub cost_per_dollar_bps: u64,
+    /// Total gas cost in USD (6 decimals)
+    #[prost(string, tag="11")]
+    pub total_gas_spent_usd: ::prost::alloc::string::String,
+    /// Fees paid to the facilitator itself, atomic units
+    #[prost(string, tag="12")]
+    pub total_fees_earned: ::prost::alloc::string::String,
+    /// Settlements via EIP-3009 (incl. eip3009_proxy)
+    #[prost(uint64, tag="13")]
+    pub eip3009_count: u64,
+    /// Settlements via the Permit2 proxies (incl. eip3009_proxy)
+    #[prost(uint64, tag="14")]
+    pub proxy_count: u64,
+}
+/// Facilitator settlement latency: valid_after (payer signing) to settlement block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorLatencies {
+    #[prost(message, repeated, tag="1")]
+    pub facilitators: ::prost::alloc::vec::Vec<FacilitatorLatency>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorLatency {
+    #[prost(string, tag="1")]
+    pub facilitator_address: ::prost::alloc::string::String,
+    #[prost(int64, tag="2")]
+    pub avg_latency_seconds: i64,
+    #[prost(int64, tag="3")]
+    pub max_latency_seconds: i64,
+    /// Settlements with a known valid_after
+    #[prost(uint64, tag="4")]
+    pub samples: u64,
+}
+/// Facilitator fee rates against the protocol-wide median
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FeeCompetition {
+    #[prost(message, repeated, tag="1")]
+    pub facilitators: ::prost::alloc::vec::Vec<FacilitatorFeeRate>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+    /// Approximate (25 bps histogram buckets)
+    #[prost(uint64, tag="3")]
+    pub median_fee_bps: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorFeeRate {
+    #[prost(string, tag="1")]
+    pub facilitator_address: ::prost::alloc::string::String,
+    /// Mean fee share of payment + fee
+    #[prost(uint64, tag="2")]
+    pub avg_fee_bps: u64,
+    /// Matched settlements
+    #[prost(uint64, tag="3")]
+    pub samples: u64,
+    /// avg_fee_bps below the median
+    #[prost(bool, tag="4")]
+    pub low_fee_facilitator: bool,
+}
+/// Split of settled value between recipients and facilitator fees
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueSplit {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    #[prost(message, repeated, tag="2")]
+    pub settlements: ::prost::alloc::vec::Vec<SettlementValueSplit>,
+    /// Cumulative value kept by recipients
+    #[prost(string, tag="3")]
+    pub recipient_total: ::prost::alloc::string::String,
+    /// Cumulative fees kept by facilitators
+    #[prost(string, tag="4")]
+    pub facilitator_total: ::prost::alloc::string::String,
+    /// Protocol-wide recipient share, basis points
+    #[prost(uint64, tag="5")]
+    pub recipient_share_bps: u64,
+    /// Protocol-wide facilitator share, basis points
+    #[prost(uint64, tag="6")]
+    pub facilitator_share_bps: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SettlementValueSplit {
+    #[prost(string, tag="1")]
+    pub settlement_id: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub recipient_share_bps: u64,
+    #[prost(uint64, tag="3")]
+    pub facilitator_share_bps: u64,
+}
+/// Proxy-path settlements by type: plain Settled vs Permit2 SettledWithPermit
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProxyTypeMix {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Cumulative Settled count
+    #[prost(uint64, tag="2")]
+    pub settled: u64,
+    /// Cumulative SettledWithPermit count
+    #[prost(uint64, tag="3")]
+    pub settled_with_permit: u64,
+    /// settled_with_permit / total, basis points
+    #[prost(uint64, tag="4")]
+    pub permit_share_bps: u64,
+}
+/// Recurring equal payments from one payer to one recipient
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscriptions {
+    #[prost(message, repeated, tag="1")]
+    pub subscriptions: ::prost::alloc::vec::Vec<Subscription>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscription {
+    #[prost(string, tag="1")]
+    pub payer: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub recipient: ::prost::alloc::string::String,
+    /// The repeated payment amount (atomic units)
+    #[prost(string, tag="3")]
+    pub amount: ::prost::alloc::string::String,
+    /// Payments of this amount so far
+    #[prost(uint64, tag="4")]
+    pub occurrences: u64,
+    /// Average seconds between payments
+    #[prost(int64, tag="5")]
+    pub avg_interval: i64,
+    #[prost(message, optional, tag="6")]
+    pub last_payment_at: ::core::option::Option<::prost_types::Timestamp>,
+}
+/// Recipients ranked by unique payers
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RecipientsReach {
+    #[prost(message, repeated, tag="1")]
+    pub recipients: ::prost::alloc::vec::Vec<RecipientReach>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RecipientReach {
+    #[prost(string, tag="1")]
+    pub recipient_address: ::prost::alloc::string::String,
+    /// Distinct payers that have paid this recipient
+    #[prost(uint64, tag="2")]
+    pub unique_payers: u64,
+    /// 1-based rank within the block
+    #[prost(uint32, tag="3")]
+    pub rank: u32,
+}
+/// Current top payers and recipients by cumulative volume
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Leaderboard {
+    #[prost(message, repeated, tag="1")]
+    pub entries: ::prost::alloc::vec::Vec<LeaderboardEntry>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeaderboardEntry {
+    /// 1-based rank within the role
+    #[prost(uint32, tag="1")]
+    pub rank: u32,
+    #[prost(string, tag="2")]
+    pub address: ::prost::alloc::string::String,
+    /// "payer" or "recipient"
+    #[prost(string, tag="3")]
+    pub role: ::prost::alloc::string::String,
+    /// Cumulative volume (atomic units)
+    #[prost(string, tag="4")]
+    pub total_volume: ::prost::alloc::string::String,
+}
+/// Per-recipient payment amount variability (metered vs flat-fee)
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RecipientAmountEntropies {
+    #[prost(message, repeated, tag="1")]
+    pub recipients: ::prost::alloc::vec::Vec<RecipientAmountEntropy>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RecipientAmountEntropy {
+    #[prost(string, tag="1")]
+    pub recipient_address: ::prost::alloc::string::String,
+    /// Normalized amount histogram entropy, 0-10000 bps
+    #[prost(uint64, tag="2")]
+    pub amount_variability: u64,
+    #[prost(uint64, tag="3")]
+    pub total_payments: u64,
+}
+/// Gas saved by batching settlements vs one transaction per payment
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GasSavings {
+    #[prost(message, repeated, tag="1")]
+    pub batches: ::prost::alloc::vec::Vec<BatchGasSavings>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+    /// Cumulative, protocol-wide
+    #[prost(uint64, tag="3")]
+    pub total_gas_saved: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BatchGasSavings {
+    #[prost(string, tag="1")]
+    pub tx_hash: ::prost::alloc::string::String,
+    /// Settlements in this transaction
+    #[prost(uint64, tag="2")]
+    pub settlement_count: u64,
+    #[prost(uint64, tag="3")]
+    pub gas_used: u64,
+    /// settlement_count * 21000 - gas_used, floored at 0
+    #[prost(uint64, tag="4")]
+    pub gas_saved: u64,
+}
+/// Settlements per block by whether their tx settled one payment or a batch
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BatchingStats {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Settlements alone in their tx
+    #[prost(uint64, tag="2")]
+    pub single_count: u64,
+    /// Settlements sharing a tx with others
+    #[prost(uint64, tag="3")]
+    pub batched_count: u64,
+    /// Most settlements in one tx
+    #[prost(uint64, tag="4")]
+    pub largest_batch: u64,
+}
+/// Protocol-wide cumulative totals
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProtocolTotals {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Atomic units
+    #[prost(string, tag="2")]
+    pub total_volume: ::prost::alloc::string::String,
+    /// Facilitator fees, atomic units
+    #[prost(string, tag="3")]
+    pub total_fees: ::prost::alloc::string::String,
+    #[prost(uint64, tag="4")]
+    pub total_settlements: u64,
+    /// HyperLogLog estimate, ~3.25% standard error
+    #[prost(uint64, tag="5")]
+    pub approx_unique_payers: u64,
+    /// HyperLogLog estimate, ~3.25% standard error
+    #[prost(uint64, tag="6")]
+    pub approx_unique_recipients: u64,
+    /// Approximate Gini of payer spend (see PayerGini)
+    #[prost(uint64, tag="7")]
+    pub payer_gini_bps: u64,
+    /// Facilitator gas USD / total volume (see SubsidyRate)
+    #[prost(uint64, tag="8")]
+    pub subsidy_rate_bps: u64,
+}
+/// Gas facilitators pay on payers' behalf, relative to volume settled
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubsidyRate {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Total gas USD / total volume, bps
+    #[prost(uint64, tag="2")]
+    pub subsidy_rate_bps: u64,
+    /// Facilitator gas in USD, 6 decimals
+    #[prost(string, tag="3")]
+    pub total_gas_usd: ::prost::alloc::string::String,
+}
+/// Approximate Gini coefficient of cumulative payer spend
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PayerGini {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// 0 = equal spend, 10000 = fully concentrated; tier-histogram lower bound
+    #[prost(uint64, tag="2")]
+    pub gini_bps: u64,
+}
+/// Daily volume from new vs returning payers
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NewVsReturning {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// YYYY-MM-DD (UTC)
+    #[prost(string, tag="2")]
+    pub date: ::prost::alloc::string::String,
+    /// From payers whose first payment was this day
+    #[prost(string, tag="3")]
+    pub new_volume: ::prost::alloc::string::String,
+    /// From payers first seen on an earlier day
+    #[prost(string, tag="4")]
+    pub returning_volume: ::prost::alloc::string::String,
+}
+/// Confirmation depth of each settlement in a block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SettlementConfirmations {
+    /// Clock block, or the head_block param if later
+    #[prost(uint64, tag="1")]
+    pub head_block: u64,
+    #[prost(message, repeated, tag="2")]
+    pub confirmations: ::prost::alloc::vec::Vec<SettlementConfirmation>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SettlementConfirmation {
+    #[prost(string, tag="1")]
+    pub settlement_id: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+    /// head_block - block_number
+    #[prost(uint64, tag="3")]
+    pub confirmations: u64,
+}
+/// A day's settlement count per settlement type
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DailyTypeMix {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// YYYY-MM-DD (UTC)
+    #[prost(string, tag="2")]
+    pub date: ::prost::alloc::string::String,
+    /// Types settled so far this day
+    #[prost(message, repeated, tag="3")]
+    pub types: ::prost::alloc::vec::Vec<DailyTypeCount>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DailyTypeCount {
+    #[prost(string, tag="1")]
+    pub settlement_type: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub count: u64,
+    /// Share of the day's settlements
+    #[prost(uint64, tag="3")]
+    pub share_bps: u64,
+}
+/// Data invariant violations in a block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Invariants {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Only rules violated at least once
+    #[prost(message, repeated, tag="2")]
+    pub violations: ::prost::alloc::vec::Vec<Invariant>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Invariant {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// e.g. "amount_not_uint"
+    #[prost(string, tag="2")]
+    pub rule: ::prost::alloc::string::String,
+    /// Settlements violating the rule
+    #[prost(uint64, tag="3")]
+    pub count: u64,
+}
+/// Cumulative totals per recipient category (recipient_categories param)
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CategoryStats {
+    #[prost(message, repeated, tag="1")]
+    pub stats: ::prost::alloc::vec::Vec<CategoryStat>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CategoryStat {
+    /// e.g. "api"; "uncategorized" when unlabeled
+    #[prost(string, tag="1")]
+    pub category: ::prost::alloc::string::String,
+    /// Atomic units
+    #[prost(string, tag="2")]
+    pub total_volume: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub total_payments: u64,
+}
+/// Day-over-day protocol growth (settlement count)
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GrowthRate {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// YYYY-MM-DD (UTC)
+    #[prost(string, tag="2")]
+    pub date: ::prost::alloc::string::String,
+    /// Settlements on this day so far
+    #[prost(uint64, tag="3")]
+    pub count: u64,
+    /// Change vs previous day, basis points
+    #[prost(int64, tag="4")]
+    pub pct_change_bps: i64,
+    /// False on the first day (no previous day to compare)
+    #[prost(bool, tag="5")]
+    pub has_prior: bool,
+}
+// =============================================
+// LAYER 4: Parquet Sink
+// =============================================
+
+/// Settlements as flat, typed rows for a Parquet sink. Unset optionals are
+/// nulls: values that don't fit their integer type, or empty strings.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SettlementRows {
+    #[prost(message, repeated, tag="1")]
+    pub rows: ::prost::alloc::vec::Vec<SettlementRow>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SettlementRow {
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub tx_hash: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub log_index: u32,
+    #[prost(uint64, tag="4")]
+    pub block_number: u64,
+    /// Unix seconds
+    #[prost(int64, optional, tag="5")]
+    pub block_timestamp: ::core::option::Option<i64>,
+    #[prost(string, tag="6")]
+    pub payer: ::prost::alloc::string::String,
+    #[prost(string, tag="7")]
+    pub recipient: ::prost::alloc::string::String,
+    #[prost(string, tag="8")]
+    pub token: ::prost::alloc::string::String,
+    /// Exact amount (atomic units)
+    #[prost(string, tag="9")]
+    pub amount: ::prost::alloc::string::String,
+    /// Amount as an integer, when it fits
+    #[prost(uint64, optional, tag="10")]
+    pub amount_u64: ::core::option::Option<u64>,
+    #[prost(string, tag="11")]
+    pub settlement_type: ::prost::alloc::string::String,
+    #[prost(string, tag="12")]
+    pub facilitator: ::prost::alloc::string::String,
+    #[prost(uint64, tag="13")]
+    pub gas_used: u64,
+    /// Wei
+    #[prost(uint64, optional, tag="14")]
+    pub gas_price: ::core::option::Option<u64>,
+    #[prost(string, optional, tag="15")]
+    pub nonce: ::core::option::Option<::prost::alloc::string::String>,
+    /// Atomic units
+    #[prost(uint64, optional, tag="16")]
+    pub fee_amount: ::core::option::Option<u64>,
+    #[prost(string, optional, tag="17")]
+    pub fee_recipient: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, tag="18")]
+    pub global_seq: ::prost::alloc::string::String,
+    /// amount scaled by token decimals (6 if unknown)
+    #[prost(string, optional, tag="19")]
+    pub amount_decimal: ::core::option::Option<::prost::alloc::string::String>,
+    /// fee_amount scaled by token decimals
+    #[prost(string, optional, tag="20")]
+    pub fee_amount_decimal: ::core::option::Option<::prost::alloc::string::String>,
 }
 // =============================================
 // Facilitator Registry
@@ -188,4 +816,278 @@ pub struct FacilitatorRegistryEvent {
     #[prost(bool, tag="4")]
     pub is_added: bool,
 }
+// =============================================
+// ETH/USD Price Feed
+// =============================================
+
+/// Latest Chainlink ETH/USD answer seen in a block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EthUsdPrice {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// 8 decimals; empty if no update this block
+    #[prost(string, tag="2")]
+    pub price: ::prost::alloc::string::String,
+    /// Answer timestamp (unix seconds)
+    #[prost(uint64, tag="3")]
+    pub updated_at: u64,
+}
+/// Upgraded events from the USDC proxy (a new implementation)
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UsdcUpgrades {
+    #[prost(message, repeated, tag="1")]
+    pub upgrades: ::prost::alloc::vec::Vec<UsdcUpgrade>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+    #[prost(message, optional, tag="3")]
+    pub block_timestamp: ::core::option::Option<::prost_types::Timestamp>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UsdcUpgrade {
+    /// tx_hash-log_index
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub tx_hash: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub log_index: u32,
+    /// New implementation address
+    #[prost(string, tag="4")]
+    pub implementation: ::prost::alloc::string::String,
+}
+/// Latest day a payer was active in one slot of its activity ring
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ActivityDay {
+    /// Days since the Unix epoch
+    #[prost(int64, tag="1")]
+    pub day: i64,
+}
+/// Rolling 30-day activity bitmaps of payers active this block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PayerActivity {
+    #[prost(message, repeated, tag="1")]
+    pub payers: ::prost::alloc::vec::Vec<PayerActivityBitmap>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PayerActivityBitmap {
+    #[prost(string, tag="1")]
+    pub payer_address: ::prost::alloc::string::String,
+    /// Bit i set = active i days ago (bit 0 = today)
+    #[prost(uint32, tag="2")]
+    pub bitmap: u32,
+    /// Days active in the window
+    #[prost(uint32, tag="3")]
+    pub active_days: u32,
+}
+/// Recipients forwarding received USDC onward in the same transaction
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Passthroughs {
+    #[prost(message, repeated, tag="1")]
+    pub passthroughs: ::prost::alloc::vec::Vec<Passthrough>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Passthrough {
+    /// tx_hash-log_index of the forwarding transfer
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    /// Settlement that paid the recipient
+    #[prost(string, tag="2")]
+    pub settlement_id: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub tx_hash: ::prost::alloc::string::String,
+    /// Pass-through (aggregator) address
+    #[prost(string, tag="4")]
+    pub recipient: ::prost::alloc::string::String,
+    #[prost(string, tag="5")]
+    pub forwarded_to: ::prost::alloc::string::String,
+    /// Amount forwarded, atomic units
+    #[prost(string, tag="6")]
+    pub amount: ::prost::alloc::string::String,
+}
+/// Protocol-wide USDC settlement counts by denomination
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DenominationStats {
+    #[prost(message, repeated, tag="1")]
+    pub denominations: ::prost::alloc::vec::Vec<DenominationCount>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DenominationCount {
+    /// sub_cent, cents, dollars, tens, hundreds, thousands_plus
+    #[prost(string, tag="1")]
+    pub denomination: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub count: u64,
+}
+/// Protocol-wide Pearson correlation between gas price and settlement amount
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GasAmountCorrelation {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// -10000..10000; 0 when has_correlation is false
+    #[prost(int64, tag="2")]
+    pub gas_amount_corr_bps: i64,
+    /// False under two samples or with a constant variable
+    #[prost(bool, tag="3")]
+    pub has_correlation: bool,
+    #[prost(uint64, tag="4")]
+    pub samples: u64,
+}
+/// Nonce values reused by distinct authorizers within a UTC day
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NoncePatterns {
+    #[prost(message, repeated, tag="1")]
+    pub patterns: ::prost::alloc::vec::Vec<NoncePattern>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NoncePattern {
+    /// UTC day (YYYY-MM-DD)
+    #[prost(string, tag="1")]
+    pub date: ::prost::alloc::string::String,
+    /// Hex-encoded bytes32
+    #[prost(string, tag="2")]
+    pub nonce: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub distinct_authorizers: u64,
+    /// At least min_authorizers (param) authorizers
+    #[prost(bool, tag="4")]
+    pub shared: bool,
+}
+/// Days whose cumulative volume set a new all-time daily high
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VolumeRecords {
+    #[prost(message, repeated, tag="1")]
+    pub records: ::prost::alloc::vec::Vec<VolumeRecord>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VolumeRecord {
+    /// UTC day (YYYY-MM-DD)
+    #[prost(string, tag="1")]
+    pub date: ::prost::alloc::string::String,
+    /// New record, atomic units
+    #[prost(string, tag="2")]
+    pub volume: ::prost::alloc::string::String,
+    /// Prior all-time daily high
+    #[prost(string, tag="3")]
+    pub previous_record: ::prost::alloc::string::String,
+}
+/// Composite facilitator reliability scores
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorSlas {
+    #[prost(message, repeated, tag="1")]
+    pub facilitators: ::prost::alloc::vec::Vec<FacilitatorSla>,
+    #[prost(uint64, tag="2")]
+    pub block_number: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorSla {
+    #[prost(string, tag="1")]
+    pub facilitator_address: ::prost::alloc::string::String,
+    /// (5 * success + 3 * latency + 2 * gas) / 10
+    #[prost(uint64, tag="2")]
+    pub sla_score_bps: u64,
+    /// Matched / all settlements
+    #[prost(uint64, tag="3")]
+    pub success_bps: u64,
+    /// Halves at a 60s average latency
+    #[prost(uint64, tag="4")]
+    pub latency_score_bps: u64,
+    /// Halves at 10 bps gas cost per dollar
+    #[prost(uint64, tag="5")]
+    pub gas_score_bps: u64,
+}
+/// Second difference of daily volume: whether growth is speeding up or slowing
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VolumeMomentum {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// YYYY-MM-DD (UTC)
+    #[prost(string, tag="2")]
+    pub date: ::prost::alloc::string::String,
+    /// Volume on this day so far, atomic units
+    #[prost(string, tag="3")]
+    pub volume: ::prost::alloc::string::String,
+    /// today - 2 * yesterday + the day before
+    #[prost(string, tag="4")]
+    pub momentum: ::prost::alloc::string::String,
+    /// False until two prior days have volume
+    #[prost(bool, tag="5")]
+    pub has_momentum: bool,
+}
+/// Share of matched settlements that carried a facilitator fee
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FeePrevalence {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Cumulative fee-bearing settlements
+    #[prost(uint64, tag="2")]
+    pub with_fee: u64,
+    /// Cumulative matched settlements
+    #[prost(uint64, tag="3")]
+    pub total: u64,
+    /// with_fee / total, basis points
+    #[prost(uint64, tag="4")]
+    pub fee_prevalence_bps: u64,
+    #[prost(message, repeated, tag="5")]
+    pub facilitators: ::prost::alloc::vec::Vec<FacilitatorFeePrevalence>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FacilitatorFeePrevalence {
+    #[prost(string, tag="1")]
+    pub facilitator_address: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub with_fee: u64,
+    #[prost(uint64, tag="3")]
+    pub total: u64,
+    #[prost(uint64, tag="4")]
+    pub fee_prevalence_bps: u64,
+}
+/// Instantaneous settlement throughput since the previous settlement block
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Throughput {
+    #[prost(uint64, tag="1")]
+    pub block_number: u64,
+    /// Settlements in this block
+    #[prost(uint64, tag="2")]
+    pub settlements: u64,
+    /// Since the previous block with settlements
+    #[prost(int64, tag="3")]
+    pub seconds_since_prior: i64,
+    /// Thousandths of a settlement per second
+    #[prost(uint64, tag="4")]
+    pub settlements_per_second_milli: u64,
+    /// False on the first block or a non-positive gap
+    #[prost(bool, tag="5")]
+    pub has_rate: bool,
+}
 // @@protoc_insertion_point(module)